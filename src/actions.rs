@@ -1,17 +1,173 @@
+use crate::character::CamInputState;
+use crate::simulation::PendingInput;
+use crate::widgets::Typing;
+use crate::GameState;
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub struct ActionPlugin;
 
+/// This plugin turns keyboard and mouse input into [PendingInput] for the simulation
 impl Plugin for ActionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(InputManagerPlugin::<Action>::default());
+        app.add_plugin(InputManagerPlugin::<Action>::default())
+            .init_resource::<ActionState<Action>>()
+            .init_resource::<CursorGrab>()
+            .insert_resource(default_input_map())
+            .add_system_to_stage(CoreStage::PreUpdate, sync_cursor_grab)
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(collect_input));
     }
 }
 
+/// Whether the mouse is captured by the game
+///
+/// Gameplay input is only taken while the cursor is grabbed.
+#[derive(Default)]
+pub struct CursorGrab {
+    pub locked: bool,
+}
+
 #[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug)]
 pub enum Action {
-    TurnLeft,
-    TurnRight,
-    Walk,
+    Forward,
+    Backward,
+    StrafeLeft,
+    StrafeRight,
+    Ascend,
+    Descend,
+    Combine,
+    ControlFirst,
+    ControlSecond,
+    ControlThird,
+    NextMarker,
+    PreviousMarker,
+    Annotate,
+}
+
+impl Action {
+    /// The character number that this action switches control to
+    pub fn controlled_number(&self) -> Option<u8> {
+        match self {
+            Action::ControlFirst => Some(1),
+            Action::ControlSecond => Some(2),
+            Action::ControlThird => Some(3),
+            _ => None,
+        }
+    }
+
+    /// The action that switches control to the character with the given number
+    pub fn control(number: u8) -> Option<Action> {
+        Action::variants().find(|action| action.controlled_number() == Some(number))
+    }
+}
+
+fn default_input_map() -> InputMap<Action> {
+    let mut input_map = InputMap::new([
+        (KeyCode::W, Action::Forward),
+        (KeyCode::S, Action::Backward),
+        (KeyCode::A, Action::StrafeLeft),
+        (KeyCode::D, Action::StrafeRight),
+        (KeyCode::Space, Action::Combine),
+        (KeyCode::Key1, Action::ControlFirst),
+        (KeyCode::Numpad1, Action::ControlFirst),
+        (KeyCode::Key2, Action::ControlSecond),
+        (KeyCode::Numpad2, Action::ControlSecond),
+        (KeyCode::Key3, Action::ControlThird),
+        (KeyCode::Numpad3, Action::ControlThird),
+        (KeyCode::E, Action::NextMarker),
+        (KeyCode::Q, Action::PreviousMarker),
+        (KeyCode::T, Action::Annotate),
+    ]);
+    input_map
+        .insert(MouseWheelDirection::Up, Action::NextMarker)
+        .insert(MouseWheelDirection::Down, Action::PreviousMarker);
+    #[cfg(debug_assertions)]
+    {
+        input_map
+            .insert(KeyCode::LShift, Action::Ascend)
+            .insert(KeyCode::LControl, Action::Descend);
+    }
+    input_map
+}
+
+/// A compact set of [`Action`]s, used to pass input from frames to simulation ticks
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ActionSet(u16);
+
+impl ActionSet {
+    pub fn insert(&mut self, action: Action) {
+        self.0 |= 1 << action.index();
+    }
+
+    pub fn contains(&self, action: Action) -> bool {
+        self.0 & (1 << action.index()) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Action> + '_ {
+        Action::variants().filter(|action| self.contains(*action))
+    }
+}
+
+fn sync_cursor_grab(windows: Res<Windows>, mut cursor_grab: ResMut<CursorGrab>) {
+    let locked = windows
+        .get_primary()
+        .map(|window| window.cursor_locked())
+        .unwrap_or(false);
+    if cursor_grab.locked != locked {
+        cursor_grab.locked = locked;
+    }
+}
+
+fn collect_input(
+    cursor_grab: Res<CursorGrab>,
+    typing: Res<Typing>,
+    action_state: Res<ActionState<Action>>,
+    cam_input_state: Res<CamInputState>,
+    mut pending: ResMut<PendingInput>,
+) {
+    let input = &mut pending.0;
+    input.held = ActionSet::default();
+    // the keys belong to the text field while typing
+    if typing.0 {
+        return;
+    }
+    for action in action_state.get_pressed() {
+        if cursor_grab.locked {
+            input.held.insert(action);
+        }
+    }
+    for action in action_state.get_just_pressed() {
+        input.triggered.insert(action);
+    }
+    input.yaw = cam_input_state.yaw;
+    input.pitch = cam_input_state.pitch;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::TickInput;
+
+    #[test]
+    fn typing_does_not_move_characters() {
+        let mut action_state = ActionState::<Action>::default();
+        action_state.press(Action::Forward);
+        action_state.press(Action::Combine);
+        let mut app = App::new();
+        app.insert_resource(CursorGrab { locked: true })
+            .insert_resource(Typing(true))
+            .insert_resource(action_state)
+            .init_resource::<CamInputState>()
+            .init_resource::<PendingInput>()
+            .add_system(collect_input);
+        app.update();
+        assert_eq!(app.world.resource::<PendingInput>().0, TickInput::default());
+
+        app.world.resource_mut::<Typing>().0 = false;
+        app.update();
+        let input = &app.world.resource::<PendingInput>().0;
+        assert!(input.held.contains(Action::Forward));
+        assert!(input.triggered.contains(Action::Combine));
+    }
 }
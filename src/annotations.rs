@@ -0,0 +1,298 @@
+use crate::actions::{Action, CursorGrab};
+use crate::character::{Character, Controlled, FlyCam};
+use crate::loading::FontAssets;
+use crate::map::LevelEntity;
+use crate::markers::MarkerTarget;
+use crate::palette::CharacterMaterials;
+use crate::widgets::{spawn_widget, ButtonColors, Focus, Typing, Widget, WidgetEvent, WidgetValue};
+use crate::GameState;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+/// Labels of notes further away than this are hidden
+const LABEL_DISTANCE: f32 = 2.;
+/// Longest note in characters
+const NOTE_LENGTH: usize = 60;
+
+pub struct AnnotationPlugin;
+
+/// This plugin lets players write short notes onto walls and the floor
+/// A note is shown as a label while the controlled character is close to it.
+impl Plugin for AnnotationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(start_annotation)
+                .with_system(finish_annotation)
+                .with_system(place_annotation_labels),
+        )
+        .add_system_set(SystemSet::on_pause(GameState::Playing).with_system(drop_annotation))
+        .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(drop_annotation));
+    }
+}
+
+/// A note written by the part with the given number
+#[derive(Component)]
+pub struct Annotation {
+    pub owner: u8,
+    pub text: String,
+}
+
+#[derive(Component)]
+struct AnnotationLabel {
+    annotation: Entity,
+}
+
+/// The spot the note that is currently typed will be attached to
+struct PendingAnnotation {
+    transform: Transform,
+    owner: u8,
+    /// The text input the note is typed into
+    field: Entity,
+}
+
+/// Holds the text input of the note that is typed
+#[derive(Component)]
+struct NotePanel;
+
+#[allow(clippy::too_many_arguments)]
+fn start_annotation(
+    mut commands: Commands,
+    action_state: Res<ActionState<Action>>,
+    cursor_grab: Res<CursorGrab>,
+    target: Res<MarkerTarget>,
+    typing: Res<Typing>,
+    mut focus: ResMut<Focus>,
+    font_assets: Res<FontAssets>,
+    button_colors: Res<ButtonColors>,
+) {
+    if !cursor_grab.locked || typing.0 || !action_state.just_pressed(Action::Annotate) {
+        return;
+    }
+    let (transform, marker) = match target.0 {
+        Some(target) => target,
+        None => return,
+    };
+    let text_style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let field = commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(70.),
+                    ..default()
+                },
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        })
+        .insert(NotePanel)
+        .insert(LevelEntity)
+        .add_children(|parent| {
+            let note = Widget::text_input("Note", NOTE_LENGTH);
+            spawn_widget(parent, &text_style, &button_colors, note).id()
+        });
+    focus.0 = Some(field);
+    commands.insert_resource(PendingAnnotation {
+        transform,
+        owner: marker.owner,
+        field,
+    });
+}
+
+/// Removes the text input of the note, after it was finished or dropped
+fn close_note(commands: &mut Commands, panels: &Query<Entity, With<NotePanel>>) {
+    commands.remove_resource::<PendingAnnotation>();
+    for panel in panels {
+        commands.entity(panel).despawn_recursive();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn finish_annotation(
+    mut commands: Commands,
+    mut events: EventReader<WidgetEvent>,
+    pending: Option<Res<PendingAnnotation>>,
+    panels: Query<Entity, With<NotePanel>>,
+    character_materials: Res<CharacterMaterials>,
+    font_assets: Res<FontAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut note_mesh: Local<Option<Handle<Mesh>>>,
+) {
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return,
+    };
+    let mut notes = events.iter().filter(|event| event.entity == pending.field);
+    let text = match notes.next_back().map(|event| &event.value) {
+        Some(WidgetValue::Submitted(text)) => text.trim().to_owned(),
+        Some(_) => String::new(),
+        None => return,
+    };
+    close_note(&mut commands, &panels);
+    if text.is_empty() {
+        return;
+    }
+    let mesh = note_mesh
+        .get_or_insert_with(|| meshes.add(shape::Plane { size: 0.04 }.into()))
+        .clone();
+    let annotation = commands
+        .spawn_bundle(PbrBundle {
+            mesh,
+            material: character_materials.body(pending.owner),
+            transform: pending.transform,
+            ..default()
+        })
+        .insert(NotShadowCaster)
+        .insert(LevelEntity)
+        .insert(Annotation {
+            owner: pending.owner,
+            text: text.clone(),
+        })
+        .id();
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: text,
+                    style: TextStyle {
+                        font: font_assets.fira_sans.clone(),
+                        font_size: 20.0,
+                        color: Color::rgb(0.95, 0.95, 0.95),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(LevelEntity)
+        .insert(AnnotationLabel { annotation });
+}
+
+/// Closes the note that is typed when the level is paused or left, without keeping it
+fn drop_annotation(
+    mut commands: Commands,
+    pending: Option<Res<PendingAnnotation>>,
+    panels: Query<Entity, With<NotePanel>>,
+) {
+    if pending.is_some() {
+        close_note(&mut commands, &panels);
+    }
+}
+
+/// Moves the labels of nearby notes to where the notes are on screen
+fn place_annotation_labels(
+    camera: Query<(&Camera, &GlobalTransform), With<FlyCam>>,
+    current_character: Query<&Transform, (With<Character>, With<Controlled>)>,
+    annotations: Query<&GlobalTransform, With<Annotation>>,
+    mut labels: Query<(&AnnotationLabel, &mut Style, &mut Visibility)>,
+) {
+    let (camera, camera_transform) = match camera.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let character = match current_character.get_single() {
+        Ok(character) => character,
+        Err(_) => return,
+    };
+    for (label, mut style, mut visibility) in &mut labels {
+        let position = match annotations.get(label.annotation) {
+            Ok(annotation) => annotation.translation(),
+            Err(_) => continue,
+        };
+        let on_screen = if position.distance(character.translation) < LABEL_DISTANCE {
+            camera.world_to_viewport(camera_transform, position)
+        } else {
+            None
+        };
+        visibility.is_visible = on_screen.is_some();
+        if let Some(on_screen) = on_screen {
+            style.position = UiRect {
+                left: Val::Px(on_screen.x),
+                bottom: Val::Px(on_screen.y),
+                ..default()
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loading::TextureAssets;
+    use crate::palette::{CharacterPalette, PalettePlugin};
+    use bevy::asset::AssetPlugin;
+
+    fn annotation_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_plugin(PalettePlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_event::<WidgetEvent>()
+            .add_system(finish_annotation);
+        let palette: CharacterPalette =
+            ron::de::from_str(include_str!("../assets/characters.palette.ron")).unwrap();
+        let palette = app
+            .world
+            .resource_mut::<Assets<CharacterPalette>>()
+            .add(palette);
+        app.insert_resource(TextureAssets {
+            grass: default(),
+            marker: default(),
+            character_palette: palette,
+        })
+        .insert_resource(FontAssets {
+            fira_sans: default(),
+        })
+        .init_resource::<CharacterMaterials>();
+        app
+    }
+
+    /// Types a note at the origin and finishes it with the given event
+    fn write_note(app: &mut App, value: WidgetValue) {
+        let field = app.world.spawn().id();
+        app.insert_resource(PendingAnnotation {
+            transform: Transform::identity(),
+            owner: 2,
+            field,
+        });
+        app.world.send_event(WidgetEvent {
+            entity: field,
+            value,
+        });
+        app.update();
+        assert!(app.world.get_resource::<PendingAnnotation>().is_none());
+    }
+
+    fn notes(app: &mut App) -> Vec<(u8, String)> {
+        app.world
+            .query::<&Annotation>()
+            .iter(&app.world)
+            .map(|annotation| (annotation.owner, annotation.text.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn submitted_notes_are_kept_and_cancelled_ones_discarded() {
+        let mut app = annotation_app();
+        write_note(&mut app, WidgetValue::Submitted(" Turn left ".to_owned()));
+        assert_eq!(notes(&mut app), vec![(2, "Turn left".to_owned())]);
+
+        write_note(&mut app, WidgetValue::Cancelled);
+        assert_eq!(notes(&mut app).len(), 1);
+    }
+}
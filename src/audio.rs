@@ -1,21 +1,56 @@
 use crate::loading::AudioAssets;
+use crate::settings::Settings;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_kira_audio::prelude::*;
 
+/// Volume of the flying sound at full master volume, a quiet background loop
+const FLYING_VOLUME: f64 = 0.3;
+
 pub struct InternalAudioPlugin;
 
 // This plugin is responsible to control the game audio
 impl Plugin for InternalAudioPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(AudioPlugin)
-            .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(start_audio));
+            .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(start_audio))
+            .add_system(apply_volume);
     }
 }
 
-fn start_audio(audio_assets: Res<AudioAssets>, audio: Res<Audio>) {
-    audio
+struct FlyingSound(Handle<AudioInstance>);
+
+fn start_audio(
+    mut commands: Commands,
+    audio_assets: Res<AudioAssets>,
+    audio: Res<Audio>,
+    settings: Res<Settings>,
+    sound: Option<Res<FlyingSound>>,
+) {
+    // the loop keeps playing when coming back to the menu
+    if sound.is_some() {
+        return;
+    }
+    let handle = audio
         .play(audio_assets.flying.clone())
         .looped()
-        .with_volume(0.0);
+        .with_volume(FLYING_VOLUME * f64::from(settings.volume))
+        .handle();
+    commands.insert_resource(FlyingSound(handle));
+}
+
+fn apply_volume(
+    settings: Res<Settings>,
+    sound: Option<Res<FlyingSound>>,
+    mut instances: ResMut<Assets<AudioInstance>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    if let Some(instance) = sound.and_then(|sound| instances.get_mut(&sound.0)) {
+        instance.set_volume(
+            FLYING_VOLUME * f64::from(settings.volume),
+            AudioTween::default(),
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use crate::character::Character;
+use crate::map::{LevelEntity, WALL_HEIGHT};
+use crate::palette::CharacterMaterials;
+use crate::settings::Settings;
+use crate::simulation::{GameStopWatch, Position};
+use crate::{Difficulty, GameState};
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub struct BreadcrumbPlugin;
+
+/// This plugin lets characters leave a fading trail of breadcrumbs on the floor
+/// Breadcrumbs come from a fixed pool, so a long run does not pile up entities.
+impl Plugin for BreadcrumbPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BreadcrumbSettings>()
+            .add_system(apply_breadcrumb_settings)
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(spawn_breadcrumb_pool),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(drop_breadcrumbs)
+                    .with_system(fade_breadcrumbs.after(drop_breadcrumbs)),
+            );
+    }
+}
+
+pub struct BreadcrumbSettings {
+    pub enabled: bool,
+    /// Distance a character walks between two breadcrumbs
+    pub spacing: f32,
+    /// Seconds of game time until a breadcrumb disappeared
+    pub fade_seconds: f32,
+    /// Maximum number of breadcrumbs in the level; the oldest ones are reused
+    pub pool_size: usize,
+}
+
+impl BreadcrumbSettings {
+    /// The defaults of the difficulty, the player can still turn breadcrumbs on or off
+    pub fn for_difficulty(difficulty: Difficulty) -> Self {
+        let (enabled, fade_seconds) = match difficulty {
+            Difficulty::Easy => (true, 60.),
+            Difficulty::Normal => (true, 15.),
+            Difficulty::Hard => (false, 15.),
+        };
+        BreadcrumbSettings {
+            enabled,
+            spacing: 0.35,
+            fade_seconds,
+            pool_size: 200,
+        }
+    }
+}
+
+impl Default for BreadcrumbSettings {
+    fn default() -> Self {
+        BreadcrumbSettings::for_difficulty(Difficulty::default())
+    }
+}
+
+#[derive(Component)]
+struct Breadcrumb {
+    dropped_at: f32,
+    color: Color,
+}
+
+/// Where a character dropped its last breadcrumb
+#[derive(Component)]
+struct LastBreadcrumb(Vec3);
+
+struct BreadcrumbPool {
+    crumbs: Vec<Entity>,
+    next: usize,
+}
+
+impl BreadcrumbPool {
+    /// The breadcrumb that was dropped the longest time ago
+    fn take_oldest(&mut self) -> Option<Entity> {
+        let crumb = *self.crumbs.get(self.next)?;
+        self.next = (self.next + 1) % self.crumbs.len();
+        Some(crumb)
+    }
+}
+
+/// Opacity of a breadcrumb of the given age in seconds, `None` once it disappeared
+fn fade(age: f32, fade_seconds: f32) -> Option<f32> {
+    let faded = age / fade_seconds;
+    (faded < 1.).then_some(0.4 * (1. - faded))
+}
+
+fn apply_breadcrumb_settings(
+    difficulty: Res<Difficulty>,
+    settings: Res<Settings>,
+    mut breadcrumbs: ResMut<BreadcrumbSettings>,
+) {
+    if difficulty.is_changed() || settings.is_changed() {
+        *breadcrumbs = BreadcrumbSettings::for_difficulty(*difficulty);
+        if let Some(enabled) = settings.breadcrumbs {
+            breadcrumbs.enabled = enabled;
+        }
+    }
+}
+
+fn spawn_breadcrumb_pool(
+    mut commands: Commands,
+    settings: Res<BreadcrumbSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mesh = meshes.add(Mesh::from(shape::Circle {
+        radius: 0.03,
+        vertices: 12,
+    }));
+    let crumbs = (0..settings.pool_size)
+        .map(|_| {
+            // every breadcrumb fades on its own, so it needs its own material
+            let material = materials.add(StandardMaterial {
+                base_color: Color::NONE,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            });
+            commands
+                .spawn_bundle(PbrBundle {
+                    mesh: mesh.clone(),
+                    material,
+                    transform: Transform::from_rotation(Quat::from_rotation_x(-FRAC_PI_2)),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(NotShadowCaster)
+                .insert(LevelEntity)
+                .insert(Breadcrumb {
+                    dropped_at: f32::NEG_INFINITY,
+                    color: Color::NONE,
+                })
+                .id()
+        })
+        .collect();
+    commands.insert_resource(BreadcrumbPool { crumbs, next: 0 });
+}
+
+fn drop_breadcrumbs(
+    mut commands: Commands,
+    settings: Res<BreadcrumbSettings>,
+    pool: Option<ResMut<BreadcrumbPool>>,
+    stop_watch: Res<GameStopWatch>,
+    character_materials: Res<CharacterMaterials>,
+    characters: Query<(Entity, &Character, &Position, Option<&LastBreadcrumb>)>,
+    mut crumbs: Query<(&mut Breadcrumb, &mut Transform, &mut Visibility)>,
+) {
+    let mut pool = match pool {
+        Some(pool) if settings.enabled => pool,
+        _ => return,
+    };
+    for (entity, character, position, last) in &characters {
+        if let Some(LastBreadcrumb(last)) = last {
+            if last.distance(position.current) < settings.spacing {
+                continue;
+            }
+        }
+        commands
+            .entity(entity)
+            .insert(LastBreadcrumb(position.current));
+        let crumb = match pool.take_oldest() {
+            Some(crumb) => crumb,
+            None => return,
+        };
+        if let Ok((mut breadcrumb, mut transform, mut visibility)) = crumbs.get_mut(crumb) {
+            breadcrumb.dropped_at = stop_watch.0.elapsed_secs();
+            breadcrumb.color = character_materials.color(character.numbers()[0]);
+            transform.translation =
+                Vec3::new(position.current.x, -WALL_HEIGHT + 0.002, position.current.z);
+            visibility.is_visible = true;
+        }
+    }
+}
+
+fn fade_breadcrumbs(
+    settings: Res<BreadcrumbSettings>,
+    stop_watch: Res<GameStopWatch>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut crumbs: Query<(&Breadcrumb, &Handle<StandardMaterial>, &mut Visibility)>,
+) {
+    let now = stop_watch.0.elapsed_secs();
+    for (breadcrumb, material, mut visibility) in &mut crumbs {
+        if !visibility.is_visible {
+            continue;
+        }
+        let alpha = match fade(now - breadcrumb.dropped_at, settings.fade_seconds) {
+            Some(alpha) if settings.enabled => alpha,
+            _ => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+        if let Some(material) = materials.get_mut(material) {
+            let mut color = breadcrumb.color;
+            color.set_a(alpha);
+            material.base_color = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_breadcrumb_is_reused() {
+        let crumbs: Vec<Entity> = (0..3).map(Entity::from_raw).collect();
+        let mut pool = BreadcrumbPool {
+            crumbs: crumbs.clone(),
+            next: 0,
+        };
+        let taken: Vec<Entity> = (0..7).filter_map(|_| pool.take_oldest()).collect();
+        assert_eq!(taken[..3], crumbs[..]);
+        assert_eq!(taken[3..6], crumbs[..]);
+        assert_eq!(taken[6], crumbs[0]);
+
+        let mut empty = BreadcrumbPool {
+            crumbs: vec![],
+            next: 0,
+        };
+        assert_eq!(empty.take_oldest(), None);
+    }
+
+    #[test]
+    fn difficulty_sets_the_defaults() {
+        let easy = BreadcrumbSettings::for_difficulty(Difficulty::Easy);
+        let normal = BreadcrumbSettings::for_difficulty(Difficulty::Normal);
+        let hard = BreadcrumbSettings::for_difficulty(Difficulty::Hard);
+        assert!(easy.enabled && normal.enabled && !hard.enabled);
+        assert!(easy.fade_seconds > normal.fade_seconds);
+        assert_eq!(BreadcrumbSettings::default().enabled, normal.enabled);
+    }
+
+    #[test]
+    fn breadcrumbs_fade_until_they_disappear() {
+        assert_eq!(fade(0., 15.), Some(0.4));
+        let halfway = fade(7.5, 15.).unwrap();
+        assert!((halfway - 0.2).abs() < 0.001);
+        assert!(fade(14.9, 15.).unwrap() > 0.);
+        assert_eq!(fade(15., 15.), None);
+        assert_eq!(fade(f32::INFINITY, 15.), None);
+    }
+}
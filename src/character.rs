@@ -1,43 +1,77 @@
-use crate::actions::Action;
-use crate::loading::{LabyrinthLevel, MazeAssets, TextureAssets};
-use crate::map::{MyRaycastSet, PlaneAsset, PIXEL_WORLD_SIZE, WALL_HEIGHT};
-use crate::ui::Notification;
+use crate::actions::{Action, CursorGrab};
+use crate::map::{LevelEntity, Maze, PIXEL_WORLD_SIZE, WALL_HEIGHT};
+use crate::notifications::{Notification, NotificationEvent, Priority};
+use crate::palette::CharacterMaterials;
+use crate::simulation::{
+    simulation_running, Position, SimulationStage, SimulationSystem, TickInput, TICK_SECONDS,
+};
+use crate::widgets::Typing;
 use crate::GameState;
 use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::EntityCommands;
 use bevy::input::mouse::MouseMotion;
-use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
-use bevy_mod_raycast::{DebugCursor, DebugCursorTail, RaycastSystem};
-use leafwing_input_manager::prelude::*;
+use bevy::transform::TransformSystem;
 
 pub const PLAYER_Y: f32 = -WALL_HEIGHT + PLAYER_RADIUS;
 pub const PLAYER_RADIUS: f32 = 0.125;
+/// Id of the prompt shown while another part is close enough to combine
+const COMBINE_PROMPT: &str = "combine";
+/// Mouse sensitivity the game is tuned for, bevy_flycam uses 0.00012
+pub const DEFAULT_SENSITIVITY: f32 = 0.00017;
 
 pub struct CharacterPlugin;
 
+/// This plugin contains the gameplay logic of the characters
+/// It does not need a window or any assets, so it also runs in the headless test harness.
 impl Plugin for CharacterPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MovementSettings {
-            sensitivity: 0.00017, // default: 0.00012
-            speed: 1.5,           // default: 12.0
+            sensitivity: DEFAULT_SENSITIVITY,
+            invert_y: false,
+            speed: 1.5, // default: 12.0
         })
         .init_resource::<CamInputState>()
         .add_event::<LeaveLabyrinthEvent>()
+        .add_event::<LevelCompletedEvent>()
+        .add_event::<ControlSwitchedEvent>()
         .add_system_set(
             SystemSet::on_enter(GameState::Playing)
                 .with_system(spawn_characters)
-                .with_system(initial_grab_cursor),
+                .with_system(reset_cam_input),
+        )
+        .add_system_set_to_stage(
+            SimulationStage,
+            SystemSet::new()
+                .with_run_criteria(simulation_running)
+                .label(SimulationSystem::Gameplay)
+                .with_system(switch_character_control.after(SimulationSystem::BeginTick))
+                .with_system(player_move.after(switch_character_control))
+                .with_system(leave_labyrinth.after(player_move))
+                .with_system(attempt_combine.after(player_move)),
+        );
+    }
+}
+
+pub struct CharacterViewPlugin;
+
+/// This plugin gives characters their meshes and handles the first person camera
+impl Plugin for CharacterViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing).with_system(initial_grab_cursor),
         )
         .add_system_set(SystemSet::on_resume(GameState::Playing).with_system(initial_grab_cursor))
         .add_system_set(
             SystemSet::on_update(GameState::Playing)
-                .with_system(player_look.before(follow_camera))
-                .with_system(player_move.before(follow_camera))
-                .with_system(leave_labyrinth.after(player_move))
-                .with_system(attempt_combine)
-                .with_system(follow_camera)
-                .with_system(draw_markers.after(RaycastSystem::UpdateDebugCursor::<MyRaycastSet>))
-                .with_system(switch_character_control.after(follow_camera)),
+                .with_system(add_character_meshes)
+                .with_system(player_look),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            follow_camera
+                .after(SimulationSystem::Interpolate)
+                .before(TransformSystem::TransformPropagate),
         );
     }
 }
@@ -45,137 +79,76 @@ impl Plugin for CharacterPlugin {
 #[derive(Component)]
 pub struct Character {
     numbers: Vec<u8>,
-    pub color: CharacterColor,
 }
 
-pub enum CharacterColor {
-    Green,
-    Blue,
-    Red,
-}
-
-impl CharacterColor {
-    fn from_number(number: u8) -> CharacterColor {
-        match number {
-            1 => CharacterColor::Green,
-            2 => CharacterColor::Blue,
-            _ => CharacterColor::Red,
-        }
+impl Character {
+    /// Numbers of all parts combined into this character
+    pub fn numbers(&self) -> &[u8] {
+        &self.numbers
     }
 }
 
-fn spawn_characters(
-    mut commands: Commands,
-    textures: Res<TextureAssets>,
-    maze_assets: Res<MazeAssets>,
-    maze_levels: Res<Assets<LabyrinthLevel>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-) {
-    let player_mesh = meshes.add(Mesh::from(shape::Icosphere {
-        radius: PLAYER_RADIUS,
-        subdivisions: 5,
-    }));
-    let maze_level = maze_levels.get(&maze_assets.one_level).unwrap();
-    for (index, starting_position) in maze_level.spawns.iter().enumerate() {
+fn spawn_characters(mut commands: Commands, maze: Res<Maze>) {
+    for (index, starting_position) in maze.level.spawns.iter().enumerate() {
         let character_number = (index as u8) + 1;
-        let mut character = commands.spawn_bundle(PbrBundle {
-            mesh: player_mesh.clone(),
-            material: textures.get_character_texture(character_number),
-            transform: Transform::from_translation(Vec3::new(
-                starting_position[0] * PIXEL_WORLD_SIZE,
-                PLAYER_Y,
-                starting_position[1] * PIXEL_WORLD_SIZE,
-            )),
-            ..default()
-        });
-        character
-            .insert(Character {
-                numbers: vec![character_number],
-                color: CharacterColor::from_number(character_number),
-            })
-            .insert(CamInputState::default());
+        let translation = Vec3::new(
+            starting_position[0] * PIXEL_WORLD_SIZE,
+            PLAYER_Y,
+            starting_position[1] * PIXEL_WORLD_SIZE,
+        );
+        let mut character = spawn_character(
+            &mut commands,
+            vec![character_number],
+            translation,
+            CamInputState::default(),
+        );
         if character_number == 1 {
             character.insert(Controlled);
         }
     }
 }
 
-#[derive(Component)]
-pub struct MarkerMask;
+/// Spawns a character made of the given parts, which is not controlled yet
+pub fn spawn_character<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    numbers: Vec<u8>,
+    translation: Vec3,
+    cam_state: CamInputState,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut character = commands.spawn_bundle(SpatialBundle::from_transform(
+        Transform::from_translation(translation),
+    ));
+    character
+        .insert(Character { numbers })
+        .insert(Position::new(translation))
+        .insert(cam_state)
+        .insert(LevelEntity);
+    character
+}
 
-fn draw_markers(
-    input: Res<Input<MouseButton>>,
+fn add_character_meshes(
     mut commands: Commands,
-    mut cursor: Query<
-        (
-            Entity,
-            &mut Handle<Mesh>,
-            &mut Handle<StandardMaterial>,
-            &mut Transform,
-        ),
-        (With<DebugCursor<MyRaycastSet>>, Without<Controlled>),
-    >,
-    textures: Res<TextureAssets>,
-    current_character: Query<(&Character, &Transform), With<Controlled>>,
-    plane: Res<PlaneAsset>,
-    tail: Query<Entity, With<DebugCursorTail<MyRaycastSet>>>,
+    character_materials: Res<CharacterMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut player_mesh: Local<Option<Handle<Mesh>>>,
+    characters: Query<(Entity, &Character), Added<Character>>,
 ) {
-    let (character, char_transform) = current_character.single();
-    if let Ok((entity, mut mesh, mut material, mut transform)) = cursor.get_single_mut() {
-        if transform.translation.distance(char_transform.translation) < 1. {
-            let up = transform.up();
-            transform.translation += up.normalize() * 0.005; // 0.005
-            *mesh = plane.0.clone();
-            *material = match character.color {
-                CharacterColor::Green => textures.green_marker_mask.clone(),
-                CharacterColor::Blue => textures.blue_marker_mask.clone(),
-                CharacterColor::Red => textures.red_marker_mask.clone(),
-            };
-            commands.entity(entity).insert(NotShadowCaster);
-            if input.just_pressed(MouseButton::Left) {
-                commands
-                    .spawn_bundle(PbrBundle {
-                        mesh: plane.0.clone(),
-                        transform: transform.clone(),
-                        material: match character.color {
-                            CharacterColor::Green => textures.green_marker.clone(),
-                            CharacterColor::Blue => textures.blue_marker.clone(),
-                            CharacterColor::Red => textures.red_marker.clone(),
-                        },
-                        ..default()
-                    })
-                    .insert(NotShadowCaster);
-            }
-            if let Ok(tail) = tail.get_single() {
-                commands.entity(tail).despawn();
-            }
-        } else {
-            commands.entity(entity).despawn_recursive();
-        }
+    for (entity, character) in &characters {
+        let mesh = player_mesh
+            .get_or_insert_with(|| {
+                meshes.add(Mesh::from(shape::Icosphere {
+                    radius: PLAYER_RADIUS,
+                    subdivisions: 5,
+                }))
+            })
+            .clone();
+        commands
+            .entity(entity)
+            .insert(mesh)
+            .insert(character_materials.body(character.numbers[0]));
     }
 }
 
-// fn draw_markers(mut commands: Commands, query: Query<(&Transform, &Intersection<MyRaycastSet>)>, old_marker_masks: Query<Entity, With<MarkerMask>>, textures: Res<TextureAssets>, current_character: Query<(&Character, &Transform), With<Controlled>>) {
-// let (character, char_transform) = current_character.single();
-// println!("draw_markers");
-// for (transform, intersection) in &query {
-//     println!("intersect");
-//     if let Some(position) = intersection.position() {
-//         if position.distance(char_transform.translation) < 100. {
-//             println!("draw at {:?}", position);
-//             let mut mask_transform = transform.clone();
-//             mask_transform = mask_transform.with_translation(position.clone());
-//             commands.spawn_bundle(PbrBundle {
-//                 material: textures.green_marker_mask.clone(),
-//                 transform: mask_transform,
-//                 ..default()
-//             }).insert(MarkerMask);
-//         }
-//     }
-// }
-// old_marker_masks.iter().for_each(|entity| commands.entity(entity).despawn());
-// }
-
 fn initial_grab_cursor(mut windows: ResMut<Windows>) {
     if let Some(window) = windows.get_primary_mut() {
         window.set_cursor_lock_mode(true);
@@ -190,87 +163,68 @@ pub struct Controlled;
 
 fn switch_character_control(
     mut commands: Commands,
-    input: Res<Input<KeyCode>>,
+    tick_input: Res<TickInput>,
     mut fly_cam_input_state: ResMut<CamInputState>,
+    mut switched_events: EventWriter<ControlSwitchedEvent>,
     mut controlled_character: Query<(Entity, &mut CamInputState), With<Controlled>>,
-    characters: Query<
-        (Entity, &Transform, &Character, &CamInputState),
-        (Without<Camera>, Without<Controlled>),
-    >,
-    mut camera: Query<&mut Transform, With<Camera>>,
+    characters: Query<(Entity, &Character, &CamInputState), Without<Controlled>>,
 ) {
-    let pressed = if input.just_pressed(KeyCode::Numpad1) || input.just_pressed(KeyCode::Key1) {
-        1u8
-    } else if input.just_pressed(KeyCode::Numpad2) || input.just_pressed(KeyCode::Key2) {
-        2u8
-    } else if input.just_pressed(KeyCode::Numpad3) || input.just_pressed(KeyCode::Key3) {
-        3u8
-    } else {
-        0u8
-    };
-    if pressed > 0 {
-        if let Some((entity, transform, _, cam_character_state)) = characters
+    let pressed = tick_input
+        .triggered
+        .iter()
+        .find_map(|action| action.controlled_number());
+    if let Some(pressed) = pressed {
+        if let Some((entity, _, cam_character_state)) = characters
             .iter()
-            .find(|(_, _, character, _)| character.numbers.contains(&pressed))
+            .find(|(_, character, _)| character.numbers.contains(&pressed))
         {
             let (controlled_entity, mut cam_state) = controlled_character.single_mut();
-            cam_state.yaw = fly_cam_input_state.yaw;
-            cam_state.pitch = fly_cam_input_state.pitch;
-            commands
-                .entity(controlled_entity)
-                .remove_bundle::<InputManagerBundle<Action>>()
-                .remove::<Controlled>();
+            cam_state.yaw = tick_input.yaw;
+            cam_state.pitch = tick_input.pitch;
+            commands.entity(controlled_entity).remove::<Controlled>();
             fly_cam_input_state.pitch = cam_character_state.pitch;
             fly_cam_input_state.yaw = cam_character_state.yaw;
-            let mut camera_transform = camera.single_mut();
-            camera_transform.translation = transform.translation;
-            camera_transform.rotation = Quat::from_axis_angle(Vec3::Y, cam_character_state.yaw)
-                * Quat::from_axis_angle(Vec3::X, cam_character_state.pitch);
-            commands.entity(entity).insert(Controlled).insert_bundle(
-                InputManagerBundle::<Action> {
-                    action_state: ActionState::default(),
-                    input_map: InputMap::new([
-                        (KeyCode::A, Action::TurnLeft),
-                        (KeyCode::D, Action::TurnRight),
-                        (KeyCode::W, Action::Walk),
-                    ]),
-                },
-            );
+            commands.entity(entity).insert(Controlled);
+            switched_events.send(ControlSwitchedEvent);
         }
     }
 }
 
 pub struct LeaveLabyrinthEvent;
 
+/// Sent when all parts left the labyrinth together
+pub struct LevelCompletedEvent;
+
+/// Sent when control switched to another character
+pub struct ControlSwitchedEvent;
+
 fn follow_camera(
-    mut character: Query<&mut Transform, (With<Controlled>, Without<Camera>)>,
-    mut camera: Query<&mut Transform, With<Camera>>,
+    character: Query<&Transform, (With<Controlled>, Without<FlyCam>)>,
+    cam_input_state: Res<CamInputState>,
+    mut camera: Query<&mut Transform, With<FlyCam>>,
 ) {
-    if let Ok(mut transform) = character.get_single_mut() {
-        transform.translation = camera.single_mut().translation.clone();
+    if let (Ok(character), Ok(mut camera)) = (character.get_single(), camera.get_single_mut()) {
+        camera.translation = character.translation;
+        // Order is important to prevent unintended roll
+        camera.rotation = Quat::from_axis_angle(Vec3::Y, cam_input_state.yaw)
+            * Quat::from_axis_angle(Vec3::X, cam_input_state.pitch);
     }
 }
 
 fn attempt_combine(
     mut commands: Commands,
-    input: Res<Input<KeyCode>>,
-    characters: Query<(Entity, &Transform, &Character), Without<Controlled>>,
-    mut notification: ResMut<Notification>,
-    mut controlled_character: Query<(&Transform, &mut Character), With<Controlled>>,
+    tick_input: Res<TickInput>,
+    characters: Query<(Entity, &Position, &Character), Without<Controlled>>,
+    mut notifications: EventWriter<NotificationEvent>,
+    mut controlled_character: Query<(&Position, &mut Character), With<Controlled>>,
 ) {
-    if notification.text == Some("Press space to combine parts".to_string()) {
-        notification.text = None;
-    }
-    let (controlled_transform, mut controlled_character) = controlled_character.single_mut();
-    for (entity, transform, character) in &characters {
-        if transform
-            .translation
-            .distance(controlled_transform.translation)
-            < PLAYER_RADIUS * 2.
-        {
-            notification.text = Some("Press space to combine parts".to_string());
-            if !input.just_pressed(KeyCode::Space) {
-                return;
+    let (controlled_position, mut controlled_character) = controlled_character.single_mut();
+    let mut can_combine = false;
+    for (entity, position, character) in &characters {
+        if position.current.distance(controlled_position.current) < PLAYER_RADIUS * 2. {
+            if !tick_input.triggered.contains(Action::Combine) {
+                can_combine = true;
+                break;
             }
             character
                 .numbers
@@ -279,6 +233,14 @@ fn attempt_combine(
             commands.entity(entity).despawn_recursive();
         }
     }
+    notifications.send(if can_combine {
+        NotificationEvent::Show(Notification::prompt(
+            COMBINE_PROMPT,
+            "Press space to combine parts",
+        ))
+    } else {
+        NotificationEvent::Clear(COMBINE_PROMPT)
+    });
 }
 
 /// Modified from bevy_flycam (see credits directory for copyright notice and license file)
@@ -289,10 +251,27 @@ pub struct CamInputState {
     pub yaw: f32,
 }
 
+impl CamInputState {
+    pub fn looking(yaw: f32, pitch: f32) -> Self {
+        CamInputState {
+            pitch,
+            yaw,
+            ..default()
+        }
+    }
+}
+
+/// A new or restarted level starts looking straight ahead
+fn reset_cam_input(mut state: ResMut<CamInputState>) {
+    state.pitch = 0.;
+    state.yaw = 0.;
+}
+
 /// Modified from bevy_flycam (see credits directory for copyright notice and license file)
 /// Mouse sensitivity and movement speed
 pub struct MovementSettings {
     pub sensitivity: f32,
+    pub invert_y: bool,
     pub speed: f32,
 }
 
@@ -302,204 +281,70 @@ pub struct MovementSettings {
 pub struct FlyCam;
 
 /// Modified from bevy_flycam (see credits directory for copyright notice and license file)
-/// Handles keyboard input and movement
+/// Moves the controlled character one simulation tick according to the tick input
 pub fn player_move(
-    keys: Res<Input<KeyCode>>,
-    time: Res<Time>,
-    windows: Res<Windows>,
+    tick_input: Res<TickInput>,
     settings: Res<MovementSettings>,
-    maze_assets: Res<MazeAssets>,
-    maze_levels: Res<Assets<LabyrinthLevel>>,
+    maze: Res<Maze>,
     mut leave_labyrinth_events: EventWriter<LeaveLabyrinthEvent>,
-    images: Res<Assets<Image>>,
-    mut query: Query<&mut Transform, With<FlyCam>>,
+    mut query: Query<&mut Position, With<Controlled>>,
 ) {
-    if let Some(window) = windows.get_primary() {
-        if !window.cursor_locked() {
-            return;
-        }
-        let maze_image = images.get(&maze_assets.one_data).unwrap();
-        let pixel_per_row = maze_image.texture_descriptor.size.width as usize;
-        let world_width = pixel_per_row as f32 * PIXEL_WORLD_SIZE;
-        let maze_level = maze_levels.get(&maze_assets.one_level).unwrap();
-        for mut transform in query.iter_mut() {
-            let mut velocity = Vec3::ZERO;
-            let local_z = transform.local_z();
-            let forward = -Vec3::new(local_z.x, 0., local_z.z);
-            let right = Vec3::new(local_z.z, 0., -local_z.x);
-
-            for key in keys.get_pressed() {
-                match key {
-                    KeyCode::W => velocity += forward,
-                    KeyCode::S => velocity -= forward,
-                    KeyCode::A => velocity -= right,
-                    KeyCode::D => velocity += right,
-                    #[cfg(debug_assertions)]
-                    KeyCode::LShift => velocity += Vec3::Y,
-                    #[cfg(debug_assertions)]
-                    KeyCode::LControl => velocity -= Vec3::Y,
-                    _ => (),
-                }
-            }
-
-            velocity = velocity.normalize_or_zero();
-            let mut movement = velocity * time.delta_seconds() * settings.speed;
-
-            #[cfg(debug_assertions)]
-            if transform.translation.y > 0.0 {
-                transform.translation += movement;
-                continue;
+    let rotation = Quat::from_axis_angle(Vec3::Y, tick_input.yaw);
+    let local_z = rotation * Vec3::Z;
+    let forward = -Vec3::new(local_z.x, 0., local_z.z);
+    let right = Vec3::new(local_z.z, 0., -local_z.x);
+    for mut position in query.iter_mut() {
+        let mut velocity = Vec3::ZERO;
+        for action in tick_input.held.iter() {
+            match action {
+                Action::Forward => velocity += forward,
+                Action::Backward => velocity -= forward,
+                Action::StrafeLeft => velocity -= right,
+                Action::StrafeRight => velocity += right,
+                #[cfg(debug_assertions)]
+                Action::Ascend => velocity += Vec3::Y,
+                #[cfg(debug_assertions)]
+                Action::Descend => velocity -= Vec3::Y,
+                _ => (),
             }
+        }
 
-            if movement.z > 0.
-                && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE)
-                    + PLAYER_RADIUS
-                    + movement.z
-                    > PIXEL_WORLD_SIZE
-            {
-                let slot_y = ((transform.translation.z + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let slot_x = ((transform.translation.x + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let next_pixel = (slot_y + 1) * pixel_per_row + slot_x;
-                if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                    if maze_level.exit[0] == slot_x && maze_level.exit[1] == slot_y + 1 {
-                        leave_labyrinth_events.send(LeaveLabyrinthEvent);
-                    }
-                    movement.z = 0.0;
-                }
-            } else if movement.z < 0.
-                && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE) - PLAYER_RADIUS
-                    + movement.z
-                    < 0.0
-            {
-                let slot_y = ((transform.translation.z + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let slot_x = ((transform.translation.x + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let next_pixel = (slot_y - 1) * pixel_per_row + slot_x;
-                if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                    if maze_level.exit[0] == slot_x && maze_level.exit[1] == slot_y - 1 {
-                        leave_labyrinth_events.send(LeaveLabyrinthEvent);
-                    }
-                    movement.z = 0.0;
-                }
-            }
+        velocity = velocity.normalize_or_zero();
+        let movement = velocity * TICK_SECONDS as f32 * settings.speed;
 
-            if movement.x > 0.
-                && ((transform.translation.x + world_width) % PIXEL_WORLD_SIZE)
-                    + PLAYER_RADIUS
-                    + movement.x
-                    > PIXEL_WORLD_SIZE
-            {
-                let slot_y = ((transform.translation.z + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let slot_x = ((transform.translation.x + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                // corners...
-                if movement.z > 0.
-                    && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE)
-                        + PLAYER_RADIUS
-                        + movement.z
-                        > PIXEL_WORLD_SIZE
-                {
-                    let next_pixel = (slot_y + 1) * pixel_per_row + slot_x + 1;
-                    if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                        if movement.z.abs() > movement.x.abs() {
-                            movement.z = 0.0;
-                        } else {
-                            movement.x = 0.0;
-                        }
-                    }
-                } else if movement.z < 0.
-                    && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE) - PLAYER_RADIUS
-                        + movement.z
-                        < 0.0
-                {
-                    let next_pixel = (slot_y - 1) * pixel_per_row + slot_x + 1;
-                    if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                        if movement.z.abs() > movement.x.abs() {
-                            movement.z = 0.0;
-                        } else {
-                            movement.x = 0.0;
-                        }
-                    }
-                }
-                let next_pixel = slot_y * pixel_per_row + slot_x + 1;
-                if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                    if maze_level.exit[0] == slot_x + 1 && maze_level.exit[1] == slot_y {
-                        leave_labyrinth_events.send(LeaveLabyrinthEvent);
-                    }
-                    movement.x = 0.0;
-                }
-            } else if movement.x < 0.
-                && ((transform.translation.x + world_width) % PIXEL_WORLD_SIZE) - PLAYER_RADIUS
-                    + movement.x
-                    < 0.0
-            {
-                let slot_y = ((transform.translation.z + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                let slot_x = ((transform.translation.x + world_width / 2.) / PIXEL_WORLD_SIZE)
-                    .round() as usize;
-                // corners...
-                if movement.z > 0.
-                    && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE)
-                        + PLAYER_RADIUS
-                        + movement.z
-                        > PIXEL_WORLD_SIZE
-                {
-                    let next_pixel = (slot_y + 1) * pixel_per_row + slot_x - 1;
-                    if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                        if movement.z.abs() > movement.x.abs() {
-                            movement.z = 0.0;
-                        } else {
-                            movement.x = 0.0;
-                        }
-                    }
-                } else if movement.z < 0.
-                    && ((transform.translation.z + world_width) % PIXEL_WORLD_SIZE) - PLAYER_RADIUS
-                        + movement.z
-                        < 0.0
-                {
-                    let next_pixel = (slot_y - 1) * pixel_per_row + slot_x - 1;
-                    if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                        if movement.z.abs() > movement.x.abs() {
-                            movement.z = 0.0;
-                        } else {
-                            movement.x = 0.0;
-                        }
-                    }
-                }
-                let next_pixel = slot_y * pixel_per_row + slot_x - 1;
-                if maze_image.data.get(next_pixel * 4).unwrap() < &50 {
-                    if maze_level.exit[0] == slot_x - 1 && maze_level.exit[1] == slot_y {
-                        leave_labyrinth_events.send(LeaveLabyrinthEvent);
-                    }
-                    movement.x = 0.0;
-                }
-            }
+        #[cfg(debug_assertions)]
+        if position.current.y > 0.0 {
+            position.current += movement;
+            continue;
+        }
 
-            transform.translation += movement;
+        let (movement, reached_exit) = maze.restrict_movement(position.current, movement);
+        if reached_exit {
+            leave_labyrinth_events.send(LeaveLabyrinthEvent);
         }
-    } else {
-        warn!("Primary window not found for `player_move`!");
+        position.current += movement;
     }
 }
 
 fn leave_labyrinth(
     mut events: EventReader<LeaveLabyrinthEvent>,
+    mut completed_events: EventWriter<LevelCompletedEvent>,
     controlled_character: Query<&Character, With<Controlled>>,
-    mut notification: ResMut<Notification>,
-    time: Res<Time>,
+    maze: Res<Maze>,
+    mut notifications: EventWriter<NotificationEvent>,
 ) {
     if let Some(_event) = events.iter().last() {
-        if controlled_character.single().numbers.len() == 3 {
-            notification.text = Some("You won!".to_string());
-            notification.remove_when = None;
+        if controlled_character.single().numbers.len() == maze.level.spawns.len() {
+            completed_events.send(LevelCompletedEvent);
         } else {
-            notification.text =
-                Some("You need to combine all parts before you can leave".to_string());
-            notification.remove_when = Some(time.seconds_since_startup() + 5.);
+            notifications.send(NotificationEvent::Show(
+                Notification::toast(
+                    "leave",
+                    "You need to combine all parts before you can leave",
+                    5.,
+                )
+                .with_priority(Priority::High),
+            ));
         }
     }
 }
@@ -508,32 +353,130 @@ fn leave_labyrinth(
 /// Handles looking around if cursor is locked
 pub fn player_look(
     settings: Res<MovementSettings>,
+    cursor_grab: Res<CursorGrab>,
+    typing: Res<Typing>,
     windows: Res<Windows>,
     mut state: ResMut<CamInputState>,
     motion: Res<Events<MouseMotion>>,
-    mut query: Query<&mut Transform, With<FlyCam>>,
 ) {
     if let Some(window) = windows.get_primary() {
-        let mut delta_state = state.as_mut();
-        for mut transform in query.iter_mut() {
-            for ev in delta_state.reader_motion.iter(&motion) {
-                if window.cursor_locked() {
-                    // Using smallest of height or width ensures equal vertical and horizontal sensitivity
-                    let window_scale = window.height().min(window.width());
-                    delta_state.pitch -=
-                        (settings.sensitivity * ev.delta.y * window_scale).to_radians();
-                    delta_state.yaw -=
-                        (settings.sensitivity * ev.delta.x * window_scale).to_radians();
-                }
-
-                delta_state.pitch = delta_state.pitch.clamp(-1.54, 1.54);
-
-                // Order is important to prevent unintended roll
-                transform.rotation = Quat::from_axis_angle(Vec3::Y, delta_state.yaw)
-                    * Quat::from_axis_angle(Vec3::X, delta_state.pitch);
+        let delta_state = state.as_mut();
+        for ev in delta_state.reader_motion.iter(&motion) {
+            if cursor_grab.locked && !typing.0 {
+                // Using smallest of height or width ensures equal vertical and horizontal sensitivity
+                let window_scale = window.height().min(window.width());
+                let delta_y = if settings.invert_y {
+                    -ev.delta.y
+                } else {
+                    ev.delta.y
+                };
+                delta_state.pitch -= (settings.sensitivity * delta_y * window_scale).to_radians();
+                delta_state.yaw -= (settings.sensitivity * ev.delta.x * window_scale).to_radians();
             }
+
+            delta_state.pitch = delta_state.pitch.clamp(-1.54, 1.54);
         }
     } else {
         warn!("Primary window not found for `player_look`!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::Notifications;
+    use crate::testing::{maze, TestGame};
+    use std::f32::consts::FRAC_PI_2;
+
+    const CORRIDOR: [&str; 7] = [
+        "#######", "#######", "#######", "#.....#", "#######", "#######", "#######",
+    ];
+    /// Yaw of a character looking along the positive x axis
+    const EAST: f32 = -FRAC_PI_2;
+
+    fn walk_until_near(game: &mut TestGame, target: u8) {
+        game.look(EAST);
+        game.hold(Action::Forward);
+        for _ in 0..200 {
+            if game.position_of(1).distance(game.position_of(target)) < PLAYER_RADIUS * 1.5 {
+                break;
+            }
+            game.step(1);
+        }
+        game.release_all();
+    }
+
+    #[test]
+    fn walls_stop_movement() {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]));
+        let start = game.position_of(1);
+        game.look(-EAST);
+        game.hold(Action::Forward);
+        game.step(60);
+
+        let position = game.position_of(1);
+        assert_eq!(position.z, start.z);
+        assert!(position.x < start.x);
+        assert!(position.x > start.x - PIXEL_WORLD_SIZE / 2. + PLAYER_RADIUS - 0.001);
+    }
+
+    #[test]
+    fn same_input_gives_same_result() {
+        let run = || {
+            let mut game = TestGame::new(maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]));
+            game.hold(Action::Forward);
+            for tick in 0..120 {
+                game.look(EAST + (tick as f32 / 10.).sin());
+                game.step(1);
+            }
+            game.position_of(1)
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn combine_parts_and_leave() {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]));
+        walk_until_near(&mut game, 2);
+        game.trigger(Action::Combine);
+        game.step(1);
+        assert_eq!(game.controlled_numbers(), vec![1, 2]);
+        assert_eq!(game.character_count(), 2);
+
+        walk_until_near(&mut game, 3);
+        game.trigger(Action::Combine);
+        game.step(1);
+        assert_eq!(game.controlled_numbers(), vec![1, 2, 3]);
+
+        game.hold(Action::Forward);
+        game.step(60);
+        assert!(game.completed());
+    }
+
+    #[test]
+    fn cannot_leave_without_all_parts() {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[5, 3], [1, 3], [2, 3]], [6, 3]));
+        game.look(EAST);
+        game.hold(Action::Forward);
+        game.step(60);
+
+        assert!(!game.completed());
+        game.step(1);
+        assert!(game.app.world.resource::<Notifications>().contains("leave"));
+    }
+
+    #[test]
+    fn switch_control() {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]));
+        let start = game.position_of(3);
+        game.trigger(Action::ControlThird);
+        game.step(1);
+        assert_eq!(game.controlled_numbers(), vec![3]);
+
+        game.look(-EAST);
+        game.hold(Action::Forward);
+        game.step(10);
+        assert!(game.position_of(3).x < start.x);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::character::{Character, LevelCompletedEvent, PLAYER_RADIUS};
+use crate::loading::FontAssets;
+use crate::map::{CurrentLevel, LevelEntity};
+use crate::palette::CharacterMaterials;
+use crate::replay::ReplayPlayback;
+use crate::savegame::RestoredRun;
+use crate::simulation::GameStopWatch;
+use crate::simulation::{
+    simulation_running, Position, SimulationStage, SimulationSystem, SimulationTick, TICK_SECONDS,
+};
+use crate::storage;
+use crate::GameState;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Bump this whenever the simulation changes in a way that makes old ghosts misleading
+pub const GHOST_VERSION: u32 = 1;
+/// Character positions are stored every this many ticks
+const SAMPLE_TICKS: u64 = 6;
+
+pub struct GhostPlugin;
+
+/// This plugin keeps the personal best run of every level and replays it as translucent ghosts
+/// The best time is shown in the HUD, the results compare a finished run to it.
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(load_best_run)
+                .with_system(spawn_best_time_text),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(spawn_ghosts)
+                .with_system(move_ghosts)
+                .with_system(update_best_time_text),
+        )
+        .add_system_set_to_stage(
+            SimulationStage,
+            SystemSet::new()
+                .with_run_criteria(simulation_running)
+                .with_system(record_ghost.after(SimulationSystem::Gameplay))
+                .with_system(save_best_run.after(record_ghost)),
+        );
+    }
+}
+
+/// Positions of every character over one completed run
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GhostRun {
+    pub version: u32,
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+    pub tracks: Vec<GhostTrack>,
+}
+
+impl GhostRun {
+    /// Whether this run is faster than the best run so far, ties keep the older run
+    fn beats(&self, best: Option<&GhostRun>) -> bool {
+        best.is_none_or(|best| self.ticks < best.ticks)
+    }
+}
+
+/// Positions of one character, sampled every [SAMPLE_TICKS]
+///
+/// The track ends when the character was combined into another one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GhostTrack {
+    pub number: u8,
+    pub positions: Vec<[f32; 3]>,
+}
+
+impl GhostTrack {
+    fn position_at(&self, sample: f32) -> Option<Vec3> {
+        let index = sample.floor() as usize;
+        let from = Vec3::from(*self.positions.get(index)?);
+        let to = self
+            .positions
+            .get(index + 1)
+            .map(|position| Vec3::from(*position))
+            .unwrap_or(from);
+        Some(from.lerp(to, sample.fract()))
+    }
+}
+
+/// The personal best of the current level, if there is one
+pub struct BestRun(pub Option<GhostRun>);
+
+/// The run that is currently played
+#[derive(Default)]
+struct GhostRecorder {
+    run: GhostRun,
+    finished: bool,
+}
+
+#[derive(Component)]
+struct Ghost {
+    track: usize,
+}
+
+#[derive(Component)]
+struct BestTimeText;
+
+fn ghost_file(level: &CurrentLevel) -> String {
+    format!("ghosts/{}.ron", level.0)
+}
+
+fn load_best_run(mut commands: Commands, level: Res<CurrentLevel>) {
+    let best = match storage::load::<GhostRun>(ghost_file(&level)) {
+        Ok(run) if run.version == GHOST_VERSION => Some(run),
+        Ok(_) => None,
+        Err(error) => {
+            if !error.is_not_found() {
+                warn!(
+                    "Failed to load the best run of level {}: {}",
+                    level.0, error
+                );
+            }
+            None
+        }
+    };
+    commands.insert_resource(BestRun(best));
+    commands.insert_resource(GhostRecorder {
+        run: GhostRun {
+            version: GHOST_VERSION,
+            ..default()
+        },
+        finished: false,
+    });
+}
+
+fn spawn_ghosts(
+    mut commands: Commands,
+    best: Res<BestRun>,
+    character_materials: Res<CharacterMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if !best.is_added() {
+        return;
+    }
+    let run = match &best.0 {
+        Some(run) => run,
+        None => return,
+    };
+    let mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: PLAYER_RADIUS,
+        subdivisions: 3,
+    }));
+    for (index, track) in run.tracks.iter().enumerate() {
+        let mut color = character_materials.color(track.number);
+        color.set_a(0.3);
+        let material = materials.add(StandardMaterial {
+            base_color: color,
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        });
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: mesh.clone(),
+                material,
+                transform: Transform::from_translation(track.position_at(0.).unwrap_or_default()),
+                ..default()
+            })
+            .insert(NotShadowCaster)
+            .insert(LevelEntity)
+            .insert(Ghost { track: index });
+    }
+}
+
+fn move_ghosts(
+    mut commands: Commands,
+    best: Res<BestRun>,
+    stop_watch: Res<GameStopWatch>,
+    mut ghosts: Query<(Entity, &Ghost, &mut Transform)>,
+) {
+    let run = match &best.0 {
+        Some(run) => run,
+        None => return,
+    };
+    // the first sample is taken in the first tick
+    let ticks = stop_watch.0.elapsed_secs() / TICK_SECONDS as f32;
+    let sample = ((ticks - 1.) / SAMPLE_TICKS as f32).max(0.);
+    for (entity, ghost, mut transform) in &mut ghosts {
+        match run.tracks[ghost.track].position_at(sample) {
+            Some(position) => transform.translation = position,
+            None => commands.entity(entity).despawn_recursive(),
+        }
+    }
+}
+
+fn record_ghost(
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<GhostRecorder>,
+    characters: Query<(&Character, &Position)>,
+) {
+    if recorder.finished || tick.0 % SAMPLE_TICKS != 1 {
+        return;
+    }
+    for (character, position) in &characters {
+        let number = character.numbers()[0];
+        let track = match recorder
+            .run
+            .tracks
+            .iter_mut()
+            .position(|track| track.number == number)
+        {
+            Some(index) => &mut recorder.run.tracks[index],
+            None => {
+                recorder.run.tracks.push(GhostTrack {
+                    number,
+                    positions: vec![],
+                });
+                recorder.run.tracks.last_mut().unwrap()
+            }
+        };
+        track.positions.push(position.current.to_array());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_best_run(
+    mut events: EventReader<LevelCompletedEvent>,
+    mut recorder: ResMut<GhostRecorder>,
+    mut best: ResMut<BestRun>,
+    level: Res<CurrentLevel>,
+    tick: Res<SimulationTick>,
+    stop_watch: Res<GameStopWatch>,
+    playback: Option<Res<ReplayPlayback>>,
+    restored: Option<Res<RestoredRun>>,
+) {
+    if events.iter().last().is_none() || recorder.finished {
+        return;
+    }
+    recorder.finished = true;
+    // the recording of a restored run starts at the save, not at the start of the level
+    if playback.is_some() || restored.is_some() {
+        return;
+    }
+    recorder.run.ticks = tick.0;
+    recorder.run.elapsed_secs = stop_watch.0.elapsed_secs();
+    if !recorder.run.beats(best.0.as_ref()) {
+        return;
+    }
+    info!("New best time for level {}", level.0);
+    if let Err(error) = storage::save(ghost_file(&level), &recorder.run) {
+        warn!("Failed to save the best run: {}", error);
+    }
+    best.0 = Some(recorder.run.clone());
+}
+
+fn spawn_best_time_text(mut commands: Commands, font_assets: Res<FontAssets>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.),
+                    top: Val::Px(65.),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text {
+                sections: vec![TextSection {
+                    value: "".to_string(),
+                    style: TextStyle {
+                        font: font_assets.fira_sans.clone(),
+                        font_size: 25.0,
+                        color: Color::rgb_u8(34, 32, 52),
+                    },
+                }],
+                alignment: Default::default(),
+            },
+            ..default()
+        })
+        .insert(LevelEntity)
+        .insert(BestTimeText);
+}
+
+/// Shows the best time of the level
+///
+/// The time elapsed so far says nothing about the pace of a run, so the comparison waits for the results.
+fn update_best_time_text(
+    best: Res<BestRun>,
+    added: Query<(), Added<BestTimeText>>,
+    mut texts: Query<&mut Text, With<BestTimeText>>,
+) {
+    if !best.is_changed() && added.is_empty() {
+        return;
+    }
+    let value = best.0.as_ref().map_or(String::new(), |best| {
+        format!("Best {}", format_time(best.elapsed_secs))
+    });
+    for mut text in &mut texts {
+        text.sections[0].value.clone_from(&value);
+    }
+}
+
+/// Minutes, seconds and tenths, like `01:05.3`
+pub fn format_time(seconds: f32) -> String {
+    let minutes = (seconds / 60.).floor();
+    format!("{:0>2}:{:0>4.1}", minutes, seconds % 60.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Action;
+    use crate::savegame::RestoredRun;
+    use crate::solver::Direction;
+    use crate::testing::{maze, yaw, TestGame};
+
+    const CORRIDOR: [&str; 7] = [
+        "#######", "#######", "#######", "#.....#", "#######", "#######", "#######",
+    ];
+
+    /// Completes the corridor and returns whether the run became the best run
+    fn complete_corridor(restored: bool) -> bool {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[5, 3]], [6, 3]));
+        let level = CurrentLevel(format!("ghost-{}", restored));
+        let file = ghost_file(&level);
+        game.app
+            .insert_resource(level)
+            .insert_resource(BestRun(None))
+            .init_resource::<GhostRecorder>()
+            .add_system_to_stage(
+                SimulationStage,
+                save_best_run.after(SimulationSystem::Gameplay),
+            );
+        if restored {
+            game.app.insert_resource(RestoredRun);
+        }
+
+        game.look(yaw(Direction::East));
+        game.hold(Action::Forward);
+        game.step(60);
+        assert!(game.completed());
+        let saved = storage::load_text(&file).is_ok();
+        if saved {
+            storage::remove(&file).unwrap();
+        }
+        assert_eq!(game.app.world.resource::<BestRun>().0.is_some(), saved);
+        saved
+    }
+
+    fn run(ticks: u64) -> GhostRun {
+        GhostRun {
+            version: GHOST_VERSION,
+            ticks,
+            elapsed_secs: ticks as f32 / 60.,
+            tracks: vec![],
+        }
+    }
+
+    #[test]
+    fn only_faster_runs_become_the_best_run() {
+        assert!(run(600).beats(None));
+        assert!(run(300).beats(Some(&run(600))));
+        assert!(!run(600).beats(Some(&run(600))));
+        assert!(!run(900).beats(Some(&run(600))));
+    }
+
+    #[test]
+    fn tracks_are_interpolated_until_they_end() {
+        let track = GhostTrack {
+            number: 1,
+            positions: vec![[0., 0., 0.], [1., 0., 2.], [1., 0., 4.]],
+        };
+        assert_eq!(track.position_at(0.), Some(Vec3::ZERO));
+        assert!(track
+            .position_at(0.5)
+            .unwrap()
+            .abs_diff_eq(Vec3::new(0.5, 0., 1.), 0.001));
+        assert!(track
+            .position_at(1.25)
+            .unwrap()
+            .abs_diff_eq(Vec3::new(1., 0., 2.5), 0.001));
+        // the last sample is held until the track ends
+        assert_eq!(track.position_at(2.5), Some(Vec3::new(1., 0., 4.)));
+        assert_eq!(track.position_at(3.), None);
+    }
+
+    #[test]
+    fn restored_runs_do_not_become_ghosts() {
+        assert!(complete_corridor(false));
+        assert!(!complete_corridor(true));
+    }
+}
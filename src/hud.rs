@@ -0,0 +1,212 @@
+use crate::actions::Action;
+use crate::character::{CamInputState, Character, Controlled};
+use crate::loading::FontAssets;
+use crate::map::{LevelEntity, PIXEL_WORLD_SIZE};
+use crate::palette::CharacterMaterials;
+use crate::simulation::PendingInput;
+use crate::GameState;
+use bevy::prelude::*;
+use std::f32::consts::{FRAC_PI_4, TAU};
+
+/// Arrows for the direction of another character, turning left from straight ahead
+const ARROWS: [char; 8] = ['↑', '↖', '←', '↙', '↓', '↘', '→', '↗'];
+
+pub struct HudPlugin;
+
+/// This plugin shows a panel with all characters, which of them is controlled
+/// and where the separated ones are. Clicking a character switches to it.
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing).with_system(spawn_character_panel),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(fill_character_panel)
+                .with_system(point_to_characters.after(fill_character_panel))
+                .with_system(click_character_row),
+        );
+    }
+}
+
+#[derive(Component)]
+struct CharacterPanel;
+
+/// A row of the panel, for the character containing this number
+#[derive(Component)]
+struct CharacterRow(u8);
+
+#[derive(Component)]
+struct CharacterDirection(u8);
+
+fn spawn_character_panel(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.),
+                    top: Val::Px(10.),
+                    ..default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        })
+        .insert(LevelEntity)
+        .insert(CharacterPanel);
+}
+
+/// Rebuilds the rows whenever characters combine or control switches
+#[allow(clippy::type_complexity)]
+fn fill_character_panel(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    character_materials: Res<CharacterMaterials>,
+    panel: Query<(Entity, Option<&Children>), With<CharacterPanel>>,
+    changed: Query<(), Or<(Changed<Character>, Added<Controlled>)>>,
+    characters: Query<(&Character, Option<&Controlled>)>,
+) {
+    let (panel, rows) = match panel.get_single() {
+        Ok(panel) => panel,
+        Err(_) => return,
+    };
+    if changed.is_empty() && rows.is_some_and(|rows| !rows.is_empty()) {
+        return;
+    }
+    if let Some(rows) = rows {
+        for row in rows {
+            commands.entity(*row).despawn_recursive();
+        }
+    }
+    let mut characters: Vec<_> = characters.iter().collect();
+    characters.sort_by_key(|(character, _)| character.numbers()[0]);
+    commands.entity(panel).with_children(|parent| {
+        for (character, controlled) in characters {
+            let number = character.numbers()[0];
+            let color = character_materials.color(number);
+            let numbers: Vec<String> = character
+                .numbers()
+                .iter()
+                .map(|number| number.to_string())
+                .collect();
+            let background = if controlled.is_some() {
+                Color::rgba(0.45, 0.45, 0.45, 0.8)
+            } else {
+                Color::rgba(0.15, 0.15, 0.15, 0.6)
+            };
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        margin: UiRect::all(Val::Px(3.)),
+                        padding: UiRect::all(Val::Px(5.)),
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: UiColor(background),
+                    ..default()
+                })
+                .insert(CharacterRow(number))
+                .with_children(|row| {
+                    row.spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(20.), Val::Px(20.)),
+                            margin: UiRect::new(Val::Px(0.), Val::Px(8.), Val::Px(0.), Val::Px(0.)),
+                            ..default()
+                        },
+                        color: UiColor(color),
+                        ..default()
+                    });
+                    row.spawn_bundle(TextBundle::from_section(
+                        numbers.join("+"),
+                        TextStyle {
+                            font: font_assets.fira_sans.clone(),
+                            font_size: 30.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                        },
+                    ));
+                    row.spawn_bundle(TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: font_assets.fira_sans.clone(),
+                            font_size: 25.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                        },
+                    ))
+                    .insert(CharacterDirection(number));
+                });
+        }
+    });
+}
+
+/// Shows the rough direction and distance in tiles from the controlled character to the others
+fn point_to_characters(
+    cam_input_state: Res<CamInputState>,
+    characters: Query<(&Character, &Transform, Option<&Controlled>)>,
+    mut directions: Query<(&CharacterDirection, &mut Text)>,
+) {
+    let controlled = match characters
+        .iter()
+        .find(|(_, _, controlled)| controlled.is_some())
+    {
+        Some((_, transform, _)) => transform.translation,
+        None => return,
+    };
+    for (CharacterDirection(number), mut text) in &mut directions {
+        let other = characters
+            .iter()
+            .find(|(character, _, _)| character.numbers().contains(number));
+        let value = match other {
+            Some((_, transform, None)) => {
+                let offset = transform.translation - controlled;
+                let yaw = f32::atan2(-offset.x, -offset.z);
+                format!(
+                    "  {} {:.0}",
+                    direction_arrow(yaw - cam_input_state.yaw),
+                    offset.length() / PIXEL_WORLD_SIZE
+                )
+            }
+            _ => String::new(),
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// The arrow closest to the given yaw relative to the view, where positive is to the left
+fn direction_arrow(relative_yaw: f32) -> char {
+    let eighths = (relative_yaw.rem_euclid(TAU) / FRAC_PI_4).round() as usize;
+    ARROWS[eighths % ARROWS.len()]
+}
+
+fn click_character_row(
+    mut pending: ResMut<PendingInput>,
+    rows: Query<(&CharacterRow, &Interaction), Changed<Interaction>>,
+) {
+    for (CharacterRow(number), interaction) in &rows {
+        if *interaction == Interaction::Clicked {
+            if let Some(action) = Action::control(*number) {
+                pending.0.triggered.insert(action);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    #[test]
+    fn arrows_turn_with_the_view() {
+        assert_eq!(direction_arrow(0.), '↑');
+        assert_eq!(direction_arrow(FRAC_PI_2), '←');
+        assert_eq!(direction_arrow(-FRAC_PI_2), '→');
+        assert_eq!(direction_arrow(PI), '↓');
+        assert_eq!(direction_arrow(3. * TAU - 0.1), '↑');
+        assert_eq!(direction_arrow(-FRAC_PI_4 - 0.1), '↗');
+    }
+}
@@ -1,5 +1,11 @@
+use crate::annotations::Annotation;
+use crate::level_pack::ExportLevelEvent;
+use crate::levels::Levels;
 use crate::loading::FontAssets;
-use crate::menu::ButtonColors;
+use crate::map::{CurrentLevel, LevelEntity};
+use crate::maze_code::CopyMazeCodeEvent;
+use crate::savegame::{load_slot, slot_label, SaveGame, SaveGameEvent, SAVE_SLOTS};
+use crate::widgets::{spawn_label, spawn_widget, ButtonColors, Widget, WidgetEvent, WidgetValue};
 use crate::GameState;
 use bevy::app::AppExit;
 use bevy::prelude::*;
@@ -8,12 +14,13 @@ pub struct InGameMenuPlugin;
 
 impl Plugin for InGameMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(open_menu))
+        app.init_resource::<InGameMenuPage>()
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(open_menu))
             .add_system_set(SystemSet::on_enter(GameState::InGameMenu).with_system(setup_menu))
             .add_system_set(
                 SystemSet::on_update(GameState::InGameMenu)
-                    .with_system(click_continue_button)
-                    .with_system(click_quit_button)
+                    .with_system(show_menu_page)
+                    .with_system(use_menu_widgets)
                     .with_system(close_menu),
             )
             .add_system_set(SystemSet::on_exit(GameState::InGameMenu).with_system(cleanup_menu));
@@ -23,11 +30,33 @@ impl Plugin for InGameMenuPlugin {
 #[derive(Component)]
 struct InGameMenuElement;
 
+/// The container holding the buttons of the current page
 #[derive(Component)]
-struct ContinueButton;
+struct InGameMenuRoot;
 
-#[derive(Component)]
-struct QuitButton;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum InGameMenuPage {
+    #[default]
+    Main,
+    Save,
+    Load,
+}
+
+#[derive(Component, Clone, Copy)]
+enum InGameMenuButton {
+    Continue,
+    Page(InGameMenuPage),
+    SaveSlot(usize),
+    LoadSlot(usize),
+    /// Starts the current level from the beginning
+    Restart,
+    /// Writes the current level into a level pack
+    Export,
+    /// Copies the code of the current level to share it in a chat
+    CopyCode,
+    MainMenu,
+    Quit,
+}
 
 fn open_menu(
     mut states: ResMut<State<GameState>>,
@@ -40,13 +69,26 @@ fn open_menu(
             window.set_cursor_lock_mode(false);
             window.set_cursor_visibility(true);
         }
-        states.push(GameState::InGameMenu).unwrap();
+        if let Err(error) = states.push(GameState::InGameMenu) {
+            warn!("Failed to pause the game: {:?}", error);
+        }
     }
 }
 
-fn close_menu(mut states: ResMut<State<GameState>>, mut input: ResMut<Input<KeyCode>>) {
+/// Escape goes back to the main page first and then to the game
+fn close_menu(
+    mut states: ResMut<State<GameState>>,
+    mut page: ResMut<InGameMenuPage>,
+    mut input: ResMut<Input<KeyCode>>,
+) {
     if input.just_pressed(KeyCode::Escape) {
-        states.pop().unwrap();
+        if *page == InGameMenuPage::Main {
+            if let Err(error) = states.pop() {
+                warn!("Failed to continue the game: {:?}", error);
+            }
+        } else {
+            *page = InGameMenuPage::Main;
+        }
         input.clear();
     }
 }
@@ -55,111 +97,191 @@ fn setup_menu(
     mut commands: Commands,
     font_assets: Res<FontAssets>,
     button_colors: Res<ButtonColors>,
+    mut page: ResMut<InGameMenuPage>,
+    annotations: Query<&Annotation>,
 ) {
-    commands
-        .spawn_bundle(ButtonBundle {
-            style: Style {
-                size: Size::new(Val::Px(120.0), Val::Px(50.0)),
-                margin: UiRect::all(Val::Auto),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..Default::default()
-            },
-            color: button_colors.normal,
-            ..Default::default()
-        })
-        .insert(QuitButton)
-        .insert(InGameMenuElement)
-        .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text {
-                    sections: vec![TextSection {
-                        value: "Quit".to_string(),
-                        style: TextStyle {
-                            font: font_assets.fira_sans.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    }],
-                    alignment: Default::default(),
+    *page = InGameMenuPage::Main;
+    let mut notes: Vec<&Annotation> = annotations.iter().collect();
+    notes.sort_by_key(|note| note.owner);
+    if !notes.is_empty() {
+        let style = TextStyle {
+            font: font_assets.fira_sans.clone(),
+            font_size: 25.0,
+            color: Color::rgb(0.9, 0.9, 0.9),
+        };
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(10.),
+                        top: Val::Px(10.),
+                        ..Default::default()
+                    },
+                    flex_direction: FlexDirection::ColumnReverse,
+                    padding: UiRect::all(Val::Px(5.)),
+                    ..Default::default()
                 },
+                color: button_colors.normal,
                 ..Default::default()
+            })
+            .insert(InGameMenuElement)
+            .with_children(|parent| {
+                spawn_label(parent, &style, "Notes");
+                let note_style = TextStyle {
+                    font_size: 20.0,
+                    ..style.clone()
+                };
+                for note in notes {
+                    spawn_label(
+                        parent,
+                        &note_style,
+                        format!("{}: {}", note.owner, note.text),
+                    );
+                }
             });
-        });
+    }
     commands
-        .spawn_bundle(ButtonBundle {
+        .spawn_bundle(NodeBundle {
             style: Style {
-                size: Size::new(Val::Px(120.0), Val::Px(50.0)),
                 margin: UiRect::all(Val::Auto),
-                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::ColumnReverse,
                 align_items: AlignItems::Center,
                 ..Default::default()
             },
-            color: button_colors.normal,
+            color: UiColor(Color::NONE),
             ..Default::default()
         })
-        .insert(ContinueButton)
-        .insert(InGameMenuElement)
-        .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text {
-                    sections: vec![TextSection {
-                        value: "Continue".to_string(),
-                        style: TextStyle {
-                            font: font_assets.fira_sans.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    }],
-                    alignment: Default::default(),
-                },
-                ..Default::default()
-            });
-        });
+        .insert(InGameMenuRoot)
+        .insert(InGameMenuElement);
 }
 
-fn click_quit_button(
+/// Fills the menu with the buttons of the current page
+fn show_menu_page(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
     button_colors: Res<ButtonColors>,
-    mut exit_events: EventWriter<AppExit>,
-    mut interaction_query: Query<
-        (&Interaction, &mut UiColor),
-        (Changed<Interaction>, With<QuitButton>),
-    >,
+    page: Res<InGameMenuPage>,
+    root: Query<(Entity, Option<&Children>), With<InGameMenuRoot>>,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
-        match *interaction {
-            Interaction::Clicked => {
-                exit_events.send(AppExit);
-            }
-            Interaction::Hovered => {
-                *color = button_colors.hovered;
-            }
-            Interaction::None => {
-                *color = button_colors.normal;
-            }
+    let (root, children) = match root.get_single() {
+        Ok(root) => root,
+        Err(_) => return,
+    };
+    if !page.is_changed() && children.is_some_and(|children| !children.is_empty()) {
+        return;
+    }
+    if let Some(children) = children {
+        for child in children {
+            commands.entity(*child).despawn_recursive();
         }
     }
+    let buttons: Vec<(String, InGameMenuButton)> = match *page {
+        InGameMenuPage::Main => vec![
+            ("Continue".to_owned(), InGameMenuButton::Continue),
+            (
+                "Save game".to_owned(),
+                InGameMenuButton::Page(InGameMenuPage::Save),
+            ),
+            (
+                "Load game".to_owned(),
+                InGameMenuButton::Page(InGameMenuPage::Load),
+            ),
+            ("Restart level".to_owned(), InGameMenuButton::Restart),
+            ("Export level".to_owned(), InGameMenuButton::Export),
+            ("Copy maze code".to_owned(), InGameMenuButton::CopyCode),
+            ("Main menu".to_owned(), InGameMenuButton::MainMenu),
+            ("Quit".to_owned(), InGameMenuButton::Quit),
+        ],
+        InGameMenuPage::Save | InGameMenuPage::Load => (1..=SAVE_SLOTS)
+            .map(|slot| {
+                let label = slot_label(slot, SaveGame::load(slot).as_ref());
+                let button = if *page == InGameMenuPage::Save {
+                    InGameMenuButton::SaveSlot(slot)
+                } else {
+                    InGameMenuButton::LoadSlot(slot)
+                };
+                (label, button)
+            })
+            .chain([(
+                "Back".to_owned(),
+                InGameMenuButton::Page(InGameMenuPage::Main),
+            )])
+            .collect(),
+    };
+    let text_style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    commands.entity(root).with_children(|parent| {
+        for (label, button) in buttons {
+            spawn_widget(parent, &text_style, &button_colors, Widget::button(label)).insert(button);
+        }
+    });
 }
 
-fn click_continue_button(
-    button_colors: Res<ButtonColors>,
+#[allow(clippy::too_many_arguments)]
+fn use_menu_widgets(
+    mut commands: Commands,
+    mut events: EventReader<WidgetEvent>,
     mut state: ResMut<State<GameState>>,
-    mut interaction_query: Query<
-        (&Interaction, &mut UiColor),
-        (Changed<Interaction>, With<ContinueButton>),
-    >,
+    mut page: ResMut<InGameMenuPage>,
+    levels: Res<Levels>,
+    mut level: ResMut<CurrentLevel>,
+    mut save_events: EventWriter<SaveGameEvent>,
+    mut export_events: EventWriter<ExportLevelEvent>,
+    mut code_events: EventWriter<CopyMazeCodeEvent>,
+    mut exit_events: EventWriter<AppExit>,
+    level_entities: Query<Entity, With<LevelEntity>>,
+    buttons: Query<&InGameMenuButton>,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
-        match *interaction {
-            Interaction::Clicked => {
-                state.pop().unwrap();
+    for WidgetEvent { entity, value } in events.iter() {
+        let button = match (buttons.get(*entity), value) {
+            (Ok(button), WidgetValue::Pressed) => button,
+            _ => continue,
+        };
+        match button {
+            InGameMenuButton::Continue => {
+                if let Err(error) = state.pop() {
+                    warn!("Failed to continue the game: {:?}", error);
+                }
+                // the menu is gone, so later events in this frame no longer apply
+                break;
+            }
+            InGameMenuButton::Page(next) => *page = *next,
+            InGameMenuButton::SaveSlot(slot) => {
+                save_events.send(SaveGameEvent(*slot));
+                *page = InGameMenuPage::Main;
             }
-            Interaction::Hovered => {
-                *color = button_colors.hovered;
+            InGameMenuButton::LoadSlot(slot) => {
+                if load_slot(&mut commands, *slot, &levels, &mut level) {
+                    for entity in &level_entities {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                    if let Err(error) = state.replace(GameState::Playing) {
+                        warn!("Failed to load save slot {}: {:?}", slot, error);
+                    }
+                    break;
+                }
             }
-            Interaction::None => {
-                *color = button_colors.normal;
+            InGameMenuButton::Export => export_events.send(ExportLevelEvent),
+            InGameMenuButton::CopyCode => code_events.send(CopyMazeCodeEvent),
+            InGameMenuButton::Restart | InGameMenuButton::MainMenu => {
+                // the paused level is left for good, so nothing of it may survive
+                for entity in &level_entities {
+                    commands.entity(entity).despawn_recursive();
+                }
+                let next = match button {
+                    InGameMenuButton::Restart => GameState::Playing,
+                    _ => GameState::Menu,
+                };
+                if let Err(error) = state.replace(next) {
+                    warn!("Failed to leave the paused level: {:?}", error);
+                }
+                break;
             }
+            InGameMenuButton::Quit => exit_events.send(AppExit),
         }
     }
 }
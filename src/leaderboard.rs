@@ -0,0 +1,600 @@
+use crate::ghost::format_time;
+use crate::levels::Levels;
+use crate::loading::FontAssets;
+use crate::menu::MenuElement;
+use crate::replay::{ReplayPlayback, SavedReplay};
+use crate::results::LevelResult;
+use crate::storage::{self, StorageError};
+use crate::widgets::{spawn_widget, ButtonColors, Widget, WidgetEvent, WidgetValue};
+use crate::GameState;
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Bump this whenever the format of [Leaderboards] changes
+pub const LEADERBOARD_VERSION: u32 = 2;
+/// Bump this whenever the format of [Profiles] changes
+pub const PROFILES_VERSION: u32 = 1;
+/// Number of runs kept per level
+pub const LEADERBOARD_SIZE: usize = 10;
+const LEADERBOARD_FILE: &str = "leaderboard.ron";
+const PROFILES_FILE: &str = "profiles.ron";
+const MAX_NAME_LENGTH: usize = 20;
+
+pub struct LeaderboardPlugin;
+
+/// This plugin keeps the fastest runs of every level on this machine
+/// Runs are entered under the active player profile, so several people can compete on one machine.
+/// The leaderboard is shown next to the results of a level and can be opened from the menu.
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_save_file(
+            LEADERBOARD_FILE,
+            LEADERBOARD_VERSION,
+            Leaderboards::migrate,
+        ))
+        .insert_resource(load_save_file(
+            PROFILES_FILE,
+            PROFILES_VERSION,
+            Profiles::migrate,
+        ))
+        .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(record_run))
+        .add_system_set(
+            SystemSet::on_exit(GameState::LevelComplete).with_system(cleanup_leaderboard),
+        )
+        .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(spawn_profile_bar))
+        .add_system_set(
+            SystemSet::on_update(GameState::Menu)
+                .with_system(use_profile_widgets)
+                .with_system(update_profile_list),
+        );
+    }
+}
+
+/// The fastest runs of every level, fastest first
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Leaderboards {
+    pub version: u32,
+    pub levels: BTreeMap<String, Vec<LeaderboardEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+    /// Day of the run as `YYYY-MM-DD` in UTC
+    pub date: String,
+    /// Replay file of the run, relative to the save directory
+    ///
+    /// Runs continued from a save game have no replay, since their start is missing,
+    /// and neither do runs whose replay could not be written.
+    pub replay: Option<String>,
+}
+
+/// [Leaderboards] of version 1, whose runs always had a replay
+#[derive(Deserialize)]
+struct LeaderboardsV1 {
+    levels: BTreeMap<String, Vec<LeaderboardEntryV1>>,
+}
+
+#[derive(Deserialize)]
+struct LeaderboardEntryV1 {
+    name: String,
+    ticks: u64,
+    elapsed_secs: f32,
+    date: String,
+    replay: String,
+}
+
+impl Default for Leaderboards {
+    fn default() -> Self {
+        Leaderboards {
+            version: LEADERBOARD_VERSION,
+            levels: BTreeMap::new(),
+        }
+    }
+}
+
+impl Leaderboards {
+    pub fn entries(&self, level: &str) -> &[LeaderboardEntry] {
+        self.levels.get(level).map_or(&[], |entries| entries)
+    }
+
+    /// Adds the run if it is fast enough and returns its rank, starting at 0
+    ///
+    /// Older runs stay in front of equally fast new ones.
+    pub fn insert(&mut self, level: &str, entry: LeaderboardEntry) -> Option<usize> {
+        let entries = self.levels.entry(level.to_owned()).or_default();
+        let rank = entries
+            .iter()
+            .position(|other| entry.ticks < other.ticks)
+            .unwrap_or(entries.len());
+        if rank >= LEADERBOARD_SIZE {
+            return None;
+        }
+        entries.insert(rank, entry);
+        entries.truncate(LEADERBOARD_SIZE);
+        Some(rank)
+    }
+
+    fn migrate(version: u32, ron: &str) -> Result<Self, StorageError> {
+        match version {
+            1 => {
+                let old: LeaderboardsV1 = ron::from_str(ron)?;
+                let levels = old
+                    .levels
+                    .into_iter()
+                    .map(|(level, entries)| {
+                        let entries = entries
+                            .into_iter()
+                            .map(|entry| LeaderboardEntry {
+                                name: entry.name,
+                                ticks: entry.ticks,
+                                elapsed_secs: entry.elapsed_secs,
+                                date: entry.date,
+                                replay: Some(entry.replay),
+                            })
+                            .collect();
+                        (level, entries)
+                    })
+                    .collect();
+                Ok(Leaderboards {
+                    version: LEADERBOARD_VERSION,
+                    levels,
+                })
+            }
+            _ => Err(StorageError::Outdated(version)),
+        }
+    }
+}
+
+/// Names of the people playing on this machine
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Profiles {
+    pub version: u32,
+    pub names: Vec<String>,
+    pub active: usize,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Profiles {
+            version: PROFILES_VERSION,
+            names: vec!["Player".to_owned()],
+            active: 0,
+        }
+    }
+}
+
+impl Profiles {
+    pub fn active_name(&self) -> &str {
+        self.names.get(self.active).map_or("Player", |name| name)
+    }
+
+    /// Switches to the profile with the given name, creating it if needed
+    pub fn activate(&mut self, name: &str) {
+        self.active = match self.names.iter().position(|other| other == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_owned());
+                self.names.len() - 1
+            }
+        };
+    }
+
+    /// Converts profiles of older versions, once there are any
+    fn migrate(version: u32, _ron: &str) -> Result<Self, StorageError> {
+        Err(StorageError::Outdated(version))
+    }
+}
+
+/// Reads one of the files of this module, converting versions older than `current` with `migrate`
+///
+/// Falls back to an empty file. Files of newer versions are kept on disk, see [save_file].
+fn load_save_file<T: DeserializeOwned + Default>(
+    file: &str,
+    current: u32,
+    migrate: fn(u32, &str) -> Result<T, StorageError>,
+) -> T {
+    match storage::load_versioned(file, current, migrate) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            if !error.is_not_found() {
+                warn!("Failed to load {}: {}", file, error);
+            }
+            T::default()
+        }
+    }
+}
+
+fn save_file<T: Serialize>(file: &str, value: &T, current: u32) {
+    if let Err(error) = storage::save_versioned(file, value, current) {
+        warn!("Failed to save {}: {}", file, error);
+    }
+}
+
+/// Today as `YYYY-MM-DD` in UTC
+pub fn today() -> String {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        format_date(seconds)
+    }
+    // there is no clock without a browser API, and nothing is saved on the web anyway
+    #[cfg(target_arch = "wasm32")]
+    {
+        String::new()
+    }
+}
+
+/// Formats seconds since the unix epoch as a date in the proleptic Gregorian calendar
+fn format_date(unix_seconds: u64) -> String {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (unix_seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[derive(Component)]
+struct LeaderboardPanel;
+
+#[derive(Component, Clone, Copy)]
+enum ProfileWidget {
+    Switch,
+    /// Creates a profile with the entered name
+    New,
+    Leaderboard,
+}
+
+fn record_run(
+    mut commands: Commands,
+    result: Res<LevelResult>,
+    playback: Option<Res<ReplayPlayback>>,
+    saved_replay: Option<Res<SavedReplay>>,
+    profiles: Res<Profiles>,
+    mut leaderboards: ResMut<Leaderboards>,
+    font_assets: Res<FontAssets>,
+) {
+    let mut rank = None;
+    // a replay is the same run again
+    if playback.is_none() {
+        rank = leaderboards.insert(
+            &result.level,
+            LeaderboardEntry {
+                name: profiles.active_name().to_owned(),
+                ticks: result.ticks,
+                elapsed_secs: result.elapsed_secs,
+                date: today(),
+                replay: saved_replay.map(|saved| saved.0.clone()),
+            },
+        );
+        if rank.is_some() {
+            save_file(LEADERBOARD_FILE, &*leaderboards, LEADERBOARD_VERSION);
+        }
+    }
+    // next to the results, which are centered as well
+    let position = Style {
+        margin: UiRect::all(Val::Auto),
+        ..default()
+    };
+    let panel = spawn_leaderboard_panel(
+        &mut commands,
+        &font_assets,
+        &leaderboards,
+        &[&result.level],
+        rank,
+        position,
+    );
+    commands.entity(panel).insert(LeaderboardPanel);
+}
+
+/// Shows the leaderboards of the given levels, highlighting the entry at the given rank
+fn spawn_leaderboard_panel(
+    commands: &mut Commands,
+    font_assets: &FontAssets,
+    leaderboards: &Leaderboards,
+    levels: &[&str],
+    highlight: Option<usize>,
+    position: Style,
+) -> Entity {
+    let style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 22.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                padding: UiRect::all(Val::Px(10.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                ..position
+            },
+            color: UiColor(Color::rgba(0.1, 0.1, 0.1, 0.85)),
+            ..default()
+        })
+        .with_children(|parent| {
+            for level in levels {
+                parent.spawn_bundle(TextBundle::from_section(
+                    format!("Level {}", level),
+                    TextStyle {
+                        font_size: 30.0,
+                        ..style.clone()
+                    },
+                ));
+                let entries = leaderboards.entries(level);
+                if entries.is_empty() {
+                    parent.spawn_bundle(TextBundle::from_section("No runs yet", style.clone()));
+                }
+                for (rank, entry) in entries.iter().enumerate() {
+                    let color = if Some(rank) == highlight {
+                        Color::rgb(1., 0.85, 0.3)
+                    } else {
+                        style.color
+                    };
+                    parent.spawn_bundle(TextBundle::from_section(
+                        format!(
+                            "{:>2}. {}  {}  {}",
+                            rank + 1,
+                            entry.name,
+                            format_time(entry.elapsed_secs),
+                            entry.date
+                        ),
+                        TextStyle {
+                            color,
+                            ..style.clone()
+                        },
+                    ));
+                }
+            }
+        })
+        .id()
+}
+
+fn cleanup_leaderboard(mut commands: Commands, panels: Query<Entity, With<LeaderboardPanel>>) {
+    for entity in &panels {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn spawn_profile_bar(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    button_colors: Res<ButtonColors>,
+    profiles: Res<Profiles>,
+) {
+    let style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 25.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.),
+                    top: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        })
+        .insert(MenuElement)
+        .with_children(|parent| {
+            for (widget, button) in [
+                (profile_list(&profiles), ProfileWidget::Switch),
+                (
+                    Widget::text_input("New player", MAX_NAME_LENGTH),
+                    ProfileWidget::New,
+                ),
+                (Widget::button("Leaderboard"), ProfileWidget::Leaderboard),
+            ] {
+                spawn_widget(parent, &style, &button_colors, widget)
+                    .insert(button)
+                    .insert(Style {
+                        margin: UiRect::all(Val::Px(5.)),
+                        padding: UiRect::all(Val::Px(4.)),
+                        align_items: AlignItems::Center,
+                        ..default()
+                    });
+            }
+        });
+}
+
+fn profile_list(profiles: &Profiles) -> Widget {
+    Widget::list("Player", profiles.names.clone(), profiles.active)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn use_profile_widgets(
+    mut commands: Commands,
+    mut events: EventReader<WidgetEvent>,
+    font_assets: Res<FontAssets>,
+    leaderboards: Res<Leaderboards>,
+    levels: Res<Levels>,
+    mut profiles: ResMut<Profiles>,
+    panels: Query<Entity, With<LeaderboardPanel>>,
+    widgets: Query<&ProfileWidget>,
+) {
+    for WidgetEvent { entity, value } in events.iter() {
+        let widget = match widgets.get(*entity) {
+            Ok(widget) => widget,
+            Err(_) => continue,
+        };
+        match (widget, value) {
+            (ProfileWidget::Switch, WidgetValue::Selected(index)) => {
+                profiles.active = *index;
+                save_file(PROFILES_FILE, &*profiles, PROFILES_VERSION);
+            }
+            (ProfileWidget::New, WidgetValue::Submitted(name)) => {
+                let name = name.trim();
+                if !name.is_empty() {
+                    profiles.activate(name);
+                    save_file(PROFILES_FILE, &*profiles, PROFILES_VERSION);
+                }
+            }
+            (ProfileWidget::Leaderboard, WidgetValue::Pressed) => {
+                if panels.is_empty() {
+                    let panel = spawn_leaderboard_panel(
+                        &mut commands,
+                        &font_assets,
+                        &leaderboards,
+                        &levels.names().collect::<Vec<_>>(),
+                        None,
+                        Style {
+                            position_type: PositionType::Absolute,
+                            position: UiRect {
+                                right: Val::Px(10.),
+                                top: Val::Px(10.),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                    );
+                    commands
+                        .entity(panel)
+                        .insert(LeaderboardPanel)
+                        .insert(MenuElement);
+                } else {
+                    for panel in &panels {
+                        commands.entity(panel).despawn_recursive();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Keeps the player list up to date with new and switched profiles
+fn update_profile_list(profiles: Res<Profiles>, mut widgets: Query<(&mut Widget, &ProfileWidget)>) {
+    if !profiles.is_changed() {
+        return;
+    }
+    for (mut widget, profile_widget) in &mut widgets {
+        if let ProfileWidget::Switch = profile_widget {
+            *widget = profile_list(&profiles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, ticks: u64) -> LeaderboardEntry {
+        LeaderboardEntry {
+            name: name.to_owned(),
+            ticks,
+            elapsed_secs: ticks as f32 / 60.,
+            date: "2022-08-20".to_owned(),
+            replay: Some(format!("replays/1-{}.ron", ticks)),
+        }
+    }
+
+    #[test]
+    fn leaderboard_keeps_the_fastest_runs_in_order() {
+        let mut leaderboards = Leaderboards::default();
+        assert_eq!(leaderboards.insert("1", entry("Ada", 600)), Some(0));
+        assert_eq!(leaderboards.insert("1", entry("Bob", 500)), Some(0));
+        assert_eq!(leaderboards.insert("1", entry("Cy", 600)), Some(2));
+        let names: Vec<&str> = leaderboards
+            .entries("1")
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Bob", "Ada", "Cy"]);
+
+        for ticks in 0..LEADERBOARD_SIZE as u64 {
+            leaderboards.insert("1", entry("Fast", ticks));
+        }
+        assert_eq!(leaderboards.entries("1").len(), LEADERBOARD_SIZE);
+        assert_eq!(leaderboards.insert("1", entry("Slow", 1000)), None);
+        assert!(leaderboards.entries("2").is_empty());
+    }
+
+    #[test]
+    fn profiles_are_created_once() {
+        let mut profiles = Profiles::default();
+        profiles.activate("Ada");
+        profiles.activate("Bob");
+        profiles.activate("Ada");
+        assert_eq!(profiles.names, vec!["Player", "Ada", "Bob"]);
+        assert_eq!(profiles.active_name(), "Ada");
+        // switching goes through the player list of the menu
+        assert_eq!(
+            profile_list(&profiles).step(true),
+            Some(WidgetValue::Selected(2))
+        );
+        profiles.active = 2;
+        assert_eq!(
+            profile_list(&profiles).step(true),
+            Some(WidgetValue::Selected(0))
+        );
+    }
+
+    #[test]
+    fn leaderboards_of_version_1_keep_their_replays() {
+        let ron = r#"(version: 1, levels: {"1": [(name: "Blub", ticks: 600, elapsed_secs: 10., date: "2022-08-01", replay: "replays/1-7.ron")]})"#;
+        let leaderboards: Leaderboards =
+            storage::parse_versioned(ron, LEADERBOARD_VERSION, Leaderboards::migrate).unwrap();
+        assert_eq!(leaderboards.version, LEADERBOARD_VERSION);
+        assert_eq!(
+            leaderboards.entries("1")[0].replay.as_deref(),
+            Some("replays/1-7.ron")
+        );
+
+        // the profiles have their own version, which did not change
+        let ron = r#"(version: 1, names: ["Blub"], active: 0)"#;
+        let profiles: Profiles =
+            storage::parse_versioned(ron, PROFILES_VERSION, Profiles::migrate).unwrap();
+        assert_eq!(profiles.active_name(), "Blub");
+    }
+
+    #[test]
+    fn files_of_newer_versions_are_kept() {
+        let newer = Leaderboards {
+            version: LEADERBOARD_VERSION + 1,
+            ..default()
+        };
+        // tests have their own temporary data directory
+        storage::save(LEADERBOARD_FILE, &newer).unwrap();
+        let loaded =
+            storage::load_versioned(LEADERBOARD_FILE, LEADERBOARD_VERSION, Leaderboards::migrate);
+        let saved = storage::save_versioned(
+            LEADERBOARD_FILE,
+            &Leaderboards::default(),
+            LEADERBOARD_VERSION,
+        );
+        let kept = storage::load::<Leaderboards>(LEADERBOARD_FILE);
+        storage::remove(LEADERBOARD_FILE).unwrap();
+        assert!(matches!(loaded, Err(StorageError::Newer(_))));
+        assert!(matches!(saved, Err(StorageError::Newer(_))));
+        assert_eq!(kept.unwrap(), newer);
+    }
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_660_953_600 + 86_399), "2022-08-20");
+    }
+}
@@ -0,0 +1,373 @@
+use crate::leaderboard::Profiles;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::levels::USER_LEVELS;
+use crate::levels::{grid, maze_from_grid, LevelError, LevelMaterials, Levels};
+use crate::loading::LabyrinthLevel;
+use crate::map::{CurrentLevel, Maze};
+use crate::notifications::{Notification, NotificationEvent};
+use crate::storage;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::FileAssetIo;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::texture::{CompressedImageFormats, ImageType};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Bump this whenever the format of [PackFile] changes
+pub const PACK_VERSION: u32 = 1;
+const PACK_EXTENSION: &str = "blubpack";
+/// Directory of the shipped level packs inside the assets
+#[cfg(not(target_arch = "wasm32"))]
+const PACKS: &str = "packs";
+/// Directory for exported levels, inside the data directory
+const EXPORTS: &str = "exports";
+
+pub struct LevelPackPlugin;
+
+/// This plugin loads level packs and exports the current level as one
+/// A level pack is a single RON file ending in `.blubpack`, holding a [PackManifest],
+/// its levels as rows of `#` and `.` and optional textures as base64 encoded PNGs.
+/// Packs in `assets/packs` and in the `levels` directory of the player are loaded at start.
+impl Plugin for LevelPackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<LevelPack>()
+            .init_asset_loader::<LevelPackLoader>()
+            .add_event::<ExportLevelEvent>()
+            .add_startup_system(load_packs)
+            .add_system(add_loaded_packs)
+            .add_system(export_level);
+    }
+}
+
+/// Writes the current level into a level pack in the exports directory
+pub struct ExportLevelEvent;
+
+/// A level pack as it is written to disk
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PackFile {
+    pub version: u32,
+    pub manifest: PackManifest,
+    pub levels: Vec<PackLevel>,
+    #[serde(default)]
+    pub textures: PackTextures,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PackManifest {
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PackLevel {
+    /// Name of the level in the level select, which has to be unique
+    pub name: String,
+    /// Rows of `#` (wall) and `.` (floor)
+    pub grid: Vec<String>,
+    /// Everything of the level file except for the image
+    pub level: LabyrinthLevel,
+}
+
+/// Base64 encoded PNGs replacing the textures of walls and floor in all levels of the pack
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackTextures {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ground: Option<String>,
+}
+
+/// The part of every version of a pack needed to read the rest
+#[derive(Deserialize)]
+struct PackHeader {
+    version: u32,
+}
+
+/// A loaded level pack with the mazes of all valid levels
+#[derive(TypeUuid)]
+#[uuid = "4b7f3c1e-9a2d-4e8b-b6f5-2d1c0a9e8f73"]
+pub struct LevelPack {
+    pub file: PackFile,
+    pub levels: Vec<(String, Maze)>,
+    pub wall: Option<Handle<Image>>,
+    pub ground: Option<Handle<Image>>,
+}
+
+/// Handles of the packs found at start, which would be unloaded otherwise
+struct LoadedPacks(Vec<Handle<LevelPack>>);
+
+#[derive(Default)]
+struct LevelPackLoader;
+
+impl AssetLoader for LevelPackLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let file = read_pack(bytes)?;
+            let mut levels = vec![];
+            for level in &file.levels {
+                match check_name(&level.name)
+                    .and_then(|_| maze_from_grid(&level.grid, level.level.clone()))
+                {
+                    Ok(maze) => levels.push((level.name.clone(), maze)),
+                    Err(error) => warn!(
+                        "Skipping level {} of {:?}: {}",
+                        level.name,
+                        load_context.path(),
+                        error
+                    ),
+                }
+            }
+            let mut texture = |label: &str, png: &Option<String>| {
+                let image = match png.as_deref().map(decode_texture)? {
+                    Ok(image) => image,
+                    Err(error) => {
+                        warn!(
+                            "Ignoring the {} texture of {:?}: {}",
+                            label,
+                            load_context.path(),
+                            error
+                        );
+                        return None;
+                    }
+                };
+                Some(load_context.set_labeled_asset(label, LoadedAsset::new(image)))
+            };
+            let wall = texture("wall", &file.textures.wall);
+            let ground = texture("ground", &file.textures.ground);
+            load_context.set_default_asset(LoadedAsset::new(LevelPack {
+                file,
+                levels,
+                wall,
+                ground,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[PACK_EXTENSION]
+    }
+}
+
+/// Reads a pack of any known version
+pub fn read_pack(bytes: &[u8]) -> Result<PackFile, LevelError> {
+    let header: PackHeader = ron::de::from_bytes(bytes)?;
+    match header.version {
+        PACK_VERSION => Ok(ron::de::from_bytes(bytes)?),
+        version => Err(LevelError::Invalid(format!(
+            "the pack has version {}, but only {} is supported",
+            version, PACK_VERSION
+        ))),
+    }
+}
+
+/// Level names end up in file names, like the one of the markers of the level
+fn check_name(name: &str) -> Result<(), LevelError> {
+    let allowed = |character: char| character.is_alphanumeric() || " -_".contains(character);
+    if name.trim().is_empty() || !name.chars().all(allowed) {
+        return Err(LevelError::Invalid(format!(
+            "the name {:?} may only contain letters, digits, spaces, - and _",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn decode_texture(png: &str) -> Result<Image, LevelError> {
+    let bytes = base64::decode(png.trim())
+        .map_err(|error| LevelError::Invalid(format!("invalid base64: {}", error)))?;
+    Ok(Image::from_buffer(
+        &bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+    )?)
+}
+
+/// A pack with just the given level
+pub fn export_pack(name: &str, maze: &Maze, textures: PackTextures, author: &str) -> PackFile {
+    PackFile {
+        version: PACK_VERSION,
+        manifest: PackManifest {
+            name: format!("Level {}", name),
+            author: author.to_owned(),
+            description: String::new(),
+        },
+        levels: vec![PackLevel {
+            name: name.to_owned(),
+            grid: grid(maze),
+            level: LabyrinthLevel {
+                image: None,
+                ..maze.level.clone()
+            },
+        }],
+        textures,
+    }
+}
+
+/// Level packs in the assets and the level directory of the player
+fn pack_files() -> Vec<PathBuf> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let directories = [
+            Some(FileAssetIo::get_base_path().join("assets").join(PACKS)),
+            storage::data_dir().map(|dir| dir.join(USER_LEVELS)),
+        ];
+        directories
+            .into_iter()
+            .flatten()
+            // both directories are optional
+            .filter_map(|directory| std::fs::read_dir(directory).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == PACK_EXTENSION))
+            .collect()
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        vec![]
+    }
+}
+
+fn load_packs(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = pack_files()
+        .into_iter()
+        .map(|path| asset_server.load(path))
+        .collect();
+    commands.insert_resource(LoadedPacks(handles));
+}
+
+fn add_loaded_packs(
+    mut events: EventReader<AssetEvent<LevelPack>>,
+    packs: Res<Assets<LevelPack>>,
+    loaded: Res<LoadedPacks>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut levels: ResMut<Levels>,
+) {
+    for event in events.iter() {
+        let pack = match event {
+            AssetEvent::Created { handle } if loaded.0.contains(handle) => {
+                match packs.get(handle) {
+                    Some(pack) => pack,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let mut material = |image: &Option<Handle<Image>>| {
+            image.clone().map(|image| {
+                materials.add(StandardMaterial {
+                    base_color_texture: Some(image),
+                    ..default()
+                })
+            })
+        };
+        let level_materials = LevelMaterials {
+            wall: material(&pack.wall),
+            ground: material(&pack.ground),
+        };
+        for (name, maze) in &pack.levels {
+            levels.add_with_textures(
+                name,
+                maze.clone(),
+                pack.file.textures.clone(),
+                level_materials.clone(),
+            );
+        }
+        info!(
+            "Added {} levels of the pack {}",
+            pack.levels.len(),
+            pack.file.manifest.name
+        );
+    }
+}
+
+fn export_level(
+    mut events: EventReader<ExportLevelEvent>,
+    level: Res<CurrentLevel>,
+    maze: Option<Res<Maze>>,
+    levels: Res<Levels>,
+    profiles: Res<Profiles>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+    let maze = match maze {
+        Some(maze) => maze,
+        None => return,
+    };
+    let pack = export_pack(
+        &level.0,
+        &maze,
+        levels.textures(&level.0),
+        profiles.active_name(),
+    );
+    let file = format!("{}/{}.{}", EXPORTS, level.0, PACK_EXTENSION);
+    let text = match storage::save(file, &pack) {
+        Ok(path) => {
+            info!("Exported level {} to {:?}", level.0, path);
+            format!("Exported level {}", level.0)
+        }
+        Err(error) => {
+            warn!("Failed to export level {}: {}", level.0, error);
+            format!("Failed to export level {}", level.0)
+        }
+    };
+    notifications.send(NotificationEvent::Show(Notification::toast(
+        "export", text, 3.,
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::maze;
+
+    #[test]
+    fn exported_levels_can_be_read_again() {
+        let rows = ["#####", "#...#", "#.#.#", "#...#", "#####"];
+        let maze = maze(&rows, &[[1, 1], [3, 3]], [4, 2]);
+        let textures = PackTextures {
+            wall: Some("aGVsbG8=".to_owned()),
+            ground: None,
+        };
+        let pack = export_pack("custom", &maze, textures.clone(), "Blub");
+        let ron = ron::ser::to_string_pretty(&pack, ron::ser::PrettyConfig::default()).unwrap();
+
+        let read = read_pack(ron.as_bytes()).unwrap();
+        assert_eq!(read.manifest.author, "Blub");
+        assert_eq!(read.textures, textures);
+        let level = &read.levels[0];
+        assert_eq!(level.grid, rows);
+        let read_maze = maze_from_grid(&level.grid, level.level.clone()).unwrap();
+        assert_eq!(read_maze.level.spawns, maze.level.spawns);
+        assert!(read_maze.is_exit(4, 2));
+    }
+
+    #[test]
+    fn broken_packs_are_rejected() {
+        let newer = format!(
+            "(version: {}, manifest: (name: \"\"), levels: [])",
+            PACK_VERSION + 1
+        );
+        assert!(read_pack(newer.as_bytes()).is_err());
+
+        let level = maze(&["..", ".."], &[[0, 0]], [1, 1]).level;
+        let crooked = ["...", ".."].map(String::from);
+        assert!(maze_from_grid(&crooked, level.clone()).is_err());
+        let unknown = ["..", ".x"].map(String::from);
+        assert!(maze_from_grid(&unknown, level).is_err());
+        assert!(check_name("../markers").is_err());
+        assert!(check_name("Level 2_b").is_ok());
+    }
+}
@@ -0,0 +1,367 @@
+use crate::level_pack::PackTextures;
+use crate::loading::LabyrinthLevel;
+use crate::map::Maze;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+use bevy::render::texture::{CompressedImageFormats, ImageType, TextureError};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+const LEVEL_EXTENSION: &str = ".ron.level";
+/// Directory with levels of the player, inside the data directory
+#[cfg(not(target_arch = "wasm32"))]
+pub const USER_LEVELS: &str = "levels";
+/// The browser can not list directories, so the web build ships with these levels
+#[cfg(target_arch = "wasm32")]
+const EMBEDDED_LEVELS: [(&str, &str, &[u8]); 1] = [(
+    "1",
+    include_str!("../assets/mazes/1.ron.level"),
+    include_bytes!("../assets/mazes/1.png"),
+)];
+
+pub struct LevelsPlugin;
+
+/// This plugin finds all levels when the game starts
+/// Every `*.ron.level` file in `assets/mazes` and in the `levels` directory of the player
+/// is a level, named like the file. Its maze is read from the image next to it.
+/// Levels from level packs are added once their pack is loaded, see [crate::level_pack].
+impl Plugin for LevelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Levels::discover());
+    }
+}
+
+/// All playable levels, in the order they are played
+#[derive(Default)]
+pub struct Levels {
+    levels: Vec<LevelEntry>,
+}
+
+struct LevelEntry {
+    name: String,
+    maze: Maze,
+    /// Custom textures from a level pack, kept to export the level again
+    textures: PackTextures,
+    materials: LevelMaterials,
+}
+
+/// Materials replacing the default ones of [LabyrinthMaterials](crate::loading::LabyrinthMaterials)
+#[derive(Clone, Default)]
+pub struct LevelMaterials {
+    pub wall: Option<Handle<StandardMaterial>>,
+    pub ground: Option<Handle<StandardMaterial>>,
+}
+
+impl Levels {
+    /// Reads the shipped levels and those of the player, skipping broken ones with a warning
+    pub fn discover() -> Self {
+        let mut levels = Levels::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            levels.add_directory(&FileAssetIo::get_base_path().join("assets/mazes"));
+            if let Some(directory) = storage::data_dir().map(|dir| dir.join(USER_LEVELS)) {
+                if directory.is_dir() {
+                    levels.add_directory(&directory);
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        for (name, level, image) in EMBEDDED_LEVELS {
+            let maze = ron::de::from_str(level)
+                .map_err(LevelError::from)
+                .and_then(|level| build_maze(level, image));
+            match maze {
+                Ok(maze) => levels.add(name, maze),
+                Err(error) => warn!("Skipping level {}: {}", name, error),
+            }
+        }
+        if levels.is_empty() {
+            error!("No levels found");
+        }
+        levels
+    }
+
+    fn add_directory(&mut self, directory: &Path) {
+        for (path, level) in read_directory(directory) {
+            match level {
+                Ok((name, maze)) => self.add(&name, maze),
+                Err(error) => warn!("Skipping level {:?}: {}", path, error),
+            }
+        }
+    }
+
+    /// Adds a level, unless there already is one with that name
+    pub fn add(&mut self, name: &str, maze: Maze) {
+        self.add_with_textures(
+            name,
+            maze,
+            PackTextures::default(),
+            LevelMaterials::default(),
+        );
+    }
+
+    /// Adds a level of a level pack with the custom textures of the pack
+    pub fn add_with_textures(
+        &mut self,
+        name: &str,
+        maze: Maze,
+        textures: PackTextures,
+        materials: LevelMaterials,
+    ) {
+        if self.get(name).is_some() {
+            warn!(
+                "Skipping level {}, there already is a level with that name",
+                name
+            );
+            return;
+        }
+        self.levels.push(LevelEntry {
+            name: name.to_owned(),
+            maze,
+            textures,
+            materials,
+        });
+        self.levels.sort_by(|a, b| play_order(&a.name, &b.name));
+    }
+
+    fn entry(&self, name: &str) -> Option<&LevelEntry> {
+        self.levels.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Maze> {
+        self.entry(name).map(|entry| &entry.maze)
+    }
+
+    /// The custom textures of a level, empty for levels outside of packs
+    pub fn textures(&self, name: &str) -> PackTextures {
+        self.entry(name)
+            .map(|entry| entry.textures.clone())
+            .unwrap_or_default()
+    }
+
+    /// The custom materials of a level, empty for levels outside of packs
+    pub fn materials(&self, name: &str) -> LevelMaterials {
+        self.entry(name)
+            .map(|entry| entry.materials.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.levels.iter().map(|entry| entry.name.as_str())
+    }
+
+    pub fn first(&self) -> Option<&str> {
+        self.names().next()
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.names().last()
+    }
+
+    /// The level after the given one, if there is any
+    pub fn next(&self, level: &str) -> Option<&str> {
+        let index = self.names().position(|other| other == level)?;
+        self.names().nth(index + 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+/// Numbered levels come first and in numerical order, then the rest by name
+fn play_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let number = |name: &str| name.parse::<u32>().unwrap_or(u32::MAX);
+    number(a).cmp(&number(b)).then_with(|| a.cmp(b))
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Image(TextureError),
+    /// The level does not fit its maze
+    Invalid(String),
+}
+
+impl Display for LevelError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelError::Io(error) => write!(f, "{}", error),
+            LevelError::Ron(error) => write!(f, "{}", error),
+            LevelError::Image(error) => write!(f, "{}", error),
+            LevelError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<std::io::Error> for LevelError {
+    fn from(error: std::io::Error) -> Self {
+        LevelError::Io(error)
+    }
+}
+
+impl From<ron::Error> for LevelError {
+    fn from(error: ron::Error) -> Self {
+        LevelError::Ron(error)
+    }
+}
+
+impl From<TextureError> for LevelError {
+    fn from(error: TextureError) -> Self {
+        LevelError::Image(error)
+    }
+}
+
+/// The name and maze of a level file, or why it could not be read
+pub type LevelFile = Result<(String, Maze), LevelError>;
+
+/// Reads every level file in the directory, with its name or why it could not be read
+pub fn read_directory(directory: &Path) -> Vec<(PathBuf, LevelFile)> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Failed to read the levels in {:?}: {}", directory, error);
+            return vec![];
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let name = path
+                .file_name()?
+                .to_str()?
+                .strip_suffix(LEVEL_EXTENSION)?
+                .to_owned();
+            let maze = read_level(directory, &path, &name).map(|maze| (name, maze));
+            Some((path, maze))
+        })
+        .collect()
+}
+
+fn read_level(directory: &Path, path: &Path, name: &str) -> Result<Maze, LevelError> {
+    let level: LabyrinthLevel = ron::de::from_str(&std::fs::read_to_string(path)?)?;
+    let image = directory.join(level.image.clone().unwrap_or(format!("{}.png", name)));
+    build_maze(level, &std::fs::read(image)?)
+}
+
+/// Builds the maze of a level from the PNG image of its walls
+pub fn build_maze(level: LabyrinthLevel, image: &[u8]) -> Result<Maze, LevelError> {
+    let image = Image::from_buffer(
+        image,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+    )?;
+    let size = image.texture_descriptor.size;
+    if size.width != size.height {
+        return Err(LevelError::Invalid(format!(
+            "the maze is {}x{}, but has to be square",
+            size.width, size.height
+        )));
+    }
+    validate(Maze::from_image(&image, level))
+}
+
+/// Builds the maze of a level from rows of `#` (wall) and `.` (floor)
+pub fn maze_from_grid(grid: &[String], level: LabyrinthLevel) -> Result<Maze, LevelError> {
+    if let Some(row) = grid.iter().find(|row| row.chars().count() != grid.len()) {
+        return Err(LevelError::Invalid(format!(
+            "the row {:?} does not make the maze square",
+            row
+        )));
+    }
+    let open = grid
+        .iter()
+        .flat_map(|row| row.chars())
+        .map(|tile| match tile {
+            '.' => Ok(true),
+            '#' => Ok(false),
+            other => Err(LevelError::Invalid(format!("unknown tile {:?}", other))),
+        })
+        .collect::<Result<_, _>>()?;
+    validate(Maze::new(grid.len(), open, level))
+}
+
+/// The rows of the maze as read by [maze_from_grid]
+pub fn grid(maze: &Maze) -> Vec<String> {
+    (0..maze.size)
+        .map(|y| {
+            (0..maze.size)
+                .map(|x| if maze.is_wall(x, y) { '#' } else { '.' })
+                .collect()
+        })
+        .collect()
+}
+
+/// Checks that the spawns and the exit of the level are inside of its maze
+fn validate(maze: Maze) -> Result<Maze, LevelError> {
+    let inside = |(x, y): (usize, usize)| x < maze.size && y < maze.size;
+    if maze.level.spawns.is_empty() {
+        return Err(LevelError::Invalid("the level has no spawns".to_owned()));
+    }
+    if let Some(spawn) = maze
+        .level
+        .spawns
+        .iter()
+        .find(|spawn| !inside(maze.spawn_slot(**spawn)))
+    {
+        return Err(LevelError::Invalid(format!(
+            "the spawn {:?} is outside of the maze",
+            spawn
+        )));
+    }
+    if !inside((maze.level.exit[0], maze.level.exit[1])) {
+        return Err(LevelError::Invalid(format!(
+            "the exit {:?} is outside of the maze",
+            maze.level.exit
+        )));
+    }
+    Ok(maze)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn broken_levels_are_skipped() {
+        let shipped = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/mazes");
+        let directory = std::env::temp_dir().join(format!("blub-levels-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::copy(shipped.join("1.png"), directory.join("2.png")).unwrap();
+        fs::copy(shipped.join("1.png"), directory.join("shared.png")).unwrap();
+        let level = fs::read_to_string(shipped.join("1.ron.level")).unwrap();
+        fs::write(directory.join("2.ron.level"), &level).unwrap();
+        fs::write(
+            directory.join("10.ron.level"),
+            level.replacen('(', "(image: Some(\"shared.png\"),", 1),
+        )
+        .unwrap();
+        fs::write(directory.join("broken.ron.level"), "(spawns: [").unwrap();
+        fs::write(directory.join("no_image.ron.level"), &level).unwrap();
+        fs::write(
+            directory.join("outside.ron.level"),
+            "(image: Some(\"shared.png\"), spawns: [(-0.5, 0.)], exit: (300, 0))",
+        )
+        .unwrap();
+
+        let mut levels = Levels::default();
+        levels.add_directory(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(levels.names().collect::<Vec<_>>(), vec!["2", "10"]);
+        assert_eq!(levels.next("2"), Some("10"));
+        assert_eq!(levels.next("10"), None);
+    }
+}
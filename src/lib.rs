@@ -1,21 +1,62 @@
 mod actions;
+mod annotations;
 mod audio;
+mod breadcrumbs;
 mod character;
+mod ghost;
+mod hud;
 mod in_game_menu;
+mod leaderboard;
+mod level_pack;
+mod levels;
 mod loading;
+mod loading_screen;
 mod map;
+mod markers;
+mod maze_code;
 mod menu;
+mod notifications;
+mod palette;
+mod raycast;
+mod replay;
+mod results;
+mod savegame;
+mod settings;
+mod simulation;
+mod solver;
+mod storage;
+#[cfg(test)]
+mod testing;
 mod ui;
+mod widgets;
 
 use crate::audio::InternalAudioPlugin;
 use crate::loading::LoadingPlugin;
+use crate::loading_screen::LoadingScreenPlugin;
 use crate::menu::MenuPlugin;
 
 use crate::actions::ActionPlugin;
-use crate::character::CharacterPlugin;
+use crate::annotations::AnnotationPlugin;
+use crate::breadcrumbs::BreadcrumbPlugin;
+use crate::character::{CharacterPlugin, CharacterViewPlugin};
+use crate::ghost::GhostPlugin;
+use crate::hud::HudPlugin;
 use crate::in_game_menu::InGameMenuPlugin;
+use crate::leaderboard::LeaderboardPlugin;
+use crate::level_pack::LevelPackPlugin;
+use crate::levels::LevelsPlugin;
 use crate::map::MapPlugin;
+use crate::markers::MarkerPlugin;
+use crate::maze_code::MazeCodePlugin;
+use crate::notifications::NotificationPlugin;
+use crate::palette::PalettePlugin;
+use crate::replay::ReplayPlugin;
+use crate::results::ResultsPlugin;
+use crate::savegame::SaveGamePlugin;
+use crate::settings::SettingsPlugin;
+use crate::simulation::SimulationPlugin;
 use crate::ui::UiPlugin;
+use crate::widgets::WidgetPlugin;
 use bevy::app::App;
 #[cfg(debug_assertions)]
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
@@ -23,12 +64,25 @@ use bevy::pbr::wireframe::WireframePlugin;
 use bevy::prelude::*;
 use bevy_inspector_egui::WorldInspectorPlugin;
 
+pub use crate::settings::Settings;
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum GameState {
     Loading,
     Playing,
     Menu,
     InGameMenu,
+    /// The results of a completed level are shown
+    LevelComplete,
+}
+
+/// How much help the player gets
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
 }
 
 pub struct GamePlugin;
@@ -36,21 +90,52 @@ pub struct GamePlugin;
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_state(GameState::Loading)
+            .init_resource::<Difficulty>()
+            .add_plugin(GameplayPlugin)
+            .add_plugin(SettingsPlugin)
+            .add_plugin(PalettePlugin)
             .add_plugin(LoadingPlugin)
+            .add_plugin(LoadingScreenPlugin)
+            .add_plugin(WidgetPlugin)
             .add_plugin(MenuPlugin)
             .add_plugin(InternalAudioPlugin)
+            .add_plugin(LevelsPlugin)
+            .add_plugin(LevelPackPlugin)
+            .add_plugin(MazeCodePlugin)
             .add_plugin(MapPlugin)
             .add_plugin(InGameMenuPlugin)
-            .add_plugin(CharacterPlugin)
+            .add_plugin(CharacterViewPlugin)
+            .add_plugin(MarkerPlugin)
+            .add_plugin(BreadcrumbPlugin)
+            .add_plugin(AnnotationPlugin)
             .add_plugin(UiPlugin)
-            .add_plugin(ActionPlugin);
+            .add_plugin(HudPlugin)
+            .add_plugin(ActionPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(ResultsPlugin)
+            .add_plugin(SaveGamePlugin)
+            .add_plugin(LeaderboardPlugin)
+            .add_plugin(GhostPlugin);
 
         #[cfg(debug_assertions)]
         {
-            app.add_plugin(FrameTimeDiagnosticsPlugin::default())
+            app.add_plugin(FrameTimeDiagnosticsPlugin)
                 .add_plugin(LogDiagnosticsPlugin::default())
                 .add_plugin(WorldInspectorPlugin::new())
                 .add_plugin(WireframePlugin);
         }
     }
 }
+
+/// All gameplay logic, without rendering, assets or input devices
+///
+/// Expects a [map::Maze] resource when entering [GameState::Playing].
+pub struct GameplayPlugin;
+
+impl Plugin for GameplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(SimulationPlugin)
+            .add_plugin(NotificationPlugin)
+            .add_plugin(CharacterPlugin);
+    }
+}
@@ -1,7 +1,7 @@
+use crate::palette::{CharacterMaterials, CharacterPalette};
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
-use bevy_common_assets::ron::RonAssetPlugin;
 use bevy_kira_audio::AudioSource;
 
 pub struct LoadingPlugin;
@@ -16,13 +16,11 @@ impl Plugin for LoadingPlugin {
                 .with_collection::<FontAssets>()
                 .with_collection::<AudioAssets>()
                 .with_collection::<TextureAssets>()
-                .with_collection::<MazeAssets>()
                 .with_collection::<LabyrinthTextures>()
                 .init_resource::<LabyrinthMaterials>()
+                .init_resource::<CharacterMaterials>()
                 .continue_to_state(GameState::Menu),
-        )
-        .add_system_set(SystemSet::on_exit(GameState::Loading).with_system(make_markers_opaque))
-        .add_plugin(RonAssetPlugin::<LabyrinthLevel>::new(&["ron.level"]));
+        );
     }
 }
 
@@ -45,55 +43,11 @@ pub struct AudioAssets {
 pub struct TextureAssets {
     #[asset(path = "textures/grass.jpg", standard_material)]
     pub grass: Handle<StandardMaterial>,
-    pub green: ColorStandardMaterial<80, 125, 80, { u8::MAX }>,
-    pub red: ColorStandardMaterial<{ u8::MAX }, 0, 0, { u8::MAX }>,
-    pub blue: ColorStandardMaterial<0, 0, { u8::MAX }, { u8::MAX }>,
-    #[asset(path = "textures/green_marker_mask.png", standard_material)]
-    pub green_marker_mask: Handle<StandardMaterial>,
-    #[asset(path = "textures/green_marker.png", standard_material)]
-    pub green_marker: Handle<StandardMaterial>,
-    #[asset(path = "textures/red_marker_mask.png", standard_material)]
-    pub red_marker_mask: Handle<StandardMaterial>,
-    #[asset(path = "textures/red_marker.png", standard_material)]
-    pub red_marker: Handle<StandardMaterial>,
-    #[asset(path = "textures/blue_marker_mask.png", standard_material)]
-    pub blue_marker_mask: Handle<StandardMaterial>,
-    #[asset(path = "textures/blue_marker.png", standard_material)]
-    pub blue_marker: Handle<StandardMaterial>,
-}
-
-fn make_markers_opaque(
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    textures: Res<TextureAssets>,
-) {
-    materials
-        .get_mut(&textures.green_marker_mask)
-        .unwrap()
-        .alpha_mode = AlphaMode::Opaque;
-    materials
-        .get_mut(&textures.green_marker)
-        .unwrap()
-        .alpha_mode = AlphaMode::Opaque;
-    materials
-        .get_mut(&textures.red_marker_mask)
-        .unwrap()
-        .alpha_mode = AlphaMode::Opaque;
-    materials.get_mut(&textures.red_marker).unwrap().alpha_mode = AlphaMode::Opaque;
-    materials
-        .get_mut(&textures.blue_marker_mask)
-        .unwrap()
-        .alpha_mode = AlphaMode::Opaque;
-    materials.get_mut(&textures.blue_marker).unwrap().alpha_mode = AlphaMode::Opaque;
-}
-
-impl TextureAssets {
-    pub fn get_character_texture(&self, character: u8) -> Handle<StandardMaterial> {
-        match character {
-            1 => self.green.handle.clone(),
-            2 => self.red.handle.clone(),
-            _ => self.blue.handle.clone(),
-        }
-    }
+    /// Greyscale, tinted with the marker colour of each character
+    #[asset(path = "textures/marker.png")]
+    pub marker: Handle<Image>,
+    #[asset(path = "characters.palette.ron")]
+    pub character_palette: Handle<CharacterPalette>,
 }
 
 #[derive(AssetCollection)]
@@ -145,36 +99,33 @@ impl FromWorld for LabyrinthMaterials {
     }
 }
 
-#[derive(AssetCollection)]
-pub struct MazeAssets {
-    #[asset(path = "mazes/1.png")]
-    pub one_data: Handle<Image>,
-    #[asset(path = "mazes/1.ron.level")]
-    pub one_level: Handle<LabyrinthLevel>,
-    #[asset(path = "mazes/2.png")]
-    pub two_data: Handle<Image>,
+/// A `*.ron.level` file, see [crate::levels]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct LabyrinthLevel {
+    /// Path of the maze image relative to the level file like `Some("maze.png")`,
+    /// defaults to `<level>.png`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    pub spawns: Vec<[f32; 2]>,
+    pub exit: [usize; 2],
+    #[serde(default)]
+    pub marker_budget: MarkerBudget,
+    /// Characters only see markers placed by their own parts
+    #[serde(default)]
+    pub private_markers: bool,
 }
 
-pub struct ColorStandardMaterial<const R: u8, const G: u8, const B: u8, const A: u8> {
-    pub handle: Handle<StandardMaterial>,
+/// How many markers can be placed in a level
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum MarkerBudget {
+    /// Every part brings this many markers; combined characters share them
+    PerCharacter(usize),
+    /// All characters together can place this many markers
+    PerLevel(usize),
 }
 
-impl<const R: u8, const G: u8, const B: u8, const A: u8> FromWorld
-    for ColorStandardMaterial<R, G, B, A>
-{
-    fn from_world(world: &mut World) -> Self {
-        let mut materials = world
-            .get_resource_mut::<Assets<StandardMaterial>>()
-            .unwrap();
-        ColorStandardMaterial {
-            handle: materials.add(StandardMaterial::from(Color::rgba_u8(R, G, B, A))),
-        }
+impl Default for MarkerBudget {
+    fn default() -> Self {
+        MarkerBudget::PerCharacter(10)
     }
 }
-
-#[derive(serde::Deserialize, bevy::reflect::TypeUuid)]
-#[uuid = "84f362c3-62e0-cac3-73c8-7e013e8049f5"]
-pub struct LabyrinthLevel {
-    pub spawns: Vec<[f32; 2]>,
-    pub exit: [usize; 2],
-}
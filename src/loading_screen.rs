@@ -0,0 +1,256 @@
+use crate::loading::{AudioAssets, FontAssets, LabyrinthTextures, TextureAssets};
+use crate::widgets::spawn_label;
+use crate::GameState;
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+
+/// Same font as [FontAssets], which only exists once everything is loaded
+const FONT: &str = "fonts/FiraSans-Bold.ttf";
+const BAR_WIDTH: f32 = 400.;
+
+pub struct LoadingScreenPlugin;
+
+/// This plugin shows the progress of [GameState::Loading]
+/// If an asset fails to load, the loading state would wait forever,
+/// so the screen switches to a list of the failing paths instead.
+impl Plugin for LoadingScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Loading)
+                .with_system(track_assets.exclusive_system())
+                .with_system(setup_loading_screen),
+        )
+        .add_system_set(SystemSet::on_update(GameState::Loading).with_system(update_loading_screen))
+        .add_system_set(SystemSet::on_exit(GameState::Loading).with_system(cleanup_loading_screen));
+    }
+}
+
+/// Handles of all assets of the loading state
+struct LoadingAssets(Vec<HandleUntyped>);
+
+#[derive(Component)]
+struct LoadingScreenElement;
+
+/// The container of the progress bar or the error list
+#[derive(Component)]
+struct LoadingScreenRoot;
+
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct CurrentAsset;
+
+/// Progress of the loading state, from the asset paths and their load states
+#[derive(Debug, PartialEq)]
+struct LoadingStatus {
+    loaded: usize,
+    total: usize,
+    /// The first asset still loading
+    current: Option<String>,
+    failed: Vec<String>,
+}
+
+impl LoadingStatus {
+    fn new(assets: impl Iterator<Item = (String, LoadState)>) -> Self {
+        let mut status = LoadingStatus {
+            loaded: 0,
+            total: 0,
+            current: None,
+            failed: vec![],
+        };
+        for (path, state) in assets {
+            status.total += 1;
+            match state {
+                LoadState::Loaded => status.loaded += 1,
+                LoadState::Failed => status.failed.push(path),
+                _ if status.current.is_none() => status.current = Some(path),
+                _ => {}
+            }
+        }
+        status
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.;
+        }
+        self.loaded as f32 / self.total as f32
+    }
+}
+
+/// Requests the assets of every collection, to follow them next to the loading state
+///
+/// The asset server hands out the handles the loading state already requested.
+fn track_assets(world: &mut World) {
+    let handles = [
+        FontAssets::load(world),
+        AudioAssets::load(world),
+        TextureAssets::load(world),
+        LabyrinthTextures::load(world),
+    ]
+    .concat();
+    world.insert_resource(LoadingAssets(handles));
+}
+
+fn setup_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load(FONT),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    commands
+        .spawn_bundle(Camera2dBundle::default())
+        .insert(LoadingScreenElement);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        })
+        .insert(LoadingScreenRoot)
+        .insert(LoadingScreenElement)
+        .with_children(|parent| {
+            spawn_label(parent, &text_style, "Loading");
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(BAR_WIDTH), Val::Px(20.)),
+                        margin: UiRect::all(Val::Px(5.)),
+                        ..default()
+                    },
+                    color: UiColor(Color::rgb(0.15, 0.15, 0.15)),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(0.), Val::Percent(100.)),
+                            ..default()
+                        },
+                        color: UiColor(Color::rgb(0.35, 0.75, 0.35)),
+                        ..default()
+                    })
+                    .insert(ProgressBar);
+                });
+            spawn_label(
+                parent,
+                &TextStyle {
+                    font_size: 20.0,
+                    ..text_style.clone()
+                },
+                "",
+            )
+            .insert(CurrentAsset);
+        });
+}
+
+fn update_loading_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    assets: Option<Res<LoadingAssets>>,
+    mut failed: Local<bool>,
+    root: Query<Entity, With<LoadingScreenRoot>>,
+    mut bar: Query<&mut Style, With<ProgressBar>>,
+    mut current: Query<&mut Text, With<CurrentAsset>>,
+) {
+    let assets = match assets {
+        Some(assets) if !*failed => assets,
+        _ => return,
+    };
+    let status = LoadingStatus::new(assets.0.iter().map(|handle| {
+        let path = asset_server
+            .get_handle_path(handle)
+            .map_or_else(String::new, |path| path.path().display().to_string());
+        (path, asset_server.get_load_state(handle))
+    }));
+    if !status.failed.is_empty() {
+        *failed = true;
+        for path in &status.failed {
+            error!("Failed to load {}", path);
+        }
+        show_failed_assets(&mut commands, &asset_server, root.single(), &status.failed);
+        return;
+    }
+    for mut style in &mut bar {
+        style.size.width = Val::Px(BAR_WIDTH * status.fraction());
+    }
+    for mut text in &mut current {
+        text.sections[0].value = status.current.clone().unwrap_or_default();
+    }
+}
+
+/// Replaces the progress bar with the paths that could not be loaded
+fn show_failed_assets(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    root: Entity,
+    failed: &[String],
+) {
+    let text_style = TextStyle {
+        font: asset_server.load(FONT),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let path_style = TextStyle {
+        font_size: 20.0,
+        ..text_style.clone()
+    };
+    commands.entity(root).despawn_descendants();
+    commands.entity(root).with_children(|parent| {
+        spawn_label(parent, &text_style, "Failed to load");
+        for path in failed {
+            spawn_label(parent, &path_style, path);
+        }
+        spawn_label(
+            parent,
+            &path_style,
+            "Make sure the assets directory is complete and restart the game.",
+        );
+    });
+}
+
+fn cleanup_loading_screen(
+    mut commands: Commands,
+    elements: Query<Entity, With<LoadingScreenElement>>,
+) {
+    for entity in &elements {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<LoadingAssets>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_shows_the_first_loading_asset_and_all_failures() {
+        let status = LoadingStatus::new(
+            [
+                ("a.png", LoadState::Loaded),
+                ("b.png", LoadState::Loading),
+                ("c.png", LoadState::Failed),
+                ("d.png", LoadState::NotLoaded),
+            ]
+            .into_iter()
+            .map(|(path, state)| (path.to_owned(), state)),
+        );
+        assert_eq!(
+            status,
+            LoadingStatus {
+                loaded: 1,
+                total: 4,
+                current: Some("b.png".to_owned()),
+                failed: vec!["c.png".to_owned()],
+            }
+        );
+        assert_eq!(status.fraction(), 0.25);
+    }
+}
@@ -5,21 +5,27 @@ use bevy::prelude::{App, ClearColor, Color, Msaa, NonSend, WindowDescriptor};
 use bevy::window::WindowId;
 use bevy::winit::WinitWindows;
 use bevy::DefaultPlugins;
-use blubs_dilemma::GamePlugin;
+use blubs_dilemma::{GamePlugin, Settings};
 use std::io::Cursor;
 use winit::window::Icon;
 
 fn main() {
+    // the window and MSAA cannot be configured by a plugin
+    let settings = Settings::load();
     App::new()
-        .insert_resource(Msaa { samples: 1 })
+        .insert_resource(Msaa {
+            samples: settings.msaa,
+        })
         .insert_resource(ClearColor(Color::rgb(0.4, 0.4, 0.4)))
         .insert_resource(WindowDescriptor {
-            width: 800.,
-            height: 600.,
+            width: settings.resolution.0 as f32,
+            height: settings.resolution.1 as f32,
+            mode: settings.window_mode.into(),
             title: "Blub's dilemma".to_string(),
             canvas: Some("#bevy".to_owned()),
             ..Default::default()
         })
+        .insert_resource(settings)
         .add_plugins(DefaultPlugins)
         .add_plugin(GamePlugin)
         .add_startup_system(set_window_icon)
@@ -1,8 +1,12 @@
-use crate::loading::{LabyrinthLevel, LabyrinthMaterials, MazeAssets, TextureAssets};
+use crate::character::PLAYER_RADIUS;
+use crate::levels::Levels;
+use crate::loading::{LabyrinthLevel, LabyrinthMaterials, TextureAssets};
+use crate::menu::MenuMessage;
 use crate::shape::Plane;
+#[cfg(debug_assertions)]
+use crate::solver;
 use crate::GameState;
 use bevy::prelude::*;
-use bevy_mod_raycast::{DefaultPluginState, DefaultRaycastingPlugin, RayCastMesh};
 
 pub const PIXEL_WORLD_SIZE: f32 = 0.7;
 pub const WALL_HEIGHT: f32 = 0.3;
@@ -15,47 +19,218 @@ impl Plugin for MapPlugin {
             color: Color::WHITE,
             brightness: 0.1,
         })
-        .insert_resource(DefaultPluginState::<MyRaycastSet>::default().with_debug_cursor())
-        .add_plugin(DefaultRaycastingPlugin::<MyRaycastSet>::default())
-        .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_map));
+        .init_resource::<CurrentLevel>()
+        .add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(prepare_level.exclusive_system().at_start())
+                .with_system(spawn_map),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::LevelComplete).with_system(despawn_level))
+        // a level that could not be prepared is left again right away
+        .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(despawn_level));
     }
 }
 
-pub struct MyRaycastSet;
+/// Marks everything that belongs to the level being played
+///
+/// These entities are despawned when the level is left, so that it can be started again.
+#[derive(Component)]
+pub struct LevelEntity;
 
-pub struct PlaneAsset(pub Handle<Mesh>);
+/// Identifier of the level that is played
+pub struct CurrentLevel(pub String);
+
+impl Default for CurrentLevel {
+    fn default() -> Self {
+        CurrentLevel("1".to_owned())
+    }
+}
+
+/// Walkable tiles and layout of the current level, used by the simulation
+#[derive(Clone)]
+pub struct Maze {
+    pub size: usize,
+    open: Vec<bool>,
+    pub level: LabyrinthLevel,
+}
+
+impl Maze {
+    pub fn new(size: usize, open: Vec<bool>, level: LabyrinthLevel) -> Self {
+        assert_eq!(size * size, open.len(), "A maze has to be square");
+        Maze { size, open, level }
+    }
+
+    pub fn from_image(image: &Image, level: LabyrinthLevel) -> Self {
+        let size = image.texture_descriptor.size.width as usize;
+        let open = image
+            .data
+            .chunks(4)
+            .take(size * size)
+            .map(|pixel| pixel[0] >= 50)
+            .collect();
+        Maze::new(size, open, level)
+    }
+
+    /// Tile of the given spawn from the level file
+    pub fn spawn_slot(&self, spawn: [f32; 2]) -> (usize, usize) {
+        self.slot(Vec3::new(
+            spawn[0] * PIXEL_WORLD_SIZE,
+            0.,
+            spawn[1] * PIXEL_WORLD_SIZE,
+        ))
+    }
+
+    pub fn world_width(&self) -> f32 {
+        self.size as f32 * PIXEL_WORLD_SIZE
+    }
+
+    /// Tiles outside of the maze count as walls
+    pub fn is_wall(&self, x: usize, y: usize) -> bool {
+        x >= self.size || !self.open.get(y * self.size + x).copied().unwrap_or(false)
+    }
+
+    pub fn is_exit(&self, x: usize, y: usize) -> bool {
+        self.level.exit[0] == x && self.level.exit[1] == y
+    }
+
+    /// Maze tile containing the given world position
+    pub fn slot(&self, translation: Vec3) -> (usize, usize) {
+        let world_width = self.world_width();
+        (
+            ((translation.x + world_width / 2.) / PIXEL_WORLD_SIZE).round() as usize,
+            ((translation.z + world_width / 2.) / PIXEL_WORLD_SIZE).round() as usize,
+        )
+    }
+
+    /// Cuts the given movement of a player at `translation` short where it would hit a wall
+    ///
+    /// The second return value is true if the player ran into the exit.
+    pub fn restrict_movement(&self, translation: Vec3, mut movement: Vec3) -> (Vec3, bool) {
+        let world_width = self.world_width();
+        let offset_x = (translation.x + world_width) % PIXEL_WORLD_SIZE;
+        let offset_z = (translation.z + world_width) % PIXEL_WORLD_SIZE;
+        let (slot_x, slot_y) = self.slot(translation);
+        let mut reached_exit = false;
+
+        let towards_z =
+            if movement.z > 0. && offset_z + PLAYER_RADIUS + movement.z > PIXEL_WORLD_SIZE {
+                Some(slot_y + 1)
+            } else if movement.z < 0. && offset_z - PLAYER_RADIUS + movement.z < 0.0 {
+                Some(slot_y.wrapping_sub(1))
+            } else {
+                None
+            };
+        if let Some(next_y) = towards_z {
+            if self.is_wall(slot_x, next_y) {
+                reached_exit |= self.is_exit(slot_x, next_y);
+                movement.z = 0.0;
+            }
+        }
+
+        let towards_x =
+            if movement.x > 0. && offset_x + PLAYER_RADIUS + movement.x > PIXEL_WORLD_SIZE {
+                Some(slot_x + 1)
+            } else if movement.x < 0. && offset_x - PLAYER_RADIUS + movement.x < 0.0 {
+                Some(slot_x.wrapping_sub(1))
+            } else {
+                None
+            };
+        if let Some(next_x) = towards_x {
+            // corners...
+            if let Some(next_y) = towards_z.filter(|_| movement.z != 0.) {
+                if self.is_wall(next_x, next_y) {
+                    if movement.z.abs() > movement.x.abs() {
+                        movement.z = 0.0;
+                    } else {
+                        movement.x = 0.0;
+                    }
+                }
+            }
+            if self.is_wall(next_x, slot_y) {
+                reached_exit |= self.is_exit(next_x, slot_y);
+                movement.x = 0.0;
+            }
+        }
+
+        (movement, reached_exit)
+    }
+}
+
+/// Builds the [Maze] of the current level before anything else is spawned
+///
+/// A missing level goes back to the menu. The level systems still see an empty maze until then.
+fn prepare_level(world: &mut World) {
+    let level = world.resource::<CurrentLevel>().0.clone();
+    let maze = match world.resource::<Levels>().get(&level) {
+        Some(maze) => maze.clone(),
+        None => {
+            warn!("Level {} was not discovered, going back to the menu", level);
+            world.insert_resource(MenuMessage(Some(format!("Level {} is missing", level))));
+            world.insert_resource(Maze::new(
+                0,
+                vec![],
+                LabyrinthLevel {
+                    image: None,
+                    spawns: vec![],
+                    exit: [0, 0],
+                    marker_budget: default(),
+                    private_markers: false,
+                },
+            ));
+            if let Err(error) = world
+                .resource_mut::<State<GameState>>()
+                .set(GameState::Menu)
+            {
+                warn!("Failed to go back to the menu: {:?}", error);
+            }
+            return;
+        }
+    };
+    #[cfg(debug_assertions)]
+    match solver::solve(&maze) {
+        Some(solution) => info!(
+            "Level {} can be beaten walking {} tiles",
+            level,
+            solution.walked_tiles()
+        ),
+        None => warn!("Level {} cannot be beaten", level),
+    }
+    world.insert_resource(maze);
+}
 
 fn spawn_map(
     mut commands: Commands,
     textures: Res<TextureAssets>,
     labyrinth_materials: Res<LabyrinthMaterials>,
-    maze_assets: Res<MazeAssets>,
-    maze_levels: Res<Assets<LabyrinthLevel>>,
-    images: Res<Assets<Image>>,
+    maze: Res<Maze>,
+    level: Res<CurrentLevel>,
+    levels: Res<Levels>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
+    // levels from packs can bring their own textures
+    let custom = levels.materials(&level.0);
+    let wall = custom
+        .wall
+        .unwrap_or_else(|| labyrinth_materials.wall.clone());
+    let ground = custom
+        .ground
+        .unwrap_or_else(|| labyrinth_materials.ground.clone());
     let plane = meshes.add(Plane::default().into());
-    let mask = meshes.add(Plane { size: 0.1 }.into());
-    commands.insert_resource(PlaneAsset(mask));
-    let maze_image = images.get(&maze_assets.one_data).unwrap();
-    let pixel_per_row = maze_image.texture_descriptor.size.width as usize;
-    let world_width = pixel_per_row as f32 * PIXEL_WORLD_SIZE;
-    let data = &maze_image.data;
-    let maze_level = maze_levels.get(&maze_assets.one_level).unwrap();
+    let pixel_per_row = maze.size;
+    let world_width = maze.world_width();
     let mut elements = vec![];
     for pixel_x in 0..pixel_per_row {
         for pixel_y in 0..pixel_per_row {
-            let pixel = pixel_y * pixel_per_row + pixel_x;
-            if data.get(pixel * 4).unwrap() > &50 {
+            if !maze.is_wall(pixel_x, pixel_y) {
                 let mut transform = Transform::from_translation(Vec3::new(
                     pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                     -WALL_HEIGHT,
                     pixel_y as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                 ));
                 transform = transform.with_scale(Vec3::splat(PIXEL_WORLD_SIZE));
-                elements.push((labyrinth_materials.ground.clone(), transform));
+                elements.push((ground.clone(), transform));
                 // +x
-                if pixel_x < pixel_per_row - 1 && data.get((pixel + 1) * 4).unwrap() < &50 {
+                if pixel_x < pixel_per_row - 1 && maze.is_wall(pixel_x + 1, pixel_y) {
                     let mut transform = Transform::from_translation(Vec3::new(
                         pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.
                             + PIXEL_WORLD_SIZE / 2.,
@@ -64,7 +239,7 @@ fn spawn_map(
                     ));
                     transform = transform.with_scale(Vec3::new(PIXEL_WORLD_SIZE, 1.0, WALL_HEIGHT));
                     transform = transform.looking_at(transform.translation - Vec3::Y, -Vec3::X);
-                    if maze_level.exit[0] == pixel_x + 1 && maze_level.exit[1] == pixel_y {
+                    if maze.level.exit[0] == pixel_x + 1 && maze.level.exit[1] == pixel_y {
                         transform.translation.y = -3. * (WALL_HEIGHT / 4.);
                         transform = transform.with_scale(Vec3::new(
                             PIXEL_WORLD_SIZE,
@@ -72,10 +247,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                     }
-                    elements.push((labyrinth_materials.wall.clone(), transform));
+                    elements.push((wall.clone(), transform));
                 }
                 // -x
-                if pixel_x > 0 && data.get((pixel - 1) * 4).unwrap() < &50 {
+                if pixel_x > 0 && maze.is_wall(pixel_x - 1, pixel_y) {
                     let mut transform = Transform::from_translation(Vec3::new(
                         pixel_x as f32 * PIXEL_WORLD_SIZE
                             - world_width / 2.
@@ -85,7 +260,7 @@ fn spawn_map(
                     ));
                     transform = transform.with_scale(Vec3::new(PIXEL_WORLD_SIZE, 1.0, WALL_HEIGHT));
                     transform = transform.looking_at(transform.translation + Vec3::Y, Vec3::X);
-                    if maze_level.exit[0] == pixel_x - 1 && maze_level.exit[1] == pixel_y {
+                    if maze.level.exit[0] == pixel_x - 1 && maze.level.exit[1] == pixel_y {
                         transform.translation.y = -3. * (WALL_HEIGHT / 4.);
                         transform = transform.with_scale(Vec3::new(
                             PIXEL_WORLD_SIZE,
@@ -93,12 +268,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                     }
-                    elements.push((labyrinth_materials.wall.clone(), transform));
+                    elements.push((wall.clone(), transform));
                 }
                 // +y
-                if pixel_y < pixel_per_row - 1
-                    && data.get((pixel + pixel_per_row) * 4).unwrap() < &50
-                {
+                if pixel_y < pixel_per_row - 1 && maze.is_wall(pixel_x, pixel_y + 1) {
                     let mut transform = Transform::from_translation(Vec3::new(
                         pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                         -WALL_HEIGHT / 2.,
@@ -107,7 +280,7 @@ fn spawn_map(
                     ));
                     transform = transform.with_scale(Vec3::new(PIXEL_WORLD_SIZE, 1.0, WALL_HEIGHT));
                     transform = transform.looking_at(transform.translation - Vec3::Y, -Vec3::Z);
-                    if maze_level.exit[0] == pixel_x && maze_level.exit[1] == pixel_y + 1 {
+                    if maze.level.exit[0] == pixel_x && maze.level.exit[1] == pixel_y + 1 {
                         transform.translation.y = -3. * (WALL_HEIGHT / 4.);
                         transform = transform.with_scale(Vec3::new(
                             PIXEL_WORLD_SIZE,
@@ -115,10 +288,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                     }
-                    elements.push((labyrinth_materials.wall.clone(), transform));
+                    elements.push((wall.clone(), transform));
                 }
                 // -y
-                if pixel_y > 0 && data.get((pixel - pixel_per_row) * 4).unwrap() < &50 {
+                if pixel_y > 0 && maze.is_wall(pixel_x, pixel_y - 1) {
                     let mut transform = Transform::from_translation(Vec3::new(
                         pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                         -WALL_HEIGHT / 2.,
@@ -128,7 +301,7 @@ fn spawn_map(
                     ));
                     transform = transform.with_scale(Vec3::new(PIXEL_WORLD_SIZE, 1.0, WALL_HEIGHT));
                     transform = transform.looking_at(transform.translation + Vec3::Y, Vec3::Z);
-                    if maze_level.exit[0] == pixel_x && maze_level.exit[1] == pixel_y - 1 {
+                    if maze.level.exit[0] == pixel_x && maze.level.exit[1] == pixel_y - 1 {
                         transform.translation.y = -3. * (WALL_HEIGHT / 4.);
                         transform = transform.with_scale(Vec3::new(
                             PIXEL_WORLD_SIZE,
@@ -136,10 +309,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                     }
-                    elements.push((labyrinth_materials.wall.clone(), transform));
+                    elements.push((wall.clone(), transform));
                 }
             } else {
-                if maze_level.exit[0] == pixel_x && maze_level.exit[1] == pixel_y {
+                if maze.level.exit[0] == pixel_x && maze.level.exit[1] == pixel_y {
                     let mut transform = Transform::from_translation(Vec3::new(
                         pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                         -WALL_HEIGHT / 2.,
@@ -148,7 +321,7 @@ fn spawn_map(
                     transform = transform.with_scale(Vec3::splat(PIXEL_WORLD_SIZE));
                     elements.push((textures.grass.clone(), transform));
                     // +x
-                    if pixel_x < pixel_per_row - 1 && data.get((pixel + 1) * 4).unwrap() < &50 {
+                    if pixel_x < pixel_per_row - 1 && maze.is_wall(pixel_x + 1, pixel_y) {
                         let mut transform = Transform::from_translation(Vec3::new(
                             pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.
                                 + PIXEL_WORLD_SIZE / 2.,
@@ -161,10 +334,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                         transform = transform.looking_at(transform.translation - Vec3::Y, -Vec3::X);
-                        elements.push((labyrinth_materials.wall.clone(), transform));
+                        elements.push((wall.clone(), transform));
                     }
                     // -x
-                    if pixel_x > 0 && data.get((pixel - 1) * 4).unwrap() < &50 {
+                    if pixel_x > 0 && maze.is_wall(pixel_x - 1, pixel_y) {
                         let mut transform = Transform::from_translation(Vec3::new(
                             pixel_x as f32 * PIXEL_WORLD_SIZE
                                 - world_width / 2.
@@ -178,12 +351,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                         transform = transform.looking_at(transform.translation + Vec3::Y, Vec3::X);
-                        elements.push((labyrinth_materials.wall.clone(), transform));
+                        elements.push((wall.clone(), transform));
                     }
                     // +y
-                    if pixel_y < pixel_per_row - 1
-                        && data.get((pixel + pixel_per_row) * 4).unwrap() < &50
-                    {
+                    if pixel_y < pixel_per_row - 1 && maze.is_wall(pixel_x, pixel_y + 1) {
                         let mut transform = Transform::from_translation(Vec3::new(
                             pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                             -WALL_HEIGHT / 4.,
@@ -196,10 +367,10 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                         transform = transform.looking_at(transform.translation - Vec3::Y, -Vec3::Z);
-                        elements.push((labyrinth_materials.wall.clone(), transform));
+                        elements.push((wall.clone(), transform));
                     }
                     // -y
-                    if pixel_y > 0 && data.get((pixel - pixel_per_row) * 4).unwrap() < &50 {
+                    if pixel_y > 0 && maze.is_wall(pixel_x, pixel_y - 1) {
                         let mut transform = Transform::from_translation(Vec3::new(
                             pixel_x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
                             -WALL_HEIGHT / 4.,
@@ -213,7 +384,7 @@ fn spawn_map(
                             WALL_HEIGHT / 2.,
                         ));
                         transform = transform.looking_at(transform.translation + Vec3::Y, Vec3::Z);
-                        elements.push((labyrinth_materials.wall.clone(), transform));
+                        elements.push((wall.clone(), transform));
                     }
                 } else {
                     let mut transform = Transform::from_translation(Vec3::new(
@@ -235,18 +406,26 @@ fn spawn_map(
                 transform,
                 ..default()
             })
-            .insert(RayCastMesh::<MyRaycastSet>::default());
+            .insert(LevelEntity);
     }
 
-    commands.spawn_bundle(PointLightBundle {
-        point_light: PointLight {
-            intensity: 1500.0,
-            shadows_enabled: true,
+    commands
+        .spawn_bundle(PointLightBundle {
+            point_light: PointLight {
+                intensity: 1500.0,
+                shadows_enabled: true,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 5.0, 0.0),
             ..default()
-        },
-        transform: Transform::from_xyz(0.0, 5.0, 0.0),
-        ..default()
-    });
+        })
+        .insert(LevelEntity);
+}
+
+fn despawn_level(mut commands: Commands, entities: Query<Entity, With<LevelEntity>>) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 // #[derive(Debug, Copy, Clone)]
@@ -356,3 +535,31 @@ fn spawn_map(
 //         colors
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::maze;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    #[test]
+    fn pixels_from_50_on_are_floor() {
+        let level = maze(&["..", ".."], &[], [0, 0]).level;
+        let data = [49, 50, 51, 0]
+            .into_iter()
+            .flat_map(|red| [red, 0, 0, 255])
+            .collect();
+        let image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let maze = Maze::from_image(&image, level);
+        assert_eq!(maze.open, vec![false, true, true, false]);
+    }
+}
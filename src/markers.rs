@@ -0,0 +1,717 @@
+use crate::actions::{Action, CursorGrab};
+use crate::character::{CamInputState, Character, Controlled, LevelCompletedEvent};
+use crate::loading::MarkerBudget;
+use crate::map::{CurrentLevel, LevelEntity, Maze};
+use crate::notifications::{Notification, NotificationEvent, Priority};
+use crate::palette::CharacterMaterials;
+use crate::results::RunStats;
+use crate::savegame::{PendingLoad, RestoredRun};
+use crate::shape::Plane;
+use crate::simulation::{simulation_running, SimulationStage, SimulationSystem};
+use crate::storage;
+use crate::widgets::Typing;
+use crate::GameState;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use leafwing_input_manager::prelude::ActionState;
+use serde::{Deserialize, Serialize};
+
+/// Markers can only be placed and erased this close to the controlled character
+const REACH: f32 = 1.;
+/// Right clicking closer than this to a marker erases it
+const ERASE_DISTANCE: f32 = 0.08;
+/// Width of the strokes of arrows, crosses and numbers
+const STROKE: f32 = 0.018;
+
+pub struct MarkerPlugin;
+
+/// This plugin lets players paint markers onto walls and the floor and erase them again
+/// Markers are limited by the [MarkerBudget] of the level and stored per level,
+/// so they are still there when coming back to an unfinished level.
+/// Runs continued from a save game bring their own markers and leave those of the level alone.
+/// In levels with private markers, a character only sees the markers of its parts.
+impl Plugin for MarkerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MarkerMeshes>()
+            .init_resource::<MarkerTarget>()
+            .init_resource::<ActiveMarker>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing)
+                    .with_system(restore_markers)
+                    .with_system(spawn_marker_cursor),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(cycle_active_marker)
+                    .with_system(aim_markers.after(cycle_active_marker))
+                    .with_system(place_marker.after(aim_markers))
+                    .with_system(erase_marker.after(aim_markers))
+                    .with_system(show_known_markers),
+            )
+            // the level is completed in the simulation, which runs after the update stage
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(simulation_running)
+                    .with_system(forget_markers.after(SimulationSystem::Gameplay)),
+            )
+            // placed and erased markers are only visible after the update stage
+            .add_system_set_to_stage(
+                CoreStage::PostUpdate,
+                SystemSet::on_update(GameState::Playing).with_system(save_markers),
+            );
+    }
+}
+
+/// A marker painted by the part with the given number
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Marker {
+    pub owner: u8,
+    pub kind: MarkerKind,
+}
+
+impl Marker {
+    /// Whether a character made of `numbers` can see this marker
+    ///
+    /// Combining characters merges what they know, since all their numbers are in one character.
+    pub fn known_by(&self, numbers: &[u8], private_markers: bool) -> bool {
+        !private_markers || numbers.contains(&self.owner)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MarkerKind {
+    #[default]
+    Dot,
+    /// Points along the surface in the direction the player was looking
+    Arrow,
+    Cross,
+    /// Numbers count up per character, so they can be used to number junctions
+    Number(u8),
+}
+
+impl MarkerKind {
+    /// The marker types players can cycle through
+    const PALETTE: [MarkerKind; 4] = [
+        MarkerKind::Dot,
+        MarkerKind::Arrow,
+        MarkerKind::Cross,
+        MarkerKind::Number(1),
+    ];
+
+    fn palette_index(&self) -> usize {
+        match self {
+            MarkerKind::Dot => 0,
+            MarkerKind::Arrow => 1,
+            MarkerKind::Cross => 2,
+            MarkerKind::Number(_) => 3,
+        }
+    }
+
+    fn cycle(&self, steps: isize) -> MarkerKind {
+        let length = MarkerKind::PALETTE.len() as isize;
+        let index = (self.palette_index() as isize + steps).rem_euclid(length);
+        MarkerKind::PALETTE[index as usize]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            MarkerKind::Dot => "Dot",
+            MarkerKind::Arrow => "Arrow",
+            MarkerKind::Cross => "Cross",
+            MarkerKind::Number(_) => "Number",
+        }
+    }
+}
+
+/// The marker type placed with the next click
+#[derive(Default)]
+struct ActiveMarker(MarkerKind);
+
+/// Markers of a level as they are written to disk
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SavedMarkers {
+    pub markers: Vec<SavedMarker>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedMarker {
+    pub owner: u8,
+    #[serde(default)]
+    pub kind: MarkerKind,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl SavedMarker {
+    pub fn new(marker: &Marker, transform: &Transform) -> Self {
+        SavedMarker {
+            owner: marker.owner,
+            kind: marker.kind,
+            translation: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+        }
+    }
+}
+
+struct MarkerMeshes {
+    dot: Handle<Mesh>,
+    arrow: Handle<Mesh>,
+    cross: Handle<Mesh>,
+    /// Digits 0 to 9
+    digits: Vec<Handle<Mesh>>,
+}
+
+impl MarkerMeshes {
+    fn get(&self, kind: MarkerKind) -> Handle<Mesh> {
+        match kind {
+            MarkerKind::Dot => self.dot.clone(),
+            MarkerKind::Arrow => self.arrow.clone(),
+            MarkerKind::Cross => self.cross.clone(),
+            MarkerKind::Number(number) => self.digits[number as usize % 10].clone(),
+        }
+    }
+}
+
+impl FromWorld for MarkerMeshes {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let arrow = [
+            stroke(Vec2::new(0., 0.045), Vec2::new(0., -0.01)),
+            vec![[
+                Vec2::new(0., -0.05),
+                Vec2::new(-0.035, -0.01),
+                Vec2::new(0.035, -0.01),
+            ]],
+        ]
+        .concat();
+        let cross = [
+            stroke(Vec2::new(-0.035, -0.035), Vec2::new(0.035, 0.035)),
+            stroke(Vec2::new(-0.035, 0.035), Vec2::new(0.035, -0.035)),
+        ]
+        .concat();
+        MarkerMeshes {
+            dot: meshes.add(Plane { size: 0.1 }.into()),
+            arrow: meshes.add(flat_mesh(arrow)),
+            cross: meshes.add(flat_mesh(cross)),
+            digits: (0..10).map(|digit| meshes.add(digit_mesh(digit))).collect(),
+        }
+    }
+}
+
+/// A flat mesh facing up from triangles given as (x, z)
+///
+/// In marker space, -z is the direction arrows point to and the top of numbers.
+fn flat_mesh(triangles: Vec<[Vec2; 3]>) -> Mesh {
+    let mut positions = vec![];
+    for [a, b, c] in triangles {
+        // keep the front face pointing up
+        let (b, c) = if (b - a).perp_dot(c - a) > 0. {
+            (c, b)
+        } else {
+            (b, c)
+        };
+        positions.extend([a, b, c].map(|corner| [corner.x, 0., corner.y]));
+    }
+    let uvs: Vec<[f32; 2]> = positions
+        .iter()
+        .map(|position| [position[0] + 0.5, position[2] + 0.5])
+        .collect();
+    let normals = vec![[0., 1., 0.]; positions.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh
+}
+
+/// A line of [STROKE] width as two triangles
+fn stroke(from: Vec2, to: Vec2) -> Vec<[Vec2; 3]> {
+    let side = (to - from).normalize().perp() * STROKE / 2.;
+    vec![
+        [from - side, from + side, to + side],
+        [from - side, to + side, to - side],
+    ]
+}
+
+/// A seven segment digit
+fn digit_mesh(digit: u8) -> Mesh {
+    let (right, bottom) = (0.025, 0.04);
+    let corners = [
+        Vec2::new(-right, -bottom),
+        Vec2::new(right, -bottom),
+        Vec2::new(-right, 0.),
+        Vec2::new(right, 0.),
+        Vec2::new(-right, bottom),
+        Vec2::new(right, bottom),
+    ];
+    // top, top right, bottom right, bottom, bottom left, top left, middle
+    let segments = [(0, 1), (1, 3), (3, 5), (4, 5), (2, 4), (0, 2), (2, 3)];
+    let lit: &[usize] = match digit {
+        0 => &[0, 1, 2, 3, 4, 5],
+        1 => &[1, 2],
+        2 => &[0, 1, 6, 4, 3],
+        3 => &[0, 1, 6, 2, 3],
+        4 => &[5, 6, 1, 2],
+        5 => &[0, 5, 6, 2, 3],
+        6 => &[0, 5, 4, 3, 2, 6],
+        7 => &[0, 1, 2],
+        8 => &[0, 1, 2, 3, 4, 5, 6],
+        _ => &[0, 1, 2, 3, 5, 6],
+    };
+    flat_mesh(
+        lit.iter()
+            .flat_map(|segment| {
+                let (from, to) = segments[*segment];
+                stroke(corners[from], corners[to])
+            })
+            .collect(),
+    )
+}
+
+/// Rotation of a marker on a surface with the given normal
+///
+/// Arrows and the top of numbers point along the surface in the direction the player is looking.
+/// When looking straight at a wall they point up, when looking straight down they point ahead.
+pub fn marker_rotation(normal: Vec3, yaw: f32, pitch: f32) -> Quat {
+    let normal = normal.normalize();
+    let horizontal = Quat::from_axis_angle(Vec3::Y, yaw);
+    let facing = horizontal * Quat::from_axis_angle(Vec3::X, pitch) * -Vec3::Z;
+    let along_surface = |direction: Vec3| direction - normal * direction.dot(normal);
+    let mut direction = along_surface(facing);
+    if direction.length_squared() < 0.01 {
+        direction = if normal.y.abs() > 0.5 {
+            along_surface(horizontal * -Vec3::Z)
+        } else {
+            along_surface(Vec3::Y)
+        };
+    }
+    let z = -direction.normalize();
+    Quat::from_mat3(&Mat3::from_cols(normal.cross(z), normal, z))
+}
+
+/// Where and which marker would be placed this frame, if the cursor is in reach
+#[derive(Default)]
+pub struct MarkerTarget(pub Option<(Transform, Marker)>);
+
+impl MarkerBudget {
+    /// Whether a character containing `numbers` may place another marker
+    pub fn allows(&self, numbers: &[u8], markers: impl Iterator<Item = Marker>) -> bool {
+        match *self {
+            MarkerBudget::PerCharacter(per_part) => {
+                markers
+                    .filter(|marker| numbers.contains(&marker.owner))
+                    .count()
+                    < per_part * numbers.len()
+            }
+            MarkerBudget::PerLevel(total) => markers.count() < total,
+        }
+    }
+}
+
+/// Where the markers of the level are stored, relative to the save directory
+pub fn marker_file(level: &CurrentLevel) -> String {
+    format!("markers/{}.ron", level.0)
+}
+
+fn marker_material(materials: &CharacterMaterials, marker: &Marker) -> Handle<StandardMaterial> {
+    match marker.kind {
+        MarkerKind::Dot => materials.marker(marker.owner),
+        _ => materials.body(marker.owner),
+    }
+}
+
+/// The semi transparent material of the marker preview
+fn marker_preview_material(
+    materials: &CharacterMaterials,
+    marker: &Marker,
+) -> Handle<StandardMaterial> {
+    match marker.kind {
+        MarkerKind::Dot => materials.marker_preview(marker.owner),
+        _ => materials.body(marker.owner),
+    }
+}
+
+fn spawn_marker(
+    commands: &mut Commands,
+    meshes: &MarkerMeshes,
+    character_materials: &CharacterMaterials,
+    marker: Marker,
+    transform: Transform,
+) {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.get(marker.kind),
+            transform,
+            material: marker_material(character_materials, &marker),
+            ..default()
+        })
+        .insert(NotShadowCaster)
+        .insert(LevelEntity)
+        .insert(marker);
+}
+
+/// Spawns the markers of a loaded save game, or else those stored for the level
+fn restore_markers(
+    mut commands: Commands,
+    level: Res<CurrentLevel>,
+    pending: Option<Res<PendingLoad>>,
+    meshes: Res<MarkerMeshes>,
+    character_materials: Res<CharacterMaterials>,
+) {
+    let markers = match &pending {
+        Some(pending) => pending.0.markers.clone(),
+        None => match storage::load::<SavedMarkers>(marker_file(&level)) {
+            Ok(saved) => saved.markers,
+            Err(error) => {
+                if !error.is_not_found() {
+                    warn!("Failed to load the markers of level {}: {}", level.0, error);
+                }
+                return;
+            }
+        },
+    };
+    for saved in markers {
+        spawn_marker(
+            &mut commands,
+            &meshes,
+            &character_materials,
+            Marker {
+                owner: saved.owner,
+                kind: saved.kind,
+            },
+            Transform::from_translation(Vec3::from(saved.translation))
+                .with_rotation(Quat::from_array(saved.rotation)),
+        );
+    }
+}
+
+/// Preview of the marker that would be placed where the controlled character looks
+#[derive(Component)]
+struct MarkerCursor;
+
+fn spawn_marker_cursor(mut commands: Commands, meshes: Res<MarkerMeshes>) {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.get(MarkerKind::Dot),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(NotShadowCaster)
+        .insert(LevelEntity)
+        .insert(MarkerCursor);
+}
+
+/// Moves the marker preview to the spot the controlled character looks at while it is in reach
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn aim_markers(
+    mut cursor: Query<
+        (
+            &mut Handle<Mesh>,
+            &mut Handle<StandardMaterial>,
+            &mut Transform,
+            &mut Visibility,
+        ),
+        (With<MarkerCursor>, Without<Controlled>),
+    >,
+    character_materials: Res<CharacterMaterials>,
+    current_character: Query<(&Character, &Transform), With<Controlled>>,
+    cam_input_state: Res<CamInputState>,
+    maze: Res<Maze>,
+    meshes: Res<MarkerMeshes>,
+    active: Res<ActiveMarker>,
+    markers: Query<&Marker>,
+    mut target: ResMut<MarkerTarget>,
+    typing: Res<Typing>,
+) {
+    target.0 = None;
+    let (mut cursor_mesh, mut material, mut transform, mut visibility) =
+        match cursor.get_single_mut() {
+            Ok(cursor) => cursor,
+            Err(_) => return,
+        };
+    let (character, char_transform) = current_character.single();
+    // the camera follows the character after this, so look along the current input instead
+    let look = Quat::from_axis_angle(Vec3::Y, cam_input_state.yaw)
+        * Quat::from_axis_angle(Vec3::X, cam_input_state.pitch);
+    let hit = maze.raycast(char_transform.translation, look * -Vec3::Z, REACH);
+    visibility.is_visible = hit.is_some();
+    let hit = match hit {
+        Some(hit) => hit,
+        None => return,
+    };
+    transform.translation = hit.point + hit.normal * 0.005;
+    transform.rotation = marker_rotation(hit.normal, cam_input_state.yaw, cam_input_state.pitch);
+    let preview = Marker {
+        owner: character.numbers()[0],
+        kind: next_kind(active.0, character, markers.iter()),
+    };
+    *cursor_mesh = meshes.get(preview.kind);
+    *material = marker_preview_material(&character_materials, &preview);
+    // clicks while typing belong to the text field
+    if !typing.0 {
+        target.0 = Some((*transform, preview));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn place_marker(
+    mut commands: Commands,
+    input: Res<Input<MouseButton>>,
+    target: Res<MarkerTarget>,
+    maze: Res<Maze>,
+    meshes: Res<MarkerMeshes>,
+    character_materials: Res<CharacterMaterials>,
+    current_character: Query<&Character, With<Controlled>>,
+    markers: Query<&Marker>,
+    mut notifications: EventWriter<NotificationEvent>,
+    mut stats: ResMut<RunStats>,
+) {
+    let (transform, marker) = match target.0 {
+        Some(target) if input.just_pressed(MouseButton::Left) => target,
+        _ => return,
+    };
+    let character = current_character.single();
+    if !maze
+        .level
+        .marker_budget
+        .allows(character.numbers(), markers.iter().copied())
+    {
+        notifications.send(NotificationEvent::Show(Notification::toast(
+            "markers",
+            "No markers left",
+            3.,
+        )));
+        return;
+    }
+    spawn_marker(
+        &mut commands,
+        &meshes,
+        &character_materials,
+        marker,
+        transform,
+    );
+    stats.markers_placed += 1;
+}
+
+/// The kind of marker placed next, counting numbers up for each character
+fn next_kind<'a>(
+    active: MarkerKind,
+    character: &Character,
+    markers: impl Iterator<Item = &'a Marker>,
+) -> MarkerKind {
+    if let MarkerKind::Number(_) = active {
+        let highest = markers
+            .filter(|marker| character.numbers().contains(&marker.owner))
+            .filter_map(|marker| match marker.kind {
+                MarkerKind::Number(number) => Some(number),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        MarkerKind::Number(highest % 9 + 1)
+    } else {
+        active
+    }
+}
+
+fn cycle_active_marker(
+    action_state: Res<ActionState<Action>>,
+    cursor_grab: Res<CursorGrab>,
+    typing: Res<Typing>,
+    mut active: ResMut<ActiveMarker>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    if !cursor_grab.locked || typing.0 {
+        return;
+    }
+    let steps = if action_state.just_pressed(Action::NextMarker) {
+        1
+    } else if action_state.just_pressed(Action::PreviousMarker) {
+        -1
+    } else {
+        return;
+    };
+    active.0 = active.0.cycle(steps);
+    notifications.send(NotificationEvent::Show(
+        Notification::toast("active_marker", format!("Marker: {}", active.0.name()), 2.)
+            .with_priority(Priority::Low),
+    ));
+}
+
+fn erase_marker(
+    mut commands: Commands,
+    input: Res<Input<MouseButton>>,
+    target: Res<MarkerTarget>,
+    maze: Res<Maze>,
+    current_character: Query<&Character, With<Controlled>>,
+    markers: Query<(Entity, &Marker, &Transform)>,
+) {
+    let (target, _) = match target.0 {
+        Some(target) if input.just_pressed(MouseButton::Right) => target,
+        _ => return,
+    };
+    let numbers = current_character.single().numbers();
+    let closest = markers
+        .iter()
+        .filter(|(_, marker, _)| marker.known_by(numbers, maze.level.private_markers))
+        .map(|(entity, _, transform)| (entity, transform.translation.distance(target.translation)))
+        .filter(|(_, distance)| *distance < ERASE_DISTANCE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((entity, _)) = closest {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn show_known_markers(
+    maze: Res<Maze>,
+    current_character: Query<&Character, With<Controlled>>,
+    mut markers: Query<(&Marker, &mut Visibility)>,
+) {
+    let numbers = match current_character.get_single() {
+        Ok(character) => character.numbers(),
+        Err(_) => return,
+    };
+    for (marker, mut visibility) in &mut markers {
+        let known = marker.known_by(numbers, maze.level.private_markers);
+        if visibility.is_visible != known {
+            visibility.is_visible = known;
+        }
+    }
+}
+
+fn save_markers(
+    level: Res<CurrentLevel>,
+    pending: Option<Res<PendingLoad>>,
+    restored: Option<Res<RestoredRun>>,
+    added: Query<(), Added<Marker>>,
+    removed: RemovedComponents<Marker>,
+    markers: Query<(&Marker, &Transform)>,
+) {
+    // the markers of a save game are kept in its slot
+    if pending.is_some() || restored.is_some() {
+        return;
+    }
+    if added.is_empty() && removed.iter().next().is_none() {
+        return;
+    }
+    let saved = SavedMarkers {
+        markers: markers
+            .iter()
+            .map(|(marker, transform)| SavedMarker::new(marker, transform))
+            .collect(),
+    };
+    if let Err(error) = storage::save(marker_file(&level), &saved) {
+        warn!("Failed to save markers: {}", error);
+    }
+}
+
+/// A completed level starts without markers the next time
+fn forget_markers(
+    mut events: EventReader<LevelCompletedEvent>,
+    level: Res<CurrentLevel>,
+    restored: Option<Res<RestoredRun>>,
+) {
+    if events.iter().last().is_none() || restored.is_some() {
+        return;
+    }
+    if let Err(error) = storage::remove(marker_file(&level)) {
+        warn!(
+            "Failed to remove the markers of level {}: {}",
+            level.0, error
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Direction;
+    use crate::testing::{maze, yaw, TestGame};
+
+    fn owned_by(owners: &[u8]) -> impl Iterator<Item = Marker> + '_ {
+        owners.iter().map(|owner| Marker {
+            owner: *owner,
+            kind: MarkerKind::Dot,
+        })
+    }
+
+    #[test]
+    fn combined_characters_share_their_budget() {
+        let budget = MarkerBudget::PerCharacter(2);
+        assert!(budget.allows(&[1], owned_by(&[1, 2, 2])));
+        assert!(!budget.allows(&[1], owned_by(&[1, 1])));
+        assert!(budget.allows(&[1, 2], owned_by(&[1, 1, 1])));
+        assert!(!budget.allows(&[1, 2], owned_by(&[1, 1, 1, 2])));
+    }
+
+    #[test]
+    fn level_budget_counts_all_markers() {
+        let budget = MarkerBudget::PerLevel(3);
+        assert!(budget.allows(&[1], owned_by(&[2, 3])));
+        assert!(!budget.allows(&[1], owned_by(&[2, 3, 3])));
+    }
+
+    #[test]
+    fn arrows_on_the_floor_point_where_the_player_looks() {
+        let east = -std::f32::consts::FRAC_PI_2;
+        let rotation = marker_rotation(Vec3::Y, east, -0.7);
+        assert!((rotation * -Vec3::Z).abs_diff_eq(Vec3::X, 0.001));
+        assert!((rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 0.001));
+    }
+
+    #[test]
+    fn arrows_on_walls_stay_on_the_wall() {
+        // looking north east at a wall facing west
+        let rotation = marker_rotation(-Vec3::X, -std::f32::consts::FRAC_PI_4, 0.);
+        assert!((rotation * -Vec3::Z).abs_diff_eq(-Vec3::Z, 0.001));
+        assert!((rotation * Vec3::Y).abs_diff_eq(-Vec3::X, 0.001));
+
+        // looking straight at it
+        let rotation = marker_rotation(-Vec3::X, -std::f32::consts::FRAC_PI_2, 0.);
+        assert!((rotation * -Vec3::Z).abs_diff_eq(Vec3::Y, 0.001));
+    }
+
+    #[test]
+    fn cycling_wraps_around() {
+        assert_eq!(MarkerKind::Dot.cycle(-1), MarkerKind::Number(1));
+        assert_eq!(MarkerKind::Number(4).cycle(1), MarkerKind::Dot);
+        assert_eq!(MarkerKind::Arrow.cycle(1), MarkerKind::Cross);
+    }
+
+    #[test]
+    fn private_markers_are_shared_by_combining() {
+        let marker = Marker {
+            owner: 2,
+            kind: MarkerKind::Dot,
+        };
+        assert!(marker.known_by(&[1], false));
+        assert!(!marker.known_by(&[1], true));
+        assert!(marker.known_by(&[2], true));
+        assert!(marker.known_by(&[1, 2], true));
+    }
+
+    #[test]
+    fn completing_a_level_forgets_its_markers() {
+        let rows = [
+            "#######", "#######", "#######", "#.....#", "#######", "#######", "#######",
+        ];
+        let mut game = TestGame::new(maze(&rows, &[[5, 3]], [6, 3]));
+        let level = CurrentLevel("forget-markers".to_owned());
+        storage::save(marker_file(&level), &SavedMarkers { markers: vec![] }).unwrap();
+        game.app.insert_resource(level).add_system_to_stage(
+            SimulationStage,
+            forget_markers.after(SimulationSystem::Gameplay),
+        );
+
+        game.look(yaw(Direction::East));
+        game.hold(Action::Forward);
+        game.step(60);
+        assert!(game.completed());
+        let level = game.app.world.resource::<CurrentLevel>();
+        assert!(storage::load_text(marker_file(level))
+            .unwrap_err()
+            .is_not_found());
+    }
+}
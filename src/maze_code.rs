@@ -0,0 +1,342 @@
+use crate::levels::{maze_from_grid, LevelError, Levels};
+use crate::loading::{LabyrinthLevel, MarkerBudget};
+use crate::map::{CurrentLevel, Maze};
+use crate::notifications::{Notification, NotificationEvent};
+use bevy::prelude::*;
+use std::fmt::{Display, Formatter};
+
+/// Every code starts with this, so codes can be told apart from other text in a chat
+const PREFIX: &str = "blub-";
+/// Bump this whenever the encoding changes
+const CODE_VERSION: u8 = 1;
+/// Larger mazes are rejected instead of allocating whatever a code claims
+const MAX_SIZE: usize = 256;
+
+pub struct MazeCodePlugin;
+
+/// This plugin copies the code of the current level, see [encode] for the format
+/// There is no maze generator yet, so every level is shared with its full maze;
+/// generated mazes could share their seed instead once they exist.
+impl Plugin for MazeCodePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CopyMazeCodeEvent>()
+            .add_system(copy_maze_code);
+    }
+}
+
+/// Copies the code of the current level to the clipboard
+pub struct CopyMazeCodeEvent;
+
+#[derive(Debug)]
+pub enum MazeCodeError {
+    /// The text does not start with the prefix of maze codes
+    NotACode,
+    /// The text contains characters that base64 does not use
+    Encoding,
+    /// The checksum does not match, usually because a character was changed or lost
+    Corrupted,
+    /// The code ends before the maze is complete
+    Truncated,
+    /// Written by a newer version of the game
+    Newer(u8),
+    /// The code is intact, but does not describe a valid level
+    Invalid(LevelError),
+}
+
+impl Display for MazeCodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MazeCodeError::NotACode => write!(f, "maze codes start with \"{}\"", PREFIX),
+            MazeCodeError::Encoding => write!(f, "the code contains invalid characters"),
+            MazeCodeError::Corrupted => write!(f, "the code is corrupted, check for typos"),
+            MazeCodeError::Truncated => write!(f, "the code is incomplete"),
+            MazeCodeError::Newer(version) => write!(
+                f,
+                "the code has version {}, but only {} is supported",
+                version, CODE_VERSION
+            ),
+            MazeCodeError::Invalid(error) => write!(f, "the maze is invalid: {}", error),
+        }
+    }
+}
+
+/// Encodes the maze, its spawns, exit and marker rules as a short text
+///
+/// The bytes are the version, the size, the spawns as `f32`s, the exit, the marker rules
+/// and the tiles as alternating run lengths starting with walls, followed by a CRC32
+/// of all of them. Numbers are LEB128 varints. The bytes are written as URL safe base64.
+pub fn encode(maze: &Maze) -> String {
+    let level = &maze.level;
+    let mut bytes = vec![CODE_VERSION];
+    write_number(&mut bytes, maze.size);
+    write_number(&mut bytes, level.spawns.len());
+    for spawn in &level.spawns {
+        for coordinate in spawn {
+            bytes.extend(coordinate.to_le_bytes());
+        }
+    }
+    write_number(&mut bytes, level.exit[0]);
+    write_number(&mut bytes, level.exit[1]);
+    let (kind, markers) = match level.marker_budget {
+        MarkerBudget::PerCharacter(markers) => (0, markers),
+        MarkerBudget::PerLevel(markers) => (1, markers),
+    };
+    bytes.push(kind);
+    write_number(&mut bytes, markers);
+    bytes.push(level.private_markers.into());
+    let mut wall = true;
+    let mut run = 0;
+    for y in 0..maze.size {
+        for x in 0..maze.size {
+            if maze.is_wall(x, y) != wall {
+                write_number(&mut bytes, run);
+                wall = !wall;
+                run = 0;
+            }
+            run += 1;
+        }
+    }
+    write_number(&mut bytes, run);
+    bytes.extend(crc32fast::hash(&bytes).to_le_bytes());
+    format!(
+        "{}{}",
+        PREFIX,
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// Reads a maze from a code written by [encode]
+pub fn decode(code: &str) -> Result<Maze, MazeCodeError> {
+    let data = code
+        .trim()
+        .strip_prefix(PREFIX)
+        .ok_or(MazeCodeError::NotACode)?;
+    let bytes = base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| MazeCodeError::Encoding)?;
+    if bytes.len() < 4 {
+        return Err(MazeCodeError::Truncated);
+    }
+    let (content, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32fast::hash(content).to_le_bytes() != checksum {
+        return Err(MazeCodeError::Corrupted);
+    }
+    let mut reader = Reader(content);
+    let version = reader.byte()?;
+    if version != CODE_VERSION {
+        return Err(MazeCodeError::Newer(version));
+    }
+    let size = reader.number()?;
+    if size > MAX_SIZE {
+        return Err(MazeCodeError::Invalid(LevelError::Invalid(format!(
+            "the maze is {} tiles wide, but at most {} are supported",
+            size, MAX_SIZE
+        ))));
+    }
+    let spawns = (0..reader.number()?)
+        .map(|_| Ok([reader.float()?, reader.float()?]))
+        .collect::<Result<_, MazeCodeError>>()?;
+    let exit = [reader.number()?, reader.number()?];
+    let marker_budget = match (reader.byte()?, reader.number()?) {
+        (0, markers) => MarkerBudget::PerCharacter(markers),
+        (1, markers) => MarkerBudget::PerLevel(markers),
+        _ => return Err(MazeCodeError::Corrupted),
+    };
+    let private_markers = reader.byte()? != 0;
+    let mut tiles = String::with_capacity(size * size);
+    let mut wall = true;
+    while tiles.len() < size * size {
+        let run = reader.number()?;
+        if run > size * size - tiles.len() {
+            return Err(MazeCodeError::Corrupted);
+        }
+        tiles.extend(std::iter::repeat_n(if wall { '#' } else { '.' }, run));
+        wall = !wall;
+    }
+    if !reader.0.is_empty() {
+        return Err(MazeCodeError::Corrupted);
+    }
+    let rows: Vec<String> = (0..size)
+        .map(|y| tiles[y * size..(y + 1) * size].to_owned())
+        .collect();
+    let level = LabyrinthLevel {
+        image: None,
+        spawns,
+        exit,
+        marker_budget,
+        private_markers,
+    };
+    maze_from_grid(&rows, level).map_err(MazeCodeError::Invalid)
+}
+
+/// Adds the maze of the code as a level and returns its name
+///
+/// The name is a hash of the code, so importing the same code again finds the same level.
+pub fn import(code: &str, levels: &mut Levels) -> Result<String, MazeCodeError> {
+    let maze = decode(code)?;
+    let name = format!("Code {:08x}", crc32fast::hash(encode(&maze).as_bytes()));
+    if levels.get(&name).is_none() {
+        levels.add(&name, maze);
+    }
+    Ok(name)
+}
+
+fn write_number(bytes: &mut Vec<u8>, mut number: usize) {
+    while number >= 0x80 {
+        bytes.push(number as u8 | 0x80);
+        number >>= 7;
+    }
+    bytes.push(number as u8);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, MazeCodeError> {
+        let (first, rest) = self.0.split_first().ok_or(MazeCodeError::Truncated)?;
+        self.0 = rest;
+        Ok(*first)
+    }
+
+    fn number(&mut self) -> Result<usize, MazeCodeError> {
+        let mut number = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            number |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or(MazeCodeError::Corrupted)?;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        Err(MazeCodeError::Corrupted)
+    }
+
+    fn float(&mut self) -> Result<f32, MazeCodeError> {
+        let mut bytes = [0; 4];
+        for byte in &mut bytes {
+            *byte = self.byte()?;
+        }
+        Ok(f32::from_le_bytes(bytes))
+    }
+}
+
+/// Reads the text of the clipboard, `None` where there is no clipboard
+pub fn paste() -> Option<String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => Some(text),
+            Err(error) => {
+                warn!("Failed to read the clipboard: {}", error);
+                None
+            }
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        None
+    }
+}
+
+/// Copies the text to the clipboard, returns false where there is no clipboard
+fn copy(text: String) -> bool {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+            Ok(()) => true,
+            Err(error) => {
+                warn!("Failed to copy to the clipboard: {}", error);
+                false
+            }
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = text;
+        false
+    }
+}
+
+fn copy_maze_code(
+    mut events: EventReader<CopyMazeCodeEvent>,
+    level: Res<CurrentLevel>,
+    maze: Option<Res<Maze>>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+    let maze = match maze {
+        Some(maze) => maze,
+        None => return,
+    };
+    let code = encode(&maze);
+    // the log is the only way to get the code without a clipboard
+    info!("Code of level {}: {}", level.0, code);
+    let text = if copy(code) {
+        format!("Copied the code of level {}", level.0)
+    } else {
+        "The code of the level is in the log".to_owned()
+    };
+    notifications.send(NotificationEvent::Show(Notification::toast(
+        "maze-code",
+        text,
+        3.,
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levels::grid;
+    use crate::testing::{maze, shipped_levels};
+
+    #[test]
+    fn codes_round_trip_exactly() {
+        let mut levels = shipped_levels();
+        let mut custom = maze(&["#.", ".."], &[[1, 0], [0, 1]], [1, 1]);
+        custom.level.marker_budget = MarkerBudget::PerLevel(300);
+        custom.level.private_markers = true;
+        levels.push(("custom".to_owned(), custom));
+        for (name, maze) in levels {
+            let code = encode(&maze);
+            let decoded = decode(&code).unwrap_or_else(|error| panic!("{}: {}", name, error));
+            assert_eq!(grid(&decoded), grid(&maze), "{}", name);
+            assert_eq!(decoded.level.spawns, maze.level.spawns, "{}", name);
+            assert_eq!(decoded.level.exit, maze.level.exit, "{}", name);
+            assert_eq!(decoded.level.marker_budget, maze.level.marker_budget);
+            assert_eq!(decoded.level.private_markers, maze.level.private_markers);
+            assert_eq!(encode(&decoded), code, "{}", name);
+        }
+    }
+
+    #[test]
+    fn corrupted_codes_are_rejected() {
+        let code = encode(&maze(&["#.", ".."], &[[1, 0]], [1, 1]));
+        let mut typo: Vec<char> = code.chars().collect();
+        let index = PREFIX.len() + 2;
+        typo[index] = if typo[index] == 'A' { 'B' } else { 'A' };
+        let typo: String = typo.into_iter().collect();
+        assert!(matches!(decode(&typo), Err(MazeCodeError::Corrupted)));
+        assert!(matches!(
+            decode(&code[..code.len() - 3]),
+            Err(MazeCodeError::Corrupted | MazeCodeError::Encoding)
+        ));
+        assert!(matches!(
+            decode(&format!("{}!", code)),
+            Err(MazeCodeError::Encoding)
+        ));
+        assert!(matches!(decode("hello"), Err(MazeCodeError::NotACode)));
+        assert!(matches!(decode(PREFIX), Err(MazeCodeError::Truncated)));
+        assert!(decode(&format!("  {}\n", code)).is_ok());
+    }
+
+    #[test]
+    fn importing_a_code_twice_adds_one_level() {
+        let code = encode(&maze(&["#.", ".."], &[[1, 0]], [1, 1]));
+        let mut levels = Levels::default();
+        let name = import(&code, &mut levels).unwrap();
+        assert_eq!(import(&code, &mut levels).unwrap(), name);
+        assert_eq!(levels.len(), 1);
+    }
+}
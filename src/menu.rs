@@ -1,111 +1,491 @@
 use crate::character::{FlyCam, PLAYER_Y};
+use crate::ghost::format_time;
+use crate::levels::Levels;
 use crate::loading::FontAssets;
-use crate::map::{MyRaycastSet, PIXEL_WORLD_SIZE};
-use crate::GameState;
+use crate::map::{CurrentLevel, PIXEL_WORLD_SIZE};
+use crate::maze_code;
+use crate::results::PersonalBests;
+use crate::savegame::{load_slot, slot_label, SaveGame, SAVE_SLOTS};
+use crate::settings::{Setting, Settings};
+use crate::widgets::{spawn_label, spawn_widget, ButtonColors, Widget, WidgetEvent, WidgetValue};
+use crate::{Difficulty, GameState};
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::app::AppExit;
 use bevy::prelude::*;
-use bevy_mod_raycast::{RayCastMethod, RayCastSource};
+
+const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+/// Embedded, so the credits are also shown in the browser
+const CREDITS: &str = include_str!("../credits/CREDITS.md");
+/// Long enough for the code of a large maze
+const MAX_CODE_LENGTH: usize = 2000;
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
-/// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
+/// This plugin is responsible for the main menu
+/// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited.
+/// It consists of pages (see [MenuPage]), which are rebuilt whenever the page changes.
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<ButtonColors>()
+        app.init_resource::<MenuPage>()
+            .init_resource::<MenuMessage>()
             .add_system_set(SystemSet::on_enter(GameState::Menu).with_system(setup_menu))
-            .add_system_set(SystemSet::on_update(GameState::Menu).with_system(click_play_button))
+            .add_system_set(
+                SystemSet::on_update(GameState::Menu)
+                    .with_system(show_menu_page)
+                    .with_system(use_menu_widgets)
+                    .with_system(leave_page),
+            )
             .add_system_set(SystemSet::on_exit(GameState::Menu).with_system(cleanup_menu));
     }
 }
 
-pub struct ButtonColors {
-    pub normal: UiColor,
-    pub hovered: UiColor,
+/// Everything that is despawned when the menu is left
+#[derive(Component)]
+pub struct MenuElement;
+
+/// The container holding the current page
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum MenuPage {
+    #[default]
+    Main,
+    LevelSelect,
+    /// Save slots to continue a level from
+    Load,
+    Settings,
+    Credits,
+    /// Text field for a maze code, see [crate::maze_code]
+    ImportCode,
+}
+
+/// A problem shown below the title of the page, until the player changes the page
+#[derive(Default)]
+pub struct MenuMessage(pub Option<String>);
+
+#[derive(Component, Clone, Debug)]
+enum MenuButton {
+    Play(String),
+    Load(usize),
+    Page(MenuPage),
+    Difficulty,
+    Setting(Setting),
+    ImportCode,
+    #[cfg(not(target_arch = "wasm32"))]
+    PasteCode,
+    #[cfg(not(target_arch = "wasm32"))]
+    Quit,
+}
+
+/// The first level without a personal best, or `None` if every level was completed
+fn first_unfinished_level<'a>(levels: &'a Levels, bests: &PersonalBests) -> Option<&'a str> {
+    levels
+        .names()
+        .find(|level| !bests.levels.contains_key(*level))
 }
 
-impl Default for ButtonColors {
-    fn default() -> Self {
-        ButtonColors {
-            normal: Color::rgb(0.15, 0.15, 0.15).into(),
-            hovered: Color::rgb(0.25, 0.25, 0.25).into(),
+/// The lines of the credits without markdown syntax, and whether they are headings
+fn credit_lines(markdown: &str) -> Vec<(String, bool)> {
+    markdown
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let heading = line.starts_with('#');
+            let line = line.trim_start_matches(['#', '*', '-']).trim();
+            (strip_links(line), heading)
+        })
+        .collect()
+}
+
+/// Replaces markdown links by their text
+fn strip_links(line: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let link = rest[start..]
+            .find("](")
+            .and_then(|middle| Some((middle, rest[start + middle..].find(')')? + middle)));
+        match link {
+            Some((middle, end)) => {
+                stripped.push_str(&rest[..start]);
+                stripped.push_str(&rest[start + 1..start + middle]);
+                rest = &rest[start + end + 1..];
+            }
+            None => break,
         }
     }
+    stripped.push_str(rest);
+    stripped
 }
 
-#[derive(Component)]
-pub struct MainCamera;
-
 fn setup_menu(
     mut commands: Commands,
-    font_assets: Res<FontAssets>,
-    button_colors: Res<ButtonColors>,
+    mut page: ResMut<MenuPage>,
+    mut windows: ResMut<Windows>,
+    cameras: Query<(), With<FlyCam>>,
 ) {
-    let mut source = RayCastSource::<MyRaycastSet>::new();
-    source.cast_method = RayCastMethod::Screenspace(Vec2::new(400., 300.));
-    commands
-        .spawn_bundle(Camera3dBundle {
-            transform: Transform::from_translation(Vec3::new(
-                -PIXEL_WORLD_SIZE / 2.,
-                PLAYER_Y,
-                0.0,
-            )),
-            ..default()
-        })
-        .insert(source)
-        .insert(FlyCam);
+    // leaving a level from the paused game resumes it first, which grabs the cursor
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+    }
+    // coming back from a level, the camera is still there
+    if cameras.is_empty() {
+        commands
+            .spawn_bundle(Camera3dBundle {
+                transform: Transform::from_translation(Vec3::new(
+                    -PIXEL_WORLD_SIZE / 2.,
+                    PLAYER_Y,
+                    0.0,
+                )),
+                ..default()
+            })
+            .insert(FlyCam);
+    }
+    *page = MenuPage::Main;
     commands
-        .spawn_bundle(ButtonBundle {
+        .spawn_bundle(NodeBundle {
             style: Style {
-                size: Size::new(Val::Px(120.0), Val::Px(50.0)),
                 margin: UiRect::all(Val::Auto),
-                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::ColumnReverse,
                 align_items: AlignItems::Center,
-                ..Default::default()
+                ..default()
             },
-            color: button_colors.normal,
-            ..Default::default()
+            color: UiColor(Color::NONE),
+            ..default()
         })
-        .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text {
-                    sections: vec![TextSection {
-                        value: "Play".to_string(),
-                        style: TextStyle {
-                            font: font_assets.fira_sans.clone(),
-                            font_size: 40.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        },
-                    }],
-                    alignment: Default::default(),
-                },
-                ..Default::default()
-            });
-        });
+        .insert(MenuRoot)
+        .insert(MenuElement);
 }
 
-fn click_play_button(
+/// Fills the menu with the current page
+#[allow(clippy::too_many_arguments)]
+fn show_menu_page(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
     button_colors: Res<ButtonColors>,
+    page: Res<MenuPage>,
+    message: Res<MenuMessage>,
+    difficulty: Res<Difficulty>,
+    settings: Res<Settings>,
+    levels: Res<Levels>,
+    root: Query<(Entity, Option<&Children>), With<MenuRoot>>,
+) {
+    let (root, children) = match root.get_single() {
+        Ok(root) => root,
+        Err(_) => return,
+    };
+    if !page.is_changed()
+        && !message.is_changed()
+        && children.is_some_and(|children| !children.is_empty())
+    {
+        return;
+    }
+    if let Some(children) = children {
+        for child in children {
+            commands.entity(*child).despawn_recursive();
+        }
+    }
+
+    let text_style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let mut lines: Vec<(String, f32)> = vec![];
+    let mut widgets: Vec<(Widget, MenuButton)> = vec![];
+    match *page {
+        MenuPage::Main => {
+            lines.push(("Blub's dilemma".to_owned(), 60.));
+            let bests = PersonalBests::load();
+            if bests.levels.is_empty() {
+                if let Some(level) = levels.first() {
+                    widgets.push((Widget::button("Play"), MenuButton::Play(level.to_owned())));
+                }
+            } else if let Some(level) =
+                first_unfinished_level(&levels, &bests).or_else(|| levels.last())
+            {
+                widgets.push((
+                    Widget::button(format!("Continue: level {}", level)),
+                    MenuButton::Play(level.to_owned()),
+                ));
+            }
+            widgets.push((
+                Widget::button("Level select"),
+                MenuButton::Page(MenuPage::LevelSelect),
+            ));
+            widgets.push((
+                Widget::button("Load game"),
+                MenuButton::Page(MenuPage::Load),
+            ));
+            widgets.push((
+                Widget::button("Import maze code"),
+                MenuButton::Page(MenuPage::ImportCode),
+            ));
+            widgets.push((
+                Widget::button("Settings"),
+                MenuButton::Page(MenuPage::Settings),
+            ));
+            widgets.push((
+                Widget::button("Credits"),
+                MenuButton::Page(MenuPage::Credits),
+            ));
+            #[cfg(not(target_arch = "wasm32"))]
+            widgets.push((Widget::button("Quit"), MenuButton::Quit));
+        }
+        MenuPage::LevelSelect => {
+            let bests = PersonalBests::load();
+            let completed = levels
+                .names()
+                .filter(|level| bests.levels.contains_key(*level))
+                .count();
+            lines.push((
+                format!("{} of {} levels completed", completed, levels.len()),
+                40.,
+            ));
+            for level in levels.names() {
+                let best = bests.levels.get(level).map_or_else(
+                    || "not completed".to_owned(),
+                    |best| format!("best {}", format_time(best.elapsed_secs)),
+                );
+                widgets.push((
+                    Widget::button(format!("Level {}: {}", level, best)),
+                    MenuButton::Play(level.to_owned()),
+                ));
+            }
+            widgets.push((Widget::button("Back"), MenuButton::Page(MenuPage::Main)));
+        }
+        MenuPage::Load => {
+            lines.push(("Load game".to_owned(), 50.));
+            for slot in 1..=SAVE_SLOTS {
+                widgets.push((
+                    Widget::button(slot_label(slot, SaveGame::load(slot).as_ref())),
+                    MenuButton::Load(slot),
+                ));
+            }
+            widgets.push((Widget::button("Back"), MenuButton::Page(MenuPage::Main)));
+        }
+        MenuPage::Settings => {
+            lines.push(("Settings".to_owned(), 50.));
+            widgets.push((
+                Widget::list(
+                    "Difficulty",
+                    DIFFICULTIES
+                        .iter()
+                        .map(|difficulty| format!("{:?}", difficulty))
+                        .collect(),
+                    DIFFICULTIES
+                        .iter()
+                        .position(|other| *other == *difficulty)
+                        .unwrap_or_default(),
+                ),
+                MenuButton::Difficulty,
+            ));
+            for setting in Setting::ALL {
+                widgets.push((settings.widget(setting), MenuButton::Setting(setting)));
+            }
+            widgets.push((Widget::button("Back"), MenuButton::Page(MenuPage::Main)));
+        }
+        MenuPage::ImportCode => {
+            lines.push(("Import maze code".to_owned(), 50.));
+            widgets.push((
+                Widget::text_input("Code", MAX_CODE_LENGTH),
+                MenuButton::ImportCode,
+            ));
+            #[cfg(not(target_arch = "wasm32"))]
+            widgets.push((Widget::button("Paste code"), MenuButton::PasteCode));
+            widgets.push((Widget::button("Back"), MenuButton::Page(MenuPage::Main)));
+        }
+        MenuPage::Credits => {
+            for (line, heading) in credit_lines(CREDITS) {
+                lines.push((line, if heading { 40. } else { 20. }));
+            }
+            widgets.push((Widget::button("Back"), MenuButton::Page(MenuPage::Main)));
+        }
+    }
+
+    if let Some(message) = &message.0 {
+        lines.insert(1.min(lines.len()), (message.clone(), 20.));
+    }
+
+    // the settings do not fit the smallest window otherwise
+    let compact = *page == MenuPage::Settings;
+    let widget_style = TextStyle {
+        font_size: if compact { 28. } else { 40. },
+        ..text_style.clone()
+    };
+    commands.entity(root).with_children(|parent| {
+        for (line, font_size) in lines {
+            spawn_label(
+                parent,
+                &TextStyle {
+                    font_size,
+                    ..text_style.clone()
+                },
+                line,
+            );
+        }
+        for (widget, button) in widgets {
+            let mut entity = spawn_widget(parent, &widget_style, &button_colors, widget);
+            entity.insert(button);
+            if compact {
+                entity.insert(Style {
+                    min_size: Size::new(Val::Px(460.0), Val::Px(40.0)),
+                    margin: UiRect::all(Val::Px(2.)),
+                    padding: UiRect::new(Val::Px(5.), Val::Px(5.), Val::Px(0.), Val::Px(0.)),
+                    justify_content: JustifyContent::SpaceBetween,
+                    align_items: AlignItems::Center,
+                    ..default()
+                });
+            }
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn use_menu_widgets(
+    mut commands: Commands,
+    mut events: EventReader<WidgetEvent>,
     mut state: ResMut<State<GameState>>,
-    mut interaction_query: Query<
-        (&Interaction, &mut UiColor),
-        (Changed<Interaction>, With<Button>),
-    >,
+    mut page: ResMut<MenuPage>,
+    mut message: ResMut<MenuMessage>,
+    mut levels: ResMut<Levels>,
+    mut level: ResMut<CurrentLevel>,
+    mut difficulty: ResMut<Difficulty>,
+    mut settings: ResMut<Settings>,
+    #[cfg(not(target_arch = "wasm32"))] mut exit_events: EventWriter<AppExit>,
+    buttons: Query<&MenuButton>,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
-        match *interaction {
-            Interaction::Clicked => {
-                state.set(GameState::Playing).unwrap();
+    for WidgetEvent { entity, value } in events.iter() {
+        let button = match buttons.get(*entity) {
+            Ok(button) => button,
+            Err(_) => continue,
+        };
+        let start = match (button, value) {
+            (MenuButton::Play(next), WidgetValue::Pressed) => {
+                level.0.clone_from(next);
+                true
+            }
+            // empty slots have nothing to load
+            (MenuButton::Load(slot), WidgetValue::Pressed) => {
+                load_slot(&mut commands, *slot, &levels, &mut level)
+            }
+            (MenuButton::Page(next), WidgetValue::Pressed) => {
+                *page = *next;
+                message.0 = None;
+                false
+            }
+            (MenuButton::Difficulty, WidgetValue::Selected(index)) => {
+                *difficulty = DIFFICULTIES[*index];
+                false
+            }
+            (MenuButton::Setting(setting), value) => {
+                settings.apply(*setting, value);
+                false
+            }
+            (MenuButton::ImportCode, WidgetValue::Submitted(code)) => {
+                import_code(code, &mut levels, &mut level, &mut message)
             }
-            Interaction::Hovered => {
-                *color = button_colors.hovered;
+            #[cfg(not(target_arch = "wasm32"))]
+            (MenuButton::PasteCode, WidgetValue::Pressed) => maze_code::paste()
+                .is_some_and(|code| import_code(&code, &mut levels, &mut level, &mut message)),
+            #[cfg(not(target_arch = "wasm32"))]
+            (MenuButton::Quit, WidgetValue::Pressed) => {
+                exit_events.send(AppExit);
+                false
             }
-            Interaction::None => {
-                *color = button_colors.normal;
+            _ => false,
+        };
+        if start {
+            if let Err(error) = state.set(GameState::Playing) {
+                warn!("Failed to start level {}: {:?}", level.0, error);
             }
+            // the menu is gone, so later events in this frame no longer apply
+            break;
         }
     }
 }
 
-fn cleanup_menu(mut commands: Commands, button: Query<Entity, With<Button>>) {
-    commands.entity(button.single()).despawn_recursive();
+/// Selects the maze of the code as the level to play, or shows why it can not be played
+///
+/// Returns whether there is a level to play.
+fn import_code(
+    code: &str,
+    levels: &mut Levels,
+    level: &mut CurrentLevel,
+    message: &mut MenuMessage,
+) -> bool {
+    match maze_code::import(code, levels) {
+        Ok(name) => {
+            level.0 = name;
+            true
+        }
+        Err(error) => {
+            warn!("Failed to import the maze code: {}", error);
+            message.0 = Some(format!("Invalid code: {}", error));
+            false
+        }
+    }
+}
+
+/// Escape goes back from a page to the main page
+fn leave_page(mut page: ResMut<MenuPage>, mut input: ResMut<Input<KeyCode>>) {
+    if *page != MenuPage::Main && input.clear_just_pressed(KeyCode::Escape) {
+        *page = MenuPage::Main;
+    }
+}
+
+fn cleanup_menu(
+    mut commands: Commands,
+    mut message: ResMut<MenuMessage>,
+    elements: Query<Entity, With<MenuElement>>,
+) {
+    for entity in &elements {
+        commands.entity(entity).despawn_recursive();
+    }
+    message.0 = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::PersonalBest;
+    use crate::testing::maze;
+
+    #[test]
+    fn continue_with_the_first_unfinished_level() {
+        let mut levels = Levels::default();
+        levels.add("2", maze(&["..", ".."], &[[0, 0]], [1, 1]));
+        levels.add("1", maze(&["..", ".."], &[[0, 0]], [1, 1]));
+        let mut bests = PersonalBests::default();
+        assert_eq!(first_unfinished_level(&levels, &bests), Some("1"));
+        for level in ["1", "2"] {
+            bests.record(
+                level,
+                PersonalBest {
+                    ticks: 600,
+                    elapsed_secs: 10.,
+                },
+            );
+        }
+        assert_eq!(first_unfinished_level(&levels, &bests), None);
+    }
+
+    #[test]
+    fn credits_are_shown_without_markdown() {
+        let lines = credit_lines(
+            "# Credits\n\n* Bevy icon: [MIT License](licenses/MIT.md); Copyright\n* [a](b) and [c](d)",
+        );
+        assert_eq!(
+            lines,
+            vec![
+                ("Credits".to_owned(), true),
+                ("Bevy icon: MIT License; Copyright".to_owned(), false),
+                ("a and c".to_owned(), false),
+            ]
+        );
+        assert!(!credit_lines(CREDITS).is_empty());
+    }
 }
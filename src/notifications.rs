@@ -0,0 +1,295 @@
+use crate::GameState;
+use bevy::prelude::*;
+
+/// Toasts beyond this number push out the least important ones
+pub const MAX_TOASTS: usize = 4;
+/// Toasts fade out during their last seconds
+const FADE_SECONDS: f64 = 0.5;
+
+pub struct NotificationPlugin;
+
+/// This plugin collects the [NotificationEvent]s of all systems into [Notifications]
+/// It does not draw anything, so it also runs in the headless test harness.
+impl Plugin for NotificationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Notifications>()
+            .add_event::<NotificationEvent>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(reset_notifications),
+            )
+            .add_system(queue_notifications);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationCategory {
+    /// Tells what can be done right now and stays until it is cleared
+    Prompt,
+    /// Tells about something that happened; toasts stack and fade
+    Toast,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    /// Showing a notification replaces the one with the same id
+    pub id: &'static str,
+    pub text: String,
+    pub priority: Priority,
+    pub category: NotificationCategory,
+    /// Seconds until the notification disappears, or `None` to keep it until it is cleared
+    pub duration: Option<f64>,
+}
+
+impl Notification {
+    pub fn prompt(id: &'static str, text: impl Into<String>) -> Self {
+        Notification {
+            id,
+            text: text.into(),
+            priority: Priority::Normal,
+            category: NotificationCategory::Prompt,
+            duration: None,
+        }
+    }
+
+    pub fn toast(id: &'static str, text: impl Into<String>, seconds: f64) -> Self {
+        Notification {
+            duration: Some(seconds),
+            category: NotificationCategory::Toast,
+            ..Notification::prompt(id, text)
+        }
+    }
+
+    /// A toast that stays until it is cleared
+    pub fn sticky(id: &'static str, text: impl Into<String>) -> Self {
+        Notification {
+            duration: None,
+            ..Notification::toast(id, text, 0.)
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+pub enum NotificationEvent {
+    Show(Notification),
+    /// Removes the notification with the given id, if it is shown
+    Clear(&'static str),
+}
+
+struct Shown {
+    notification: Notification,
+    since: f64,
+}
+
+impl Shown {
+    fn remaining(&self, now: f64) -> f64 {
+        self.notification
+            .duration
+            .map_or(f64::INFINITY, |duration| self.since + duration - now)
+    }
+}
+
+/// The notifications that are currently shown
+#[derive(Default)]
+pub struct Notifications {
+    prompts: Vec<Shown>,
+    toasts: Vec<Shown>,
+}
+
+impl Notifications {
+    pub fn show(&mut self, notification: Notification, now: f64) {
+        // an unchanged prompt that is sent every tick keeps its place
+        let unchanged = self.list(notification.category).iter().any(|shown| {
+            shown.notification == notification && shown.notification.duration.is_none()
+        });
+        if unchanged {
+            return;
+        }
+        self.clear(notification.id);
+        let list = self.list_mut(notification.category);
+        list.push(Shown {
+            notification,
+            since: now,
+        });
+        if self.toasts.len() > MAX_TOASTS {
+            if let Some(least_important) = self
+                .toasts
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    (a.notification.priority, a.since)
+                        .partial_cmp(&(b.notification.priority, b.since))
+                        .unwrap()
+                })
+                .map(|(index, _)| index)
+            {
+                self.toasts.remove(least_important);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, id: &str) {
+        self.prompts.retain(|shown| shown.notification.id != id);
+        self.toasts.retain(|shown| shown.notification.id != id);
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.prompts
+            .iter()
+            .chain(&self.toasts)
+            .any(|shown| shown.notification.id == id)
+    }
+
+    fn expire(&mut self, now: f64) {
+        self.toasts.retain(|shown| shown.remaining(now) > 0.);
+        self.prompts.retain(|shown| shown.remaining(now) > 0.);
+    }
+
+    /// The most important prompt, preferring newer ones
+    pub fn prompt(&self) -> Option<&Notification> {
+        self.prompts
+            .iter()
+            .rev()
+            .max_by_key(|shown| shown.notification.priority)
+            .map(|shown| &shown.notification)
+    }
+
+    /// Toasts with their opacity, most important and newest first
+    pub fn toasts(&self, now: f64) -> Vec<(&Notification, f32)> {
+        let mut toasts: Vec<&Shown> = self.toasts.iter().collect();
+        toasts.sort_by(|a, b| {
+            (b.notification.priority, b.since)
+                .partial_cmp(&(a.notification.priority, a.since))
+                .unwrap()
+        });
+        toasts
+            .into_iter()
+            .map(|shown| {
+                let alpha = (shown.remaining(now) / FADE_SECONDS).clamp(0., 1.) as f32;
+                (&shown.notification, alpha)
+            })
+            .collect()
+    }
+
+    fn list(&self, category: NotificationCategory) -> &Vec<Shown> {
+        match category {
+            NotificationCategory::Prompt => &self.prompts,
+            NotificationCategory::Toast => &self.toasts,
+        }
+    }
+
+    fn list_mut(&mut self, category: NotificationCategory) -> &mut Vec<Shown> {
+        match category {
+            NotificationCategory::Prompt => &mut self.prompts,
+            NotificationCategory::Toast => &mut self.toasts,
+        }
+    }
+}
+
+fn reset_notifications(mut notifications: ResMut<Notifications>) {
+    *notifications = Notifications::default();
+}
+
+fn queue_notifications(
+    mut events: EventReader<NotificationEvent>,
+    mut notifications: ResMut<Notifications>,
+    time: Res<Time>,
+) {
+    let now = time.seconds_since_startup();
+    for event in events.iter() {
+        match event {
+            NotificationEvent::Show(notification) => notifications.show(notification.clone(), now),
+            NotificationEvent::Clear(id) => {
+                if notifications.contains(id) {
+                    notifications.clear(id);
+                }
+            }
+        }
+    }
+    notifications.expire(now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(notifications: &Notifications, now: f64) -> Vec<&str> {
+        notifications
+            .toasts(now)
+            .into_iter()
+            .map(|(notification, _)| notification.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn toasts_stack_by_priority_and_age() {
+        let mut notifications = Notifications::default();
+        notifications.show(Notification::toast("a", "first", 5.), 0.);
+        notifications.show(Notification::toast("b", "second", 5.), 1.);
+        notifications.show(
+            Notification::toast("c", "important", 5.).with_priority(Priority::High),
+            2.,
+        );
+        assert_eq!(
+            texts(&notifications, 2.),
+            vec!["important", "second", "first"]
+        );
+
+        // showing the same id again replaces the old toast
+        notifications.show(Notification::toast("a", "replaced", 5.), 3.);
+        assert_eq!(
+            texts(&notifications, 3.),
+            vec!["important", "replaced", "second"]
+        );
+
+        for (index, id) in ["d", "e", "f"].into_iter().enumerate() {
+            notifications.show(Notification::toast(id, id, 5.), 4. + index as f64);
+        }
+        assert_eq!(texts(&notifications, 6.), vec!["important", "f", "e", "d"]);
+    }
+
+    #[test]
+    fn toasts_fade_and_expire() {
+        let mut notifications = Notifications::default();
+        notifications.show(Notification::toast("a", "fading", 2.), 0.);
+        notifications.show(Notification::sticky("b", "staying"), 1.);
+        assert_eq!(notifications.toasts(1.)[1].1, 1.);
+        assert!((notifications.toasts(1.75)[1].1 - 0.5).abs() < 0.001);
+        assert_eq!(notifications.toasts(1.75)[0].1, 1.);
+
+        notifications.expire(2.);
+        assert_eq!(texts(&notifications, 2.), vec!["staying"]);
+        notifications.expire(1000.);
+        assert!(notifications.contains("b"));
+    }
+
+    #[test]
+    fn prompts_are_kept_apart_from_toasts() {
+        let mut notifications = Notifications::default();
+        notifications.show(Notification::prompt("combine", "Combine"), 0.);
+        notifications.show(Notification::toast("exit", "Not yet", 5.), 0.);
+        notifications.show(
+            Notification::prompt("danger", "Danger").with_priority(Priority::High),
+            0.,
+        );
+        assert_eq!(notifications.prompt().unwrap().text, "Danger");
+        assert_eq!(texts(&notifications, 0.), vec!["Not yet"]);
+
+        notifications.clear("danger");
+        assert_eq!(notifications.prompt().unwrap().text, "Combine");
+        notifications.clear("combine");
+        assert_eq!(notifications.prompt(), None);
+        assert!(notifications.contains("exit"));
+    }
+}
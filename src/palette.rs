@@ -0,0 +1,165 @@
+use crate::loading::TextureAssets;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+
+/// Opacity of the marker preview, which shows where a marker would be placed
+const PREVIEW_ALPHA: f32 = 0.6;
+
+pub struct PalettePlugin;
+
+/// This plugin loads the colours of the characters from `characters.palette.ron`
+/// The materials are built once all assets are loaded, see [CharacterMaterials].
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<CharacterPalette>()
+            .init_asset_loader::<PaletteLoader>();
+    }
+}
+
+/// The look of every character, in the order of their numbers
+#[derive(Deserialize, TypeUuid, Clone, Debug)]
+#[uuid = "8d5e2a6c-3f1b-4c7d-9e0a-6b2f4d8c1a57"]
+pub struct CharacterPalette {
+    pub characters: Vec<CharacterStyle>,
+}
+
+/// Colours as sRGB values from 0 to 255
+#[derive(Deserialize, Clone, Debug)]
+pub struct CharacterStyle {
+    pub color: [u8; 3],
+    /// Light the character gives off, black for none
+    #[serde(default)]
+    pub emissive: [u8; 3],
+    /// Tint of the greyscale marker texture
+    pub marker: [u8; 3],
+}
+
+/// Index of the style of a character, counted from 1, in a palette of the given length
+///
+/// Characters beyond the end of the palette share its last style.
+fn style_index(number: u8, styles: usize) -> usize {
+    (usize::from(number.max(1)) - 1).min(styles.saturating_sub(1))
+}
+
+fn rgb([red, green, blue]: [u8; 3]) -> Color {
+    Color::rgb_u8(red, green, blue)
+}
+
+#[derive(Default)]
+struct PaletteLoader;
+
+impl AssetLoader for PaletteLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let palette: CharacterPalette = ron::de::from_bytes(bytes)?;
+            if palette.characters.is_empty() {
+                return Err(bevy::asset::Error::msg("the palette has no characters"));
+            }
+            load_context.set_default_asset(LoadedAsset::new(palette));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["palette.ron"]
+    }
+}
+
+/// Materials of one entry of the [CharacterPalette]
+struct StyleMaterials {
+    color: Color,
+    body: Handle<StandardMaterial>,
+    marker: Handle<StandardMaterial>,
+    marker_preview: Handle<StandardMaterial>,
+}
+
+/// Materials of the characters and their markers, built from the [CharacterPalette]
+pub struct CharacterMaterials {
+    styles: Vec<StyleMaterials>,
+}
+
+impl FromWorld for CharacterMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let textures = world.resource::<TextureAssets>();
+        let marker = textures.marker.clone();
+        let palette = world
+            .resource::<Assets<CharacterPalette>>()
+            .get(&textures.character_palette)
+            .cloned()
+            .expect("The character palette is loaded with the textures");
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let styles = palette
+            .characters
+            .iter()
+            .map(|style| StyleMaterials {
+                color: rgb(style.color),
+                body: materials.add(StandardMaterial {
+                    base_color: rgb(style.color),
+                    emissive: rgb(style.emissive),
+                    ..default()
+                }),
+                // the texture is black around the marker, which stays visible
+                marker: materials.add(StandardMaterial {
+                    base_color: rgb(style.marker),
+                    base_color_texture: Some(marker.clone()),
+                    alpha_mode: AlphaMode::Opaque,
+                    ..default()
+                }),
+                marker_preview: materials.add(StandardMaterial {
+                    base_color: *rgb(style.marker).set_a(PREVIEW_ALPHA),
+                    base_color_texture: Some(marker.clone()),
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                }),
+            })
+            .collect();
+        CharacterMaterials { styles }
+    }
+}
+
+impl CharacterMaterials {
+    fn style(&self, number: u8) -> &StyleMaterials {
+        self.styles
+            .get(style_index(number, self.styles.len()))
+            .expect("The palette has at least one character")
+    }
+
+    /// Colour of the given character part
+    pub fn color(&self, number: u8) -> Color {
+        self.style(number).color
+    }
+
+    pub fn body(&self, number: u8) -> Handle<StandardMaterial> {
+        self.style(number).body.clone()
+    }
+
+    pub fn marker(&self, number: u8) -> Handle<StandardMaterial> {
+        self.style(number).marker.clone()
+    }
+
+    /// The semi transparent material of the marker preview
+    pub fn marker_preview(&self, number: u8) -> Handle<StandardMaterial> {
+        self.style(number).marker_preview.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_shipped_palette_styles_every_character() {
+        let ron = include_str!("../assets/characters.palette.ron");
+        let palette: CharacterPalette = ron::de::from_str(ron).unwrap();
+        assert_eq!(palette.characters.len(), 3);
+        assert_eq!(style_index(1, 3), 0);
+        assert_eq!(style_index(3, 3), 2);
+        assert_eq!(style_index(7, 3), 2);
+    }
+}
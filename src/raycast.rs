@@ -0,0 +1,299 @@
+use crate::map::{Maze, PIXEL_WORLD_SIZE, WALL_HEIGHT};
+use crate::solver::Tile;
+use bevy::prelude::*;
+
+/// Where a ray hit the maze
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridHit {
+    /// The tile whose floor, top or side was hit
+    pub tile: Tile,
+    pub normal: Vec3,
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+impl Maze {
+    /// Height of the walkable floor, the lowered exit or the top of a wall
+    pub fn surface_height(&self, (x, y): Tile) -> f32 {
+        if !self.is_wall(x, y) {
+            -WALL_HEIGHT
+        } else if self.is_exit(x, y) {
+            -WALL_HEIGHT / 2.
+        } else {
+            0.
+        }
+    }
+
+    /// Casts a ray against floors and walls by walking through the grid tile by tile
+    ///
+    /// The maze is a height field of axis aligned faces, so only the tiles along the ray
+    /// have to be checked. Rays starting outside of the maze never hit.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<GridHit> {
+        let direction = direction.try_normalize()?;
+        // grid coordinates, where tile `t` spans `t..t + 1`
+        let to_grid = |world: f32| (world + self.world_width() / 2.) / PIXEL_WORLD_SIZE + 0.5;
+        let start = Vec2::new(to_grid(origin.x), to_grid(origin.z));
+        let mut tile = (start.x.floor() as isize, start.y.floor() as isize);
+        let size = self.size as isize;
+        let in_maze = |(x, y): (isize, isize)| x >= 0 && y >= 0 && x < size && y < size;
+        if !in_maze(tile) {
+            return None;
+        }
+
+        let step = (direction.x.signum() as isize, direction.z.signum() as isize);
+        // world distance along the ray to cross one tile on each axis
+        let delta = Vec2::new(
+            (PIXEL_WORLD_SIZE / direction.x).abs(),
+            (PIXEL_WORLD_SIZE / direction.z).abs(),
+        );
+        let first_boundary = |start: f32, tile: isize, direction: f32, delta: f32| {
+            if direction > 0. {
+                (tile as f32 + 1. - start) * delta
+            } else if direction < 0. {
+                (start - tile as f32) * delta
+            } else {
+                f32::INFINITY
+            }
+        };
+        let mut next = Vec2::new(
+            first_boundary(start.x, tile.0, direction.x, delta.x),
+            first_boundary(start.y, tile.1, direction.z, delta.y),
+        );
+        let mut distance = 0.;
+
+        loop {
+            let current = (tile.0 as usize, tile.1 as usize);
+            let height = self.surface_height(current);
+            let leave = next.x.min(next.y).min(max_distance);
+            if direction.y < 0. {
+                let to_surface = (height - origin.y) / direction.y;
+                if to_surface >= distance && to_surface <= leave {
+                    return Some(GridHit {
+                        tile: current,
+                        normal: Vec3::Y,
+                        point: origin + direction * to_surface,
+                        distance: to_surface,
+                    });
+                }
+            }
+            if leave >= max_distance {
+                return None;
+            }
+
+            let normal = if next.x < next.y {
+                tile.0 += step.0;
+                distance = next.x;
+                next.x += delta.x;
+                Vec3::X * -step.0 as f32
+            } else {
+                tile.1 += step.1;
+                distance = next.y;
+                next.y += delta.y;
+                Vec3::Z * -step.1 as f32
+            };
+            if !in_maze(tile) {
+                return None;
+            }
+            let point = origin + direction * distance;
+            let entered = (tile.0 as usize, tile.1 as usize);
+            if point.y < self.surface_height(entered) {
+                return Some(GridHit {
+                    tile: entered,
+                    normal,
+                    point,
+                    distance,
+                });
+            }
+            if point.y > 0. && direction.y >= 0. {
+                // above all walls and going up
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::character::PLAYER_Y;
+    use crate::testing::{maze, shipped_levels, tile_center};
+    use bevy_mod_raycast::{ray_intersection_over_mesh, Backfaces, Ray3d};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::f32::consts::FRAC_PI_2;
+    use std::time::Instant;
+
+    const ROOM: [&str; 5] = ["#####", "#...#", "#...#", "#...#", "#####"];
+
+    /// One plane per face, like the maze meshes that used to be raycast
+    fn faces(maze: &Maze) -> Vec<Mat4> {
+        let mut faces = vec![];
+        for x in 0..maze.size {
+            for y in 0..maze.size {
+                let height = maze.surface_height((x, y));
+                let center = tile_center(maze, (x, y), height);
+                faces.push(Mat4::from_scale_rotation_translation(
+                    Vec3::splat(PIXEL_WORLD_SIZE),
+                    Quat::IDENTITY,
+                    center,
+                ));
+                // the plane is rotated upright, so its local x or z axis becomes the height
+                for (neighbour, offset, rotation, height_axis) in [
+                    (
+                        (x + 1, y),
+                        Vec3::X,
+                        Quat::from_rotation_z(FRAC_PI_2),
+                        Vec3::X,
+                    ),
+                    (
+                        (x, y + 1),
+                        Vec3::Z,
+                        Quat::from_rotation_x(FRAC_PI_2),
+                        Vec3::Z,
+                    ),
+                ] {
+                    if neighbour.0 >= maze.size || neighbour.1 >= maze.size {
+                        continue;
+                    }
+                    let other = maze.surface_height(neighbour);
+                    if other == height {
+                        continue;
+                    }
+                    let (low, high) = (height.min(other), height.max(other));
+                    let mut translation = center + offset * PIXEL_WORLD_SIZE / 2.;
+                    translation.y = (low + high) / 2.;
+                    let scale =
+                        height_axis * (high - low) + (Vec3::ONE - height_axis) * PIXEL_WORLD_SIZE;
+                    faces.push(Mat4::from_scale_rotation_translation(
+                        scale,
+                        rotation,
+                        translation,
+                    ));
+                }
+            }
+        }
+        faces
+    }
+
+    fn mesh_raycast(mesh: &Mesh, faces: &[Mat4], origin: Vec3, direction: Vec3) -> Option<f32> {
+        let ray = Ray3d::new(origin, direction);
+        faces
+            .iter()
+            .filter_map(|face| ray_intersection_over_mesh(mesh, face, &ray, Backfaces::Include))
+            .map(|hit| hit.distance())
+            .min_by(|a, b| a.total_cmp(b))
+    }
+
+    fn random_rays(maze: &Maze, count: usize) -> Vec<(Vec3, Vec3)> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let open: Vec<Tile> = (0..maze.size)
+            .flat_map(|x| (0..maze.size).map(move |y| (x, y)))
+            .filter(|(x, y)| !maze.is_wall(*x, *y))
+            .collect();
+        (0..count)
+            .map(|_| {
+                let tile = open[rng.gen_range(0..open.len())];
+                let origin = tile_center(maze, tile, PLAYER_Y)
+                    + Vec3::new(rng.gen_range(-0.2..0.2), 0., rng.gen_range(-0.2..0.2));
+                let direction = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..0.5),
+                    rng.gen_range(-1.0..1.0),
+                );
+                (origin, direction.normalize())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hits_floor_and_walls() {
+        let room = maze(&ROOM, &[[2, 2]], [2, 0]);
+        let center = tile_center(&room, (2, 2), PLAYER_Y);
+
+        let floor = room.raycast(center, -Vec3::Y, 5.).unwrap();
+        assert_eq!(floor.tile, (2, 2));
+        assert_eq!(floor.normal, Vec3::Y);
+        assert!((floor.distance - (PLAYER_Y + WALL_HEIGHT)).abs() < 0.0001);
+
+        let wall = room.raycast(center, Vec3::X, 5.).unwrap();
+        assert_eq!(wall.tile, (4, 2));
+        assert_eq!(wall.normal, -Vec3::X);
+        assert!((wall.distance - 1.5 * PIXEL_WORLD_SIZE).abs() < 0.0001);
+
+        let exit = room.raycast(center, -Vec3::Z, 5.).unwrap();
+        assert_eq!(exit.tile, (2, 0));
+        assert_eq!(exit.normal, Vec3::Z);
+        // the exit is lowered, so a flat ray above its top passes over it
+        let above_exit = center + Vec3::Y * (PLAYER_Y - WALL_HEIGHT / 2.).abs() * 2.;
+        assert_eq!(room.raycast(above_exit, -Vec3::Z, 5.), None);
+        assert_eq!(room.raycast(center, Vec3::X, 1.), None);
+    }
+
+    #[test]
+    fn agrees_with_mesh_raycast() {
+        let mesh = Mesh::from(shape::Plane { size: 1. });
+        for (name, level) in shipped_levels() {
+            let faces = faces(&level);
+            for (origin, direction) in random_rays(&level, 500) {
+                let grid = level
+                    .raycast(origin, direction, 100.)
+                    .map(|hit| hit.distance);
+                let mesh = mesh_raycast(&mesh, &faces, origin, direction);
+                match (grid, mesh) {
+                    (Some(grid), Some(mesh)) => assert!(
+                        (grid - mesh).abs() < 0.001,
+                        "Level {}: ray {:?} {:?} hit at {} instead of {}",
+                        name,
+                        origin,
+                        direction,
+                        grid,
+                        mesh
+                    ),
+                    _ => assert_eq!(
+                        grid, mesh,
+                        "Level {}: ray {:?} {:?}",
+                        name, origin, direction
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture raycast_benchmark`
+    #[test]
+    #[ignore]
+    fn raycast_benchmark() {
+        let mesh = Mesh::from(shape::Plane { size: 1. });
+        for (name, level) in shipped_levels() {
+            let faces = faces(&level);
+            let rays = random_rays(&level, 2000);
+
+            let started = Instant::now();
+            let grid_hits = rays
+                .iter()
+                .filter(|(origin, direction)| level.raycast(*origin, *direction, 100.).is_some())
+                .count();
+            let grid = started.elapsed();
+
+            let started = Instant::now();
+            let mesh_hits = rays
+                .iter()
+                .filter(|(origin, direction)| {
+                    mesh_raycast(&mesh, &faces, *origin, *direction).is_some()
+                })
+                .count();
+            let meshes = started.elapsed();
+
+            println!(
+                "Level {} ({} faces, {} rays): grid {:?} per ray, meshes {:?} per ray, {}/{} hits",
+                name,
+                faces.len(),
+                rays.len(),
+                grid / rays.len() as u32,
+                meshes / rays.len() as u32,
+                grid_hits,
+                mesh_hits
+            );
+        }
+    }
+}
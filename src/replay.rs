@@ -0,0 +1,327 @@
+use crate::character::{CamInputState, Character, LevelCompletedEvent};
+use crate::levels::Levels;
+use crate::map::CurrentLevel;
+use crate::menu::MenuMessage;
+use crate::notifications::{Notification, NotificationEvent};
+use crate::simulation::GameStopWatch;
+use crate::simulation::{
+    simulation_running, PendingInput, Position, SimulationSeed, SimulationStage, SimulationSystem,
+    SimulationTick, TickInput,
+};
+use crate::storage;
+use crate::GameState;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Bump this whenever a change to the simulation would make old replays diverge
+pub const REPLAY_VERSION: u32 = 1;
+
+pub struct ReplayPlugin;
+
+/// This plugin records the input of every simulation tick and writes a replay file once the level is left
+/// Runs that were given up or restarted are written without an outcome, so they can be attached to bug reports.
+/// Starting the game with `--replay <path>` plays the given file back instead of reading player input.
+/// At the end of a playback the outcome is compared to the recorded one.
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(path) = replay_argument() {
+            match storage::read::<Replay>(&path) {
+                Ok(replay) if replay.version == REPLAY_VERSION => {
+                    info!("Playing replay {:?}", path);
+                    app.insert_resource(SimulationSeed(replay.seed))
+                        .insert_resource(CurrentLevel(replay.level.clone()))
+                        .insert_resource(ReplayPlayback::new(replay))
+                        .add_system_set(
+                            SystemSet::on_enter(GameState::Menu).with_system(skip_menu),
+                        );
+                }
+                Ok(replay) => error!(
+                    "Replay {:?} has version {}, but this build only plays version {}",
+                    path, replay.version, REPLAY_VERSION
+                ),
+                Err(error) => error!("Failed to read replay {:?}: {}", path, error),
+            }
+        }
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(start_recording))
+            .add_system_set(
+                SystemSet::on_exit(GameState::Playing).with_system(save_unfinished_replay),
+            )
+            // closing the game leaves the level without any state change
+            .add_system_to_stage(CoreStage::Last, save_replay_on_exit)
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(simulation_running)
+                    .with_system(feed_replay_input.before(SimulationSystem::BeginTick))
+                    .with_system(record_tick.after(SimulationSystem::BeginTick))
+                    .with_system(finish_replay.after(SimulationSystem::Gameplay)),
+            );
+    }
+}
+
+/// Everything needed to reproduce a run
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub version: u32,
+    pub level: String,
+    pub seed: u64,
+    /// Tick inputs, run-length encoded as (number of ticks, input)
+    pub inputs: Vec<(u32, TickInput)>,
+    pub outcome: Option<ReplayOutcome>,
+}
+
+impl Replay {
+    pub fn new(level: String, seed: u64) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            level,
+            seed,
+            inputs: vec![],
+            outcome: None,
+        }
+    }
+
+    pub fn push(&mut self, input: TickInput) {
+        match self.inputs.last_mut() {
+            Some((count, last)) if *last == input => *count += 1,
+            _ => self.inputs.push((1, input)),
+        }
+    }
+}
+
+/// State of the game at the end of a run
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReplayOutcome {
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+    pub characters: Vec<CharacterOutcome>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CharacterOutcome {
+    pub numbers: Vec<u8>,
+    pub position: [f32; 3],
+}
+
+pub struct ReplayRecorder {
+    pub replay: Replay,
+    finished: bool,
+}
+
+/// The replay file of the completed level, relative to the save directory
+///
+/// Only present if the replay could be written.
+pub struct SavedReplay(pub String);
+
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    run: usize,
+    played_in_run: u32,
+    finished: bool,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            run: 0,
+            played_in_run: 0,
+            finished: false,
+        }
+    }
+
+    fn next_input(&mut self) -> Option<TickInput> {
+        let (count, input) = self.replay.inputs.get(self.run)?;
+        let input = input.clone();
+        self.played_in_run += 1;
+        if self.played_in_run >= *count {
+            self.run += 1;
+            self.played_in_run = 0;
+        }
+        Some(input)
+    }
+}
+
+/// Where the replay of a run is saved, relative to the save directory
+pub fn replay_file(level: &str, seed: u64) -> String {
+    format!("replays/{}-{}.ron", level, seed)
+}
+
+fn replay_argument() -> Option<PathBuf> {
+    std::env::args()
+        .skip_while(|argument| argument != "--replay")
+        .nth(1)
+        .map(PathBuf::from)
+}
+
+/// Starts the replay right away, unless its level is missing
+fn skip_menu(
+    mut commands: Commands,
+    mut state: ResMut<State<GameState>>,
+    levels: Res<Levels>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut message: ResMut<MenuMessage>,
+) {
+    let level = match &playback {
+        Some(playback) => &playback.replay.level,
+        None => return,
+    };
+    if levels.get(level).is_none() {
+        error!("The level {} of the replay is missing", level);
+        message.0 = Some(format!(
+            "Cannot play the replay, level {} is missing",
+            level
+        ));
+        // the menu is used normally then, so nothing may be played back
+        commands.remove_resource::<ReplayPlayback>();
+        return;
+    }
+    if let Err(error) = state.set(GameState::Playing) {
+        warn!("Failed to start the replay: {:?}", error);
+    }
+}
+
+fn start_recording(
+    mut commands: Commands,
+    playback: Option<Res<ReplayPlayback>>,
+    level: Res<CurrentLevel>,
+    seed: Res<SimulationSeed>,
+) {
+    commands.remove_resource::<SavedReplay>();
+    if playback.is_some() {
+        return;
+    }
+    commands.insert_resource(ReplayRecorder {
+        replay: Replay::new(level.0.clone(), seed.0),
+        finished: false,
+    });
+}
+
+fn feed_replay_input(
+    playback: Option<ResMut<ReplayPlayback>>,
+    mut pending: ResMut<PendingInput>,
+    mut cam_input_state: ResMut<CamInputState>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    let mut playback = match playback {
+        Some(playback) if !playback.finished => playback,
+        _ => return,
+    };
+    if let Some(input) = playback.next_input() {
+        cam_input_state.yaw = input.yaw;
+        cam_input_state.pitch = input.pitch;
+        pending.0 = input;
+    } else {
+        playback.finished = true;
+        warn!("The replay ended before the level was completed");
+        notifications.send(NotificationEvent::Show(Notification::sticky(
+            "replay",
+            "Replay ended without completing the level",
+        )));
+    }
+}
+
+fn record_tick(recorder: Option<ResMut<ReplayRecorder>>, tick_input: Res<TickInput>) {
+    if let Some(mut recorder) = recorder {
+        if !recorder.finished {
+            recorder.replay.push(tick_input.clone());
+        }
+    }
+}
+
+/// Writes the recording, whose outcome is `None` if the level was not completed
+///
+/// Returns the file relative to the save directory, if it was written.
+fn save_replay(recorder: &mut ReplayRecorder, outcome: Option<ReplayOutcome>) -> Option<String> {
+    recorder.finished = true;
+    recorder.replay.outcome = outcome;
+    let file = replay_file(&recorder.replay.level, recorder.replay.seed);
+    match storage::save(&file, &recorder.replay) {
+        Ok(path) => {
+            info!("Saved replay to {:?}", path);
+            Some(file)
+        }
+        Err(error) => {
+            warn!("Failed to save replay: {}", error);
+            None
+        }
+    }
+}
+
+/// Keeps the recording of a level that is left, restarted or closed before it was completed
+fn save_unfinished_replay(recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        // a level that was left right away has nothing worth reporting
+        if !recorder.finished && !recorder.replay.inputs.is_empty() {
+            save_replay(&mut recorder, None);
+        }
+    }
+}
+
+fn save_replay_on_exit(mut exits: EventReader<AppExit>, recorder: Option<ResMut<ReplayRecorder>>) {
+    if exits.iter().last().is_some() {
+        save_unfinished_replay(recorder);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn finish_replay(
+    mut commands: Commands,
+    mut events: EventReader<LevelCompletedEvent>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    playback: Option<ResMut<ReplayPlayback>>,
+    tick: Res<SimulationTick>,
+    stop_watch: Res<GameStopWatch>,
+    characters: Query<(&Character, &Position)>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    if events.iter().last().is_none() {
+        return;
+    }
+    let mut characters: Vec<CharacterOutcome> = characters
+        .iter()
+        .map(|(character, position)| CharacterOutcome {
+            numbers: character.numbers().to_vec(),
+            position: position.current.to_array(),
+        })
+        .collect();
+    characters.sort_by(|a, b| a.numbers.cmp(&b.numbers));
+    let outcome = ReplayOutcome {
+        ticks: tick.0,
+        elapsed_secs: stop_watch.0.elapsed_secs(),
+        characters,
+    };
+
+    if let Some(mut recorder) = recorder {
+        if !recorder.finished {
+            if let Some(file) = save_replay(&mut recorder, Some(outcome)) {
+                commands.insert_resource(SavedReplay(file));
+            }
+        }
+        return;
+    }
+    if let Some(mut playback) = playback {
+        if playback.finished {
+            return;
+        }
+        playback.finished = true;
+        if playback.replay.outcome.as_ref() == Some(&outcome) {
+            info!("Replay verified after {} ticks", outcome.ticks);
+            notifications.send(NotificationEvent::Show(Notification::sticky(
+                "replay",
+                "Replay verified",
+            )));
+        } else {
+            error!(
+                "Replay diverged! Recorded {:?}, but got {:?}",
+                playback.replay.outcome, outcome
+            );
+            notifications.send(NotificationEvent::Show(Notification::sticky(
+                "replay",
+                "Replay does not match the recorded run",
+            )));
+        }
+    }
+}
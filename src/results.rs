@@ -0,0 +1,381 @@
+use crate::character::{Character, ControlSwitchedEvent, LevelCompletedEvent};
+use crate::ghost::format_time;
+use crate::levels::Levels;
+use crate::loading::FontAssets;
+use crate::map::{CurrentLevel, PIXEL_WORLD_SIZE};
+use crate::palette::CharacterMaterials;
+use crate::replay::ReplayPlayback;
+use crate::simulation::{
+    simulation_running, GameStopWatch, Position, SimulationStage, SimulationSystem, SimulationTick,
+};
+use crate::storage::{self, StorageError};
+use crate::widgets::{spawn_widget, ButtonColors, Widget, WidgetEvent, WidgetValue};
+use crate::GameState;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Bump this whenever the format of [PersonalBests] changes
+pub const RECORDS_VERSION: u32 = 1;
+const RECORDS_FILE: &str = "records.ron";
+
+pub struct ResultsPlugin;
+
+/// This plugin collects statistics while playing and shows them once a level is completed
+/// Completing a level freezes the stop watch, saves a new personal best and
+/// switches to [GameState::LevelComplete], where the player can retry, continue or go back to the menu.
+impl Plugin for ResultsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RunStats>()
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_stats))
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(simulation_running)
+                    .with_system(track_stats.after(SimulationSystem::Gameplay))
+                    .with_system(complete_level.after(track_stats)),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::LevelComplete).with_system(show_results))
+            .add_system_set(
+                SystemSet::on_update(GameState::LevelComplete).with_system(use_results_widgets),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::LevelComplete).with_system(cleanup_results),
+            );
+    }
+}
+
+/// Statistics of the current run
+#[derive(Default, Clone, Debug)]
+pub struct RunStats {
+    pub markers_placed: usize,
+    pub switches: usize,
+    /// World distance walked by every part, including the way walked combined with others
+    pub distances: BTreeMap<u8, f32>,
+}
+
+/// A completed run, shown on the results screen
+pub struct LevelResult {
+    pub level: String,
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+    pub stats: RunStats,
+    /// The best time before this run
+    pub previous_best: Option<f32>,
+}
+
+impl LevelResult {
+    pub fn is_new_best(&self) -> bool {
+        self.previous_best
+            .is_none_or(|previous| self.elapsed_secs < previous)
+    }
+}
+
+/// The best times of all levels, stored in the save directory
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PersonalBests {
+    pub version: u32,
+    pub levels: BTreeMap<String, PersonalBest>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PersonalBest {
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+}
+
+impl Default for PersonalBests {
+    fn default() -> Self {
+        PersonalBests {
+            version: RECORDS_VERSION,
+            levels: BTreeMap::new(),
+        }
+    }
+}
+
+impl PersonalBests {
+    /// Reads the personal bests, empty if there are none or they can not be read
+    ///
+    /// Files of newer versions are kept on disk, saving refuses to overwrite them.
+    pub fn load() -> Self {
+        match storage::load_versioned(RECORDS_FILE, RECORDS_VERSION, PersonalBests::migrate) {
+            Ok(bests) => bests,
+            Err(error) => {
+                if !error.is_not_found() {
+                    warn!("Failed to load the personal bests: {}", error);
+                }
+                PersonalBests::default()
+            }
+        }
+    }
+
+    /// Converts personal bests of older versions, once there are any
+    fn migrate(version: u32, _ron: &str) -> Result<Self, StorageError> {
+        Err(StorageError::Outdated(version))
+    }
+
+    /// Keeps the run if it beats the best of the level and returns the best before it
+    pub fn record(&mut self, level: &str, run: PersonalBest) -> Option<PersonalBest> {
+        let previous = self.levels.get(level).cloned();
+        if previous.as_ref().is_none_or(|best| run.ticks < best.ticks) {
+            self.levels.insert(level.to_owned(), run);
+        }
+        previous
+    }
+}
+
+#[derive(Component)]
+struct ResultsElement;
+
+#[derive(Component, Clone, Copy)]
+enum ResultsButton {
+    Retry,
+    NextLevel,
+    Menu,
+}
+
+fn reset_stats(mut commands: Commands, mut stats: ResMut<RunStats>) {
+    *stats = RunStats::default();
+    commands.remove_resource::<LevelResult>();
+}
+
+fn track_stats(
+    mut stats: ResMut<RunStats>,
+    mut switched_events: EventReader<ControlSwitchedEvent>,
+    characters: Query<(&Character, &Position)>,
+) {
+    stats.switches += switched_events.iter().count();
+    for (character, position) in &characters {
+        let walked = position.current.distance(position.previous);
+        if walked == 0. {
+            continue;
+        }
+        for number in character.numbers() {
+            *stats.distances.entry(*number).or_default() += walked;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn complete_level(
+    mut commands: Commands,
+    mut events: EventReader<LevelCompletedEvent>,
+    result: Option<Res<LevelResult>>,
+    mut stop_watch: ResMut<GameStopWatch>,
+    tick: Res<SimulationTick>,
+    stats: Res<RunStats>,
+    level: Res<CurrentLevel>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if events.iter().last().is_none() || result.is_some() {
+        return;
+    }
+    stop_watch.0.pause();
+    let run = PersonalBest {
+        ticks: tick.0,
+        elapsed_secs: stop_watch.0.elapsed_secs(),
+    };
+    let mut bests = PersonalBests::load();
+    let previous_best = bests.record(&level.0, run.clone());
+    // replays do not count as new records
+    if playback.is_none() && bests.levels.get(&level.0) == Some(&run) {
+        if let Err(error) = storage::save_versioned(RECORDS_FILE, &bests, RECORDS_VERSION) {
+            warn!("Failed to save the personal bests: {}", error);
+        }
+    }
+    commands.insert_resource(LevelResult {
+        level: level.0.clone(),
+        ticks: run.ticks,
+        elapsed_secs: run.elapsed_secs,
+        stats: stats.clone(),
+        previous_best: previous_best.map(|best| best.elapsed_secs),
+    });
+    if let Err(error) = state.set(GameState::LevelComplete) {
+        warn!("Failed to show the results: {:?}", error);
+    }
+}
+
+fn show_results(
+    mut commands: Commands,
+    result: Res<LevelResult>,
+    font_assets: Res<FontAssets>,
+    character_materials: Res<CharacterMaterials>,
+    button_colors: Res<ButtonColors>,
+    levels: Res<Levels>,
+    mut windows: ResMut<Windows>,
+) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+    }
+    let style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let best = if result.is_new_best() {
+        "New personal best!".to_owned()
+    } else {
+        format!(
+            "Best {}",
+            format_time(result.previous_best.unwrap_or_default())
+        )
+    };
+    let mut lines: Vec<(String, Color)> = [
+        format!("Level {} complete", result.level),
+        format!("Time {}", format_time(result.elapsed_secs)),
+        best,
+        format!("Markers placed {}", result.stats.markers_placed),
+        format!("Switches {}", result.stats.switches),
+    ]
+    .into_iter()
+    .map(|line| (line, style.color))
+    .collect();
+    for (number, distance) in &result.stats.distances {
+        let color = character_materials.color(*number);
+        lines.push((
+            format!(
+                "Part {} walked {:.0} tiles",
+                number,
+                distance / PIXEL_WORLD_SIZE
+            ),
+            color,
+        ));
+    }
+    let mut buttons = vec![("Retry", ResultsButton::Retry)];
+    if levels.next(&result.level).is_some() {
+        buttons.push(("Next level", ResultsButton::NextLevel));
+    }
+    buttons.push(("Menu", ResultsButton::Menu));
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                padding: UiRect::all(Val::Px(20.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: UiColor(Color::rgba(0.1, 0.1, 0.1, 0.85)),
+            ..default()
+        })
+        .insert(ResultsElement)
+        .with_children(|parent| {
+            for (index, (line, color)) in lines.into_iter().enumerate() {
+                parent.spawn_bundle(TextBundle::from_section(
+                    line,
+                    TextStyle {
+                        font_size: if index == 0 { 40.0 } else { 30.0 },
+                        color,
+                        ..style.clone()
+                    },
+                ));
+            }
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        margin: UiRect::new(Val::Px(0.), Val::Px(0.), Val::Px(20.), Val::Px(0.)),
+                        ..default()
+                    },
+                    color: UiColor(Color::NONE),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    let button_style = TextStyle {
+                        font_size: 40.0,
+                        ..style.clone()
+                    };
+                    for (label, button) in buttons {
+                        spawn_widget(parent, &button_style, &button_colors, Widget::button(label))
+                            .insert(button)
+                            .insert(Style {
+                                size: Size::new(Val::Px(180.0), Val::Px(50.0)),
+                                margin: UiRect::all(Val::Px(5.)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            });
+                    }
+                });
+        });
+}
+
+fn use_results_widgets(
+    mut events: EventReader<WidgetEvent>,
+    mut state: ResMut<State<GameState>>,
+    mut level: ResMut<CurrentLevel>,
+    levels: Res<Levels>,
+    buttons: Query<&ResultsButton>,
+) {
+    for WidgetEvent { entity, value } in events.iter() {
+        let button = match (buttons.get(*entity), value) {
+            (Ok(button), WidgetValue::Pressed) => button,
+            _ => continue,
+        };
+        let next = match button {
+            ResultsButton::Retry => GameState::Playing,
+            ResultsButton::NextLevel => {
+                if let Some(next) = levels.next(&level.0) {
+                    level.0 = next.to_owned();
+                }
+                GameState::Playing
+            }
+            ResultsButton::Menu => GameState::Menu,
+        };
+        if let Err(error) = state.set(next) {
+            warn!("Failed to leave the results: {:?}", error);
+        }
+        // the results are gone, so later events in this frame no longer apply
+        break;
+    }
+}
+
+fn cleanup_results(mut commands: Commands, elements: Query<Entity, With<ResultsElement>>) {
+    for entity in &elements {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(ticks: u64) -> PersonalBest {
+        PersonalBest {
+            ticks,
+            elapsed_secs: ticks as f32 / 60.,
+        }
+    }
+
+    #[test]
+    fn only_faster_runs_become_personal_bests() {
+        let mut bests = PersonalBests::default();
+        assert_eq!(bests.record("1", run(600)), None);
+        assert_eq!(bests.record("1", run(900)), Some(run(600)));
+        assert_eq!(bests.levels["1"], run(600));
+        assert_eq!(bests.record("1", run(300)), Some(run(600)));
+        assert_eq!(bests.levels["1"], run(300));
+
+        assert_eq!(bests.record("2", run(1200)), None);
+        assert_eq!(bests.levels.len(), 2);
+    }
+
+    #[test]
+    fn personal_bests_of_newer_versions_are_refused() {
+        let newer = PersonalBests {
+            version: RECORDS_VERSION + 1,
+            ..default()
+        };
+        let ron = ron::to_string(&newer).unwrap();
+        assert!(matches!(
+            storage::parse_versioned(&ron, RECORDS_VERSION, PersonalBests::migrate),
+            Err(StorageError::Newer(_))
+        ));
+        let current = ron::to_string(&PersonalBests::default()).unwrap();
+        assert!(
+            storage::parse_versioned(&current, RECORDS_VERSION, PersonalBests::migrate).is_ok()
+        );
+    }
+}
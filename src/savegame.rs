@@ -0,0 +1,437 @@
+use crate::character::{spawn_character, CamInputState, Character, Controlled};
+use crate::ghost::format_time;
+use crate::leaderboard::today;
+use crate::levels::Levels;
+use crate::map::CurrentLevel;
+use crate::markers::{Marker, SavedMarker};
+use crate::notifications::{Notification, NotificationEvent};
+use crate::replay::ReplayRecorder;
+use crate::simulation::{
+    GameStopWatch, PendingInput, Position, SimulationRng, SimulationSeed, SimulationTick,
+};
+use crate::storage::{self, StorageError};
+use crate::GameState;
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// Bump this whenever the format of [SaveGame] changes and convert older saves in [migrate]
+pub const SAVE_VERSION: u32 = 1;
+/// Number of slots offered in the menus, counted from 1
+pub const SAVE_SLOTS: usize = 3;
+
+pub struct SaveGamePlugin;
+
+/// This plugin saves a running level into numbered slots and restores it again
+/// Saving is requested with a [SaveGameEvent] from the paused game. Loading goes through
+/// [load_slot], which prepares the level, before the menus switch to [GameState::Playing].
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameEvent>()
+            .add_system(save_game)
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playing).with_system(forget_restored_run),
+            )
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(restore_game));
+    }
+}
+
+/// Saves the running level into the given slot
+pub struct SaveGameEvent(pub usize);
+
+/// State of a level in progress as it is written to disk
+///
+/// The maze has no doors or switches yet, so the characters, markers and time are all there is.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    pub version: u32,
+    pub level: String,
+    pub seed: u64,
+    pub ticks: u64,
+    pub elapsed_secs: f32,
+    pub characters: Vec<SavedCharacter>,
+    pub markers: Vec<SavedMarker>,
+    pub date: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedCharacter {
+    pub numbers: Vec<u8>,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub controlled: bool,
+}
+
+/// The part of every version of a save game needed to read the rest
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[derive(Debug)]
+pub enum SaveGameError {
+    Storage(StorageError),
+    /// Written by a newer version of the game
+    Newer(u32),
+}
+
+impl Display for SaveGameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveGameError::Storage(error) => error.fmt(f),
+            SaveGameError::Newer(version) => write!(
+                f,
+                "the save has version {}, but only {} is supported",
+                version, SAVE_VERSION
+            ),
+        }
+    }
+}
+
+impl From<StorageError> for SaveGameError {
+    fn from(error: StorageError) -> Self {
+        SaveGameError::Storage(error)
+    }
+}
+
+impl From<ron::Error> for SaveGameError {
+    fn from(error: ron::Error) -> Self {
+        SaveGameError::Storage(error.into())
+    }
+}
+
+fn slot_file(slot: usize) -> String {
+    format!("saves/slot-{}.ron", slot)
+}
+
+impl SaveGame {
+    /// Reads the save in the given slot, `None` if the slot is empty or unreadable
+    pub fn load(slot: usize) -> Option<SaveGame> {
+        match storage::load_text(slot_file(slot))
+            .map_err(SaveGameError::from)
+            .and_then(|ron| migrate(&ron))
+        {
+            Ok(save) => Some(save),
+            Err(SaveGameError::Storage(error)) if error.is_not_found() => None,
+            Err(error) => {
+                warn!("Failed to load save slot {}: {}", slot, error);
+                None
+            }
+        }
+    }
+
+    /// Level, time and date of the save
+    pub fn summary(&self) -> String {
+        format!(
+            "level {}, {}, {}",
+            self.level,
+            format_time(self.elapsed_secs),
+            self.date
+        )
+    }
+}
+
+/// Reads a save game of any known version
+fn migrate(ron: &str) -> Result<SaveGame, SaveGameError> {
+    let header: SaveHeader = ron::from_str(ron)?;
+    match header.version {
+        // older versions get converted here, once there are any
+        SAVE_VERSION => Ok(ron::from_str(ron)?),
+        version => Err(SaveGameError::Newer(version)),
+    }
+}
+
+/// Label of a slot in the save and load menus
+pub fn slot_label(slot: usize, save: Option<&SaveGame>) -> String {
+    match save {
+        Some(save) => format!("Slot {}: {}", slot, save.summary()),
+        None => format!("Slot {}: empty", slot),
+    }
+}
+
+/// Restored on the first frame of the level
+pub struct PendingLoad(pub SaveGame);
+
+/// Marks a level that was continued from a save game, until the next level starts
+///
+/// The start of such a run is missing, so it has no replay and does not become a ghost.
+pub struct RestoredRun;
+
+/// Prepares loading the given slot, returns false if the slot is empty or its level is missing
+///
+/// The caller switches to [GameState::Playing] afterwards. The saved markers are spawned with
+/// the level instead of those stored for it, which stay untouched for the next fresh run.
+pub fn load_slot(
+    commands: &mut Commands,
+    slot: usize,
+    levels: &Levels,
+    level: &mut CurrentLevel,
+) -> bool {
+    let save = match SaveGame::load(slot) {
+        Some(save) => save,
+        None => return false,
+    };
+    if levels.get(&save.level).is_none() {
+        warn!("The level {} of save slot {} is missing", save.level, slot);
+        return false;
+    }
+    level.0.clone_from(&save.level);
+    commands.insert_resource(PendingLoad(save));
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_game(
+    mut events: EventReader<SaveGameEvent>,
+    level: Res<CurrentLevel>,
+    seed: Res<SimulationSeed>,
+    tick: Res<SimulationTick>,
+    stop_watch: Res<GameStopWatch>,
+    cam_input_state: Res<CamInputState>,
+    characters: Query<(&Character, &Position, &CamInputState, Option<&Controlled>)>,
+    markers: Query<(&Marker, &Transform)>,
+    mut notifications: EventWriter<NotificationEvent>,
+) {
+    for SaveGameEvent(slot) in events.iter() {
+        let mut saved_characters: Vec<SavedCharacter> = characters
+            .iter()
+            .map(|(character, position, cam_state, controlled)| {
+                // the controlled character only gets its view back when control switches
+                let cam_state = if controlled.is_some() {
+                    cam_input_state.as_ref()
+                } else {
+                    cam_state
+                };
+                SavedCharacter {
+                    numbers: character.numbers().to_vec(),
+                    position: position.current.to_array(),
+                    yaw: cam_state.yaw,
+                    pitch: cam_state.pitch,
+                    controlled: controlled.is_some(),
+                }
+            })
+            .collect();
+        saved_characters.sort_by(|a, b| a.numbers.cmp(&b.numbers));
+        let save = SaveGame {
+            version: SAVE_VERSION,
+            level: level.0.clone(),
+            seed: seed.0,
+            ticks: tick.0,
+            elapsed_secs: stop_watch.0.elapsed_secs(),
+            characters: saved_characters,
+            markers: markers
+                .iter()
+                .map(|(marker, transform)| SavedMarker::new(marker, transform))
+                .collect(),
+            date: today(),
+        };
+        let text = match storage::save(slot_file(*slot), &save) {
+            Ok(path) => {
+                info!("Saved the game to {:?}", path);
+                format!("Saved to slot {}", slot)
+            }
+            Err(error) => {
+                warn!("Failed to save the game: {}", error);
+                "Failed to save the game".to_owned()
+            }
+        };
+        notifications.send(NotificationEvent::Show(Notification::toast(
+            "savegame", text, 3.,
+        )));
+    }
+}
+
+/// Replaces the freshly spawned characters and time of the level with the saved ones
+///
+/// A loaded run continues with the saved seed, but the random numbers drawn before saving are
+/// not replayed. The run cannot be recorded as a replay either, since its start is missing.
+#[allow(clippy::too_many_arguments)]
+fn restore_game(
+    mut commands: Commands,
+    pending: Option<Res<PendingLoad>>,
+    characters: Query<Entity, With<Character>>,
+    mut seed: ResMut<SimulationSeed>,
+    mut rng: ResMut<SimulationRng>,
+    mut tick: ResMut<SimulationTick>,
+    mut stop_watch: ResMut<GameStopWatch>,
+    mut cam_input_state: ResMut<CamInputState>,
+    mut pending_input: ResMut<PendingInput>,
+) {
+    let save = match &pending {
+        Some(pending) => &pending.0,
+        None => return,
+    };
+    // wait for the level to spawn
+    if characters.is_empty() {
+        return;
+    }
+    for entity in &characters {
+        commands.entity(entity).despawn_recursive();
+    }
+    for saved in &save.characters {
+        let mut character = spawn_character(
+            &mut commands,
+            saved.numbers.clone(),
+            Vec3::from(saved.position),
+            CamInputState::looking(saved.yaw, saved.pitch),
+        );
+        if saved.controlled {
+            character.insert(Controlled);
+            cam_input_state.yaw = saved.yaw;
+            cam_input_state.pitch = saved.pitch;
+            pending_input.0.yaw = saved.yaw;
+            pending_input.0.pitch = saved.pitch;
+        }
+    }
+    seed.0 = save.seed;
+    rng.0 = StdRng::seed_from_u64(save.seed);
+    tick.0 = save.ticks;
+    stop_watch
+        .0
+        .set_elapsed(Duration::from_secs_f32(save.elapsed_secs));
+    commands.remove_resource::<ReplayRecorder>();
+    commands.remove_resource::<PendingLoad>();
+    commands.insert_resource(RestoredRun);
+}
+
+fn forget_restored_run(mut commands: Commands) {
+    commands.remove_resource::<RestoredRun>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Action;
+    use crate::markers::{marker_file, MarkerKind, SavedMarkers};
+    use crate::solver::Direction;
+    use crate::testing::{maze, tile_center, yaw, TestGame};
+    use bevy::ecs::system::CommandQueue;
+
+    const CORRIDOR: [&str; 7] = [
+        "#######", "#######", "#######", "#.....#", "#######", "#######", "#######",
+    ];
+
+    fn save() -> SaveGame {
+        SaveGame {
+            version: SAVE_VERSION,
+            level: "1".to_owned(),
+            seed: 7,
+            ticks: 600,
+            elapsed_secs: 10.,
+            characters: vec![
+                SavedCharacter {
+                    numbers: vec![1, 2],
+                    position: [0.5, 0., 0.75],
+                    yaw: 1.,
+                    pitch: -0.5,
+                    controlled: false,
+                },
+                SavedCharacter {
+                    numbers: vec![3],
+                    position: [1.25, 0., 0.75],
+                    yaw: 2.,
+                    pitch: 0.,
+                    controlled: true,
+                },
+            ],
+            markers: vec![],
+            date: "2022-08-01".to_owned(),
+        }
+    }
+
+    #[test]
+    fn saves_are_read_back_unless_they_are_newer() {
+        let ron = ron::ser::to_string_pretty(&save(), ron::ser::PrettyConfig::default()).unwrap();
+        assert_eq!(migrate(&ron).unwrap(), save());
+
+        let newer = SaveGame {
+            version: SAVE_VERSION + 1,
+            ..save()
+        };
+        let ron = ron::to_string(&newer).unwrap();
+        assert!(matches!(migrate(&ron), Err(SaveGameError::Newer(_))));
+    }
+
+    #[test]
+    fn loading_restores_characters_and_time() {
+        let mut game = TestGame::new(maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]));
+        game.app
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(restore_game))
+            .insert_resource(PendingLoad(save()));
+        game.step(1);
+
+        assert_eq!(game.character_count(), 2);
+        assert_eq!(game.controlled_numbers(), vec![3]);
+        assert!(game
+            .position_of(2)
+            .abs_diff_eq(Vec3::new(0.5, 0., 0.75), 0.001));
+        assert_eq!(game.app.world.resource::<CamInputState>().yaw, 2.);
+        assert!(game.app.world.get_resource::<PendingLoad>().is_none());
+        // the tick in the same frame already continues the saved run
+        assert_eq!(game.app.world.resource::<SimulationTick>().0, 601);
+        let elapsed = game.app.world.resource::<GameStopWatch>().0.elapsed_secs();
+        assert!(elapsed > 10. && elapsed < 10.1);
+    }
+
+    #[test]
+    fn a_loaded_game_is_completed_as_a_restored_run() {
+        let corridor = maze(&CORRIDOR, &[[1, 3]], [6, 3]);
+        let start = tile_center(&corridor, (5, 3), 0.);
+        let mut game = TestGame::new(corridor);
+        game.app
+            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(restore_game))
+            .insert_resource(PendingLoad(SaveGame {
+                characters: vec![SavedCharacter {
+                    numbers: vec![1],
+                    position: start.into(),
+                    yaw: yaw(Direction::East),
+                    pitch: 0.,
+                    controlled: true,
+                }],
+                ..save()
+            }));
+        game.step(1);
+        assert!(game.app.world.get_resource::<RestoredRun>().is_some());
+
+        game.look(yaw(Direction::East));
+        game.hold(Action::Forward);
+        game.step(60);
+        assert!(game.completed());
+        assert!(game.app.world.get_resource::<RestoredRun>().is_some());
+        assert!(game.app.world.resource::<SimulationTick>().0 > 600);
+    }
+
+    #[test]
+    fn loading_a_slot_keeps_the_markers_of_the_level() {
+        let marker = SavedMarker {
+            owner: 1,
+            kind: MarkerKind::Dot,
+            translation: [0.5, 0., 0.75],
+            rotation: Quat::IDENTITY.to_array(),
+        };
+        let save = SaveGame {
+            level: "load-slot".to_owned(),
+            markers: vec![marker.clone()],
+            ..save()
+        };
+        storage::save(slot_file(1), &save).unwrap();
+        let mut levels = Levels::default();
+        levels.add(&save.level, maze(&CORRIDOR, &[[5, 3]], [6, 3]));
+        let mut level = CurrentLevel(save.level.clone());
+        storage::save(marker_file(&level), &SavedMarkers::default()).unwrap();
+
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        assert!(load_slot(&mut commands, 1, &levels, &mut level));
+        queue.apply(&mut world);
+
+        assert_eq!(world.resource::<PendingLoad>().0.markers, vec![marker]);
+        let kept: SavedMarkers = storage::load(marker_file(&level)).unwrap();
+        assert!(kept.markers.is_empty());
+    }
+}
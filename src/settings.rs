@@ -0,0 +1,383 @@
+use crate::character::{FlyCam, MovementSettings, DEFAULT_SENSITIVITY};
+use crate::storage;
+use crate::widgets::{Widget, WidgetValue};
+use bevy::prelude::*;
+use bevy::render::camera::Projection;
+use bevy::window::WindowMode;
+use serde::{Deserialize, Serialize};
+
+/// Bump this whenever the format of [Settings] changes
+pub const SETTINGS_VERSION: u32 = 1;
+const SETTINGS_FILE: &str = "settings.ron";
+/// Window sizes offered in the settings
+pub const RESOLUTIONS: [(u32, u32); 5] = [
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1600, 900),
+    (1920, 1080),
+];
+const MSAA_SAMPLES: [u32; 2] = [1, 4];
+/// Choices of the breadcrumb setting, `None` follows the difficulty
+const BREADCRUMB_CHOICES: [(Option<bool>, &str); 3] = [
+    (None, "Difficulty"),
+    (Some(true), "On"),
+    (Some(false), "Off"),
+];
+const WINDOW_MODES: [WindowModeSetting; 3] = [
+    WindowModeSetting::Windowed,
+    WindowModeSetting::Borderless,
+    WindowModeSetting::Fullscreen,
+];
+
+pub struct SettingsPlugin;
+
+/// This plugin applies the [Settings] to the running game and saves them whenever they change
+/// The settings are loaded in `main` with [Settings::load], because the window and MSAA have to be
+/// configured before the app starts.
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Settings>()
+            .add_system(apply_settings)
+            .add_system(apply_camera_settings)
+            .add_system(apply_light_settings)
+            .add_system(save_settings);
+    }
+}
+
+/// Options of the player, stored in the config directory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub version: u32,
+    /// Mouse sensitivity relative to [DEFAULT_SENSITIVITY]
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+    /// Master volume from 0 to 1
+    pub volume: f32,
+    /// Whether characters leave breadcrumbs, `None` for the default of the difficulty
+    #[serde(default)]
+    pub breadcrumbs: Option<bool>,
+    pub window_mode: WindowModeSetting,
+    /// Size of the window, when it is not fullscreen
+    pub resolution: (u32, u32),
+    pub msaa: u32,
+    pub shadows: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowModeSetting {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl From<WindowModeSetting> for WindowMode {
+    fn from(mode: WindowModeSetting) -> Self {
+        match mode {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::Borderless => WindowMode::BorderlessFullscreen,
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+}
+
+/// A single entry of the settings menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    Sensitivity,
+    InvertY,
+    Fov,
+    Volume,
+    Breadcrumbs,
+    WindowMode,
+    Resolution,
+    Msaa,
+    Shadows,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 9] = [
+        Setting::Sensitivity,
+        Setting::InvertY,
+        Setting::Fov,
+        Setting::Volume,
+        Setting::Breadcrumbs,
+        Setting::WindowMode,
+        Setting::Resolution,
+        Setting::Msaa,
+        Setting::Shadows,
+    ];
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            sensitivity: 1.,
+            invert_y: false,
+            fov: 45.,
+            volume: 1.,
+            breadcrumbs: None,
+            window_mode: WindowModeSetting::Windowed,
+            resolution: RESOLUTIONS[0],
+            msaa: 1,
+            shadows: true,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the config directory, falling back to the defaults
+    pub fn load() -> Self {
+        let path = match storage::config_dir() {
+            Some(dir) => dir.join(SETTINGS_FILE),
+            None => return Settings::default(),
+        };
+        match storage::read::<Settings>(&path) {
+            Ok(settings) if settings.version == SETTINGS_VERSION => settings,
+            Ok(settings) => {
+                warn!(
+                    "Ignoring settings with version {}, expected {}",
+                    settings.version, SETTINGS_VERSION
+                );
+                Settings::default()
+            }
+            Err(error) => {
+                if !error.is_not_found() {
+                    warn!("Failed to load the settings: {}", error);
+                }
+                Settings::default()
+            }
+        }
+    }
+
+    fn save(&self) {
+        if let Some(dir) = storage::config_dir() {
+            if let Err(error) = storage::write(&dir.join(SETTINGS_FILE), self) {
+                warn!("Failed to save the settings: {}", error);
+            }
+        }
+    }
+
+    /// A widget showing the current value of the setting
+    pub fn widget(&self, setting: Setting) -> Widget {
+        let list = |label: &str, options: Vec<String>, selected: Option<usize>| {
+            Widget::list(label, options, selected.unwrap_or_default())
+        };
+        match setting {
+            Setting::Sensitivity => Widget::slider(
+                "Mouse sensitivity",
+                self.sensitivity,
+                (0.2, 3.),
+                0.1,
+                percent,
+            ),
+            Setting::InvertY => Widget::toggle("Invert mouse Y", self.invert_y),
+            Setting::Fov => Widget::slider("Field of view", self.fov, (30., 110.), 5., degrees),
+            Setting::Volume => Widget::slider("Volume", self.volume, (0., 1.), 0.1, percent),
+            Setting::Breadcrumbs => list(
+                "Breadcrumbs",
+                BREADCRUMB_CHOICES
+                    .iter()
+                    .map(|(_, label)| label.to_string())
+                    .collect(),
+                BREADCRUMB_CHOICES
+                    .iter()
+                    .position(|(choice, _)| *choice == self.breadcrumbs),
+            ),
+            Setting::WindowMode => list(
+                "Window",
+                WINDOW_MODES
+                    .iter()
+                    .map(|mode| format!("{:?}", mode))
+                    .collect(),
+                WINDOW_MODES
+                    .iter()
+                    .position(|mode| *mode == self.window_mode),
+            ),
+            Setting::Resolution => {
+                let resolutions = self.resolutions();
+                list(
+                    "Resolution",
+                    resolutions
+                        .iter()
+                        .map(|(width, height)| format!("{}x{}", width, height))
+                        .collect(),
+                    resolutions
+                        .iter()
+                        .position(|resolution| *resolution == self.resolution),
+                )
+            }
+            Setting::Msaa => list(
+                "MSAA",
+                MSAA_SAMPLES
+                    .iter()
+                    .map(|samples| format!("{}x", samples))
+                    .collect(),
+                MSAA_SAMPLES
+                    .iter()
+                    .position(|samples| *samples == self.msaa),
+            ),
+            Setting::Shadows => Widget::toggle("Shadows", self.shadows),
+        }
+    }
+
+    /// Takes the value of a widget created by [Settings::widget]
+    pub fn apply(&mut self, setting: Setting, value: &WidgetValue) {
+        match (setting, value) {
+            (Setting::Sensitivity, WidgetValue::Slid(value)) => self.sensitivity = *value,
+            (Setting::InvertY, WidgetValue::Toggled(value)) => self.invert_y = *value,
+            (Setting::Fov, WidgetValue::Slid(value)) => self.fov = *value,
+            (Setting::Volume, WidgetValue::Slid(value)) => self.volume = *value,
+            (Setting::Breadcrumbs, WidgetValue::Selected(index)) => {
+                self.breadcrumbs = BREADCRUMB_CHOICES[*index].0
+            }
+            (Setting::WindowMode, WidgetValue::Selected(index)) => {
+                self.window_mode = WINDOW_MODES[*index]
+            }
+            (Setting::Resolution, WidgetValue::Selected(index)) => {
+                self.resolution = self.resolutions()[*index]
+            }
+            (Setting::Msaa, WidgetValue::Selected(index)) => self.msaa = MSAA_SAMPLES[*index],
+            (Setting::Shadows, WidgetValue::Toggled(value)) => self.shadows = *value,
+            _ => warn!("Ignoring {:?} for {:?}", value, setting),
+        }
+    }
+
+    /// The offered resolutions, including a custom one from the config file
+    fn resolutions(&self) -> Vec<(u32, u32)> {
+        let mut resolutions = RESOLUTIONS.to_vec();
+        if !resolutions.contains(&self.resolution) {
+            resolutions.push(self.resolution);
+        }
+        resolutions
+    }
+}
+
+fn percent(value: f32) -> String {
+    format!("{:.0}%", value * 100.)
+}
+
+fn degrees(value: f32) -> String {
+    format!("{:.0}°", value)
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut movement: ResMut<MovementSettings>,
+    mut msaa: ResMut<Msaa>,
+    mut windows: ResMut<Windows>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    movement.sensitivity = DEFAULT_SENSITIVITY * settings.sensitivity;
+    movement.invert_y = settings.invert_y;
+    if msaa.samples != settings.msaa {
+        msaa.samples = settings.msaa;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        let mode = settings.window_mode.into();
+        if window.mode() != mode {
+            window.set_mode(mode);
+        }
+        let (width, height) = (settings.resolution.0 as f32, settings.resolution.1 as f32);
+        if mode == WindowMode::Windowed
+            && (window.requested_width() != width || window.requested_height() != height)
+        {
+            window.set_resolution(width, height);
+        }
+    }
+}
+
+fn apply_camera_settings(
+    settings: Res<Settings>,
+    added: Query<(), Added<FlyCam>>,
+    mut cameras: Query<&mut Projection, With<FlyCam>>,
+) {
+    if !settings.is_changed() && added.is_empty() {
+        return;
+    }
+    for mut projection in &mut cameras {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.fov.to_radians();
+        }
+    }
+}
+
+fn apply_light_settings(
+    settings: Res<Settings>,
+    added: Query<(), Added<PointLight>>,
+    mut lights: Query<&mut PointLight>,
+) {
+    if !settings.is_changed() && added.is_empty() {
+        return;
+    }
+    for mut light in &mut lights {
+        light.shadows_enabled = settings.shadows;
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    // the loaded settings do not need to be written back
+    if settings.is_changed() && !settings.is_added() {
+        settings.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Changes the setting like its widget in the menu would
+    fn step(settings: &mut Settings, setting: Setting, forward: bool) {
+        if let Some(value) = settings.widget(setting).step(forward) {
+            settings.apply(setting, &value);
+        }
+    }
+
+    #[test]
+    fn settings_change_through_their_widgets() {
+        let mut settings = Settings::default();
+        for _ in 0..20 {
+            step(&mut settings, Setting::Volume, true);
+        }
+        assert_eq!(settings.volume, 1.);
+        for _ in 0..3 {
+            step(&mut settings, Setting::Volume, false);
+        }
+        assert_eq!(settings.widget(Setting::Volume).text(false), "Volume: 70%");
+
+        step(&mut settings, Setting::Resolution, false);
+        assert_eq!(settings.resolution, RESOLUTIONS[RESOLUTIONS.len() - 1]);
+        step(&mut settings, Setting::Resolution, true);
+        assert_eq!(settings.resolution, RESOLUTIONS[0]);
+        // a resolution from the config file stays selectable
+        settings.resolution = (640, 480);
+        step(&mut settings, Setting::Resolution, true);
+        assert_eq!(settings.resolution, RESOLUTIONS[0]);
+
+        step(&mut settings, Setting::Shadows, true);
+        assert!(!settings.shadows);
+
+        step(&mut settings, Setting::Breadcrumbs, false);
+        assert_eq!(settings.breadcrumbs, Some(false));
+        step(&mut settings, Setting::Breadcrumbs, true);
+        assert_eq!(settings.breadcrumbs, None);
+    }
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let mut settings = Settings::default();
+        step(&mut settings, Setting::WindowMode, true);
+        step(&mut settings, Setting::Fov, true);
+        let ron = ron::to_string(&settings).unwrap();
+        assert_eq!(ron::from_str::<Settings>(&ron).unwrap(), settings);
+        // settings written before the breadcrumb setting existed still load
+        let older = ron.replace("breadcrumbs:None,", "");
+        assert_ne!(older, ron);
+        assert_eq!(ron::from_str::<Settings>(&older).unwrap(), settings);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::actions::ActionSet;
+use crate::GameState;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::time::{FixedTimestep, FixedTimesteps, Stopwatch};
+use bevy::transform::TransformSystem;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Length of one gameplay tick in seconds
+pub const TICK_SECONDS: f64 = 1. / 60.;
+const SIMULATION_TIMESTEP: &str = "simulation_timestep";
+
+pub struct SimulationPlugin;
+
+/// This plugin runs all gameplay logic in fixed ticks
+/// Systems added to [SimulationStage] with [simulation_running] see the same [TickInput]s
+/// and the same delta time on every machine, so a run can be reproduced from its inputs.
+/// Entities with a [Position] get their `Transform` interpolated between the last two ticks.
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingInput>()
+            .init_resource::<TickInput>()
+            .init_resource::<SimulationTick>()
+            .init_resource::<GameStopWatch>()
+            .insert_resource(SimulationSeed(rand::random()))
+            .insert_resource(SimulationRng(StdRng::seed_from_u64(0)))
+            .add_stage_after(
+                CoreStage::Update,
+                SimulationStage,
+                SystemStage::parallel().with_run_criteria(
+                    FixedTimestep::step(TICK_SECONDS).with_label(SIMULATION_TIMESTEP),
+                ),
+            )
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(simulation_running)
+                    .with_system(begin_tick.label(SimulationSystem::BeginTick)),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(reset_simulation))
+            .add_system_set(SystemSet::on_exit(GameState::Playing).with_system(reroll_seed))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_positions
+                    .label(SimulationSystem::Interpolate)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct SimulationStage;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum SimulationSystem {
+    BeginTick,
+    /// Systems advancing the game state, after the tick began
+    Gameplay,
+    Interpolate,
+}
+
+/// Run criteria for gameplay systems in the [SimulationStage]
+pub fn simulation_running(state: Res<State<GameState>>) -> ShouldRun {
+    if state.current() == &GameState::Playing {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Number of ticks simulated since the level started
+#[derive(Default)]
+pub struct SimulationTick(pub u64);
+
+/// Time spent in the current level, advanced by every tick
+pub struct GameStopWatch(pub Stopwatch);
+
+impl Default for GameStopWatch {
+    fn default() -> Self {
+        GameStopWatch(Stopwatch::new())
+    }
+}
+
+/// Seed of the [SimulationRng] for the next run
+pub struct SimulationSeed(pub u64);
+
+/// Source of all randomness in gameplay, reseeded from [SimulationSeed] when a level starts
+pub struct SimulationRng(pub StdRng);
+
+/// The player input for the current tick
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TickInput {
+    /// Actions held down during the tick
+    pub held: ActionSet,
+    /// Actions that were pressed since the previous tick
+    pub triggered: ActionSet,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+/// Input collected from frames until the next tick consumes it
+#[derive(Default)]
+pub struct PendingInput(pub TickInput);
+
+/// Authoritative position of a simulated entity
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Position {
+    pub current: Vec3,
+    pub previous: Vec3,
+}
+
+impl Position {
+    pub fn new(translation: Vec3) -> Self {
+        Position {
+            current: translation,
+            previous: translation,
+        }
+    }
+}
+
+fn reset_simulation(
+    seed: Res<SimulationSeed>,
+    mut rng: ResMut<SimulationRng>,
+    mut tick: ResMut<SimulationTick>,
+    mut stop_watch: ResMut<GameStopWatch>,
+    mut pending: ResMut<PendingInput>,
+) {
+    rng.0 = StdRng::seed_from_u64(seed.0);
+    tick.0 = 0;
+    stop_watch.0.reset();
+    stop_watch.0.unpause();
+    pending.0 = TickInput::default();
+}
+
+fn reroll_seed(mut seed: ResMut<SimulationSeed>) {
+    seed.0 = rand::random();
+}
+
+fn begin_tick(
+    mut tick: ResMut<SimulationTick>,
+    mut stop_watch: ResMut<GameStopWatch>,
+    mut pending: ResMut<PendingInput>,
+    mut tick_input: ResMut<TickInput>,
+    mut positions: Query<&mut Position>,
+) {
+    tick.0 += 1;
+    stop_watch.0.tick(Duration::from_secs_f64(TICK_SECONDS));
+    *tick_input = pending.0.clone();
+    pending.0.triggered = ActionSet::default();
+    for mut position in &mut positions {
+        position.previous = position.current;
+    }
+}
+
+fn interpolate_positions(
+    fixed_timesteps: Res<FixedTimesteps>,
+    mut positions: Query<(&Position, &mut Transform)>,
+) {
+    let alpha = fixed_timesteps
+        .get(SIMULATION_TIMESTEP)
+        .map(|timestep| timestep.overstep_percentage() as f32)
+        .unwrap_or(1.);
+    for (position, mut transform) in &mut positions {
+        transform.translation = position.previous.lerp(position.current, alpha);
+    }
+}
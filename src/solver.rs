@@ -0,0 +1,327 @@
+use crate::map::Maze;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+/// A maze tile as (x, y)
+pub type Tile = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+
+    /// The neighbouring tile in this direction, if it is not outside of the maze
+    pub fn step(&self, (x, y): Tile, maze: &Maze) -> Option<Tile> {
+        let tile = match self {
+            Direction::North => (x, y.checked_sub(1)?),
+            Direction::East => (x + 1, y),
+            Direction::South => (x, y + 1),
+            Direction::West => (x.checked_sub(1)?, y),
+        };
+        (tile.0 < maze.size && tile.1 < maze.size).then_some(tile)
+    }
+}
+
+/// One thing the player does to beat a level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Take control of the character containing the given number
+    Control(u8),
+    /// Walk the controlled character one tile
+    Walk(Direction),
+    /// Combine with the character containing the given number, which is on the same tile
+    Combine(u8),
+    /// Walk into the exit, which is next to the controlled character
+    Leave(Direction),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub steps: Vec<Step>,
+}
+
+impl Solution {
+    /// Number of tiles walked, the measure the solver minimizes
+    pub fn walked_tiles(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step, Step::Walk(_) | Step::Leave(_)))
+            .count()
+    }
+}
+
+/// Characters standing on the same tile are still separate groups until they combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Group {
+    /// Bit `n - 1` is set for every number `n` in the character
+    members: u32,
+    tile: Tile,
+}
+
+impl Group {
+    fn lowest_number(&self) -> u8 {
+        self.members.trailing_zeros() as u8 + 1
+    }
+}
+
+/// Abstract game state the solver searches through
+///
+/// Only things that change what the player can do belong in here. Once levels get
+/// gates or doors, their state has to be added next to the groups and respected in [walkable].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct State {
+    /// Sorted, so that equal situations compare equal
+    groups: Vec<Group>,
+    controlled: u32,
+    left: bool,
+}
+
+/// Searches for the shortest way to combine all parts and leave the maze
+///
+/// Returns `None` if the level cannot be beaten.
+pub fn solve(maze: &Maze) -> Option<Solution> {
+    let mut groups: Vec<Group> = maze
+        .level
+        .spawns
+        .iter()
+        .enumerate()
+        .map(|(index, spawn)| Group {
+            members: 1 << index,
+            tile: maze.spawn_slot(*spawn),
+        })
+        .collect();
+    if groups.is_empty() {
+        return None;
+    }
+    groups.sort();
+    let start = State {
+        groups,
+        controlled: 1,
+        left: false,
+    };
+
+    let mut costs = HashMap::from([(start.clone(), 0)]);
+    let mut came_from: HashMap<State, (State, Vec<Step>)> = HashMap::new();
+    let mut queue = BinaryHeap::from([Reverse((0, start))]);
+    while let Some(Reverse((cost, state))) = queue.pop() {
+        if state.left {
+            return Some(Solution {
+                steps: collect_steps(&came_from, state),
+            });
+        }
+        if costs.get(&state).is_some_and(|known| *known < cost) {
+            continue;
+        }
+        for (next, steps, walked) in transitions(maze, &state) {
+            let next_cost = cost + walked;
+            if costs.get(&next).is_none_or(|known| next_cost < *known) {
+                costs.insert(next.clone(), next_cost);
+                came_from.insert(next.clone(), (state.clone(), steps));
+                queue.push(Reverse((next_cost, next)));
+            }
+        }
+    }
+
+    None
+}
+
+fn collect_steps(came_from: &HashMap<State, (State, Vec<Step>)>, mut state: State) -> Vec<Step> {
+    let mut parts = vec![];
+    while let Some((previous, steps)) = came_from.get(&state) {
+        parts.push(steps.clone());
+        state = previous.clone();
+    }
+    parts.into_iter().rev().flatten().collect()
+}
+
+/// All states reachable by walking one group to another and combining them,
+/// or by leaving once every part is combined
+fn transitions(maze: &Maze, state: &State) -> Vec<(State, Vec<Step>, usize)> {
+    let mut transitions = vec![];
+    if let [group] = state.groups[..] {
+        if let Some((path, exit_direction)) = path_to_exit(maze, group.tile) {
+            let mut steps = control(state, &group);
+            steps.extend(path.iter().copied().map(Step::Walk));
+            steps.push(Step::Leave(exit_direction));
+            let next = State {
+                left: true,
+                ..state.clone()
+            };
+            transitions.push((next, steps, path.len() + 1));
+        }
+        return transitions;
+    }
+
+    for mover in &state.groups {
+        for target in state.groups.iter().filter(|target| *target != mover) {
+            let path = match path_between(maze, mover.tile, target.tile) {
+                Some(path) => path,
+                None => continue,
+            };
+            let mut steps = control(state, mover);
+            steps.extend(path.iter().copied().map(Step::Walk));
+            steps.push(Step::Combine(target.lowest_number()));
+            let combined = Group {
+                members: mover.members | target.members,
+                tile: target.tile,
+            };
+            let mut groups: Vec<Group> = state
+                .groups
+                .iter()
+                .filter(|group| *group != mover && *group != target)
+                .copied()
+                .chain([combined])
+                .collect();
+            groups.sort();
+            let next = State {
+                groups,
+                controlled: combined.members,
+                left: false,
+            };
+            transitions.push((next, steps, path.len()));
+        }
+    }
+    transitions
+}
+
+fn control(state: &State, group: &Group) -> Vec<Step> {
+    if state.controlled == group.members {
+        vec![]
+    } else {
+        vec![Step::Control(group.lowest_number())]
+    }
+}
+
+fn walkable(maze: &Maze, (x, y): Tile) -> bool {
+    !maze.is_wall(x, y)
+}
+
+fn path_between(maze: &Maze, from: Tile, to: Tile) -> Option<Vec<Direction>> {
+    find_path(maze, from, |tile| tile == to).map(|(path, _)| path)
+}
+
+/// Shortest path to a tile next to the exit and the direction of the exit from there
+fn path_to_exit(maze: &Maze, from: Tile) -> Option<(Vec<Direction>, Direction)> {
+    let exit = (maze.level.exit[0], maze.level.exit[1]);
+    let exit_direction = |tile: Tile| {
+        Direction::ALL
+            .into_iter()
+            .find(|direction| direction.step(tile, maze) == Some(exit))
+    };
+    let (path, end) = find_path(maze, from, |tile| exit_direction(tile).is_some())?;
+    Some((path, exit_direction(end)?))
+}
+
+/// Breadth first search over walkable tiles
+fn find_path(
+    maze: &Maze,
+    from: Tile,
+    is_goal: impl Fn(Tile) -> bool,
+) -> Option<(Vec<Direction>, Tile)> {
+    let mut visited = HashSet::from([from]);
+    let mut came_from: HashMap<Tile, (Tile, Direction)> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(tile) = queue.pop_front() {
+        if is_goal(tile) {
+            let mut path = vec![];
+            let mut current = tile;
+            while let Some((previous, direction)) = came_from.get(&current) {
+                path.push(*direction);
+                current = *previous;
+            }
+            path.reverse();
+            return Some((path, tile));
+        }
+        for direction in Direction::ALL {
+            let next = match direction.step(tile, maze) {
+                Some(next) if walkable(maze, next) => next,
+                _ => continue,
+            };
+            if visited.insert(next) {
+                came_from.insert(next, (tile, direction));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{maze, shipped_levels, TestGame};
+
+    const CORRIDOR: [&str; 7] = [
+        "#######", "#######", "#######", "#.....#", "#######", "#######", "#######",
+    ];
+
+    #[test]
+    fn solves_corridor() {
+        let corridor = maze(&CORRIDOR, &[[1, 3], [3, 3], [5, 3]], [6, 3]);
+        let solution = solve(&corridor).expect("The corridor is winnable");
+        assert_eq!(solution.walked_tiles(), 5);
+
+        let mut game = TestGame::new(corridor);
+        game.play(&solution);
+        assert!(game.completed());
+    }
+
+    #[test]
+    fn solves_loop() {
+        let ring = [
+            "#######", "#.....#", "#.###.#", "#.###.#", "#.###.#", "#.....#", "#######",
+        ];
+        let ring = maze(&ring, &[[5, 1], [5, 5], [1, 5]], [3, 6]);
+        let solution = solve(&ring).expect("All parts can meet");
+
+        let mut game = TestGame::new(ring);
+        game.play(&solution);
+        assert!(game.completed());
+    }
+
+    #[test]
+    fn separated_parts_cannot_combine() {
+        let split = [
+            "#######", "#######", "#######", "#..#..#", "#######", "#######", "#######",
+        ];
+        assert_eq!(
+            solve(&maze(&split, &[[1, 3], [2, 3], [4, 3]], [6, 3])),
+            None
+        );
+    }
+
+    #[test]
+    fn unreachable_exit() {
+        let blocked = [
+            "#######", "#######", "#######", "#...#.#", "#######", "#######", "#######",
+        ];
+        assert_eq!(
+            solve(&maze(&blocked, &[[1, 3], [2, 3], [3, 3]], [6, 3])),
+            None
+        );
+    }
+
+    #[test]
+    fn shipped_levels_are_winnable() {
+        let levels = shipped_levels();
+        assert!(!levels.is_empty());
+        for (name, level) in levels {
+            let solution = solve(&level).unwrap_or_else(|| panic!("Level {} is unwinnable", name));
+            let mut game = TestGame::new(level);
+            game.play(&solution);
+            assert!(game.completed(), "The solution of level {} failed", name);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Directory for files the game writes, like replays and save games
+///
+/// There is no file system on the web, so this is always `None` there.
+/// Tests get a temporary directory instead, so they never touch the files of the player.
+pub fn data_dir() -> Option<PathBuf> {
+    #[cfg(test)]
+    {
+        Some(test_dir().join("data"))
+    }
+    #[cfg(all(not(test), not(target_arch = "wasm32")))]
+    {
+        directories::ProjectDirs::from("me", "nikl", "blubs_dilemma")
+            .map(|dirs| dirs.data_dir().to_path_buf())
+    }
+    #[cfg(all(not(test), target_arch = "wasm32"))]
+    {
+        None
+    }
+}
+
+/// Directory for the configuration of the game, which is `None` on the web as well
+pub fn config_dir() -> Option<PathBuf> {
+    #[cfg(test)]
+    {
+        Some(test_dir().join("config"))
+    }
+    #[cfg(all(not(test), not(target_arch = "wasm32")))]
+    {
+        directories::ProjectDirs::from("me", "nikl", "blubs_dilemma")
+            .map(|dirs| dirs.config_dir().to_path_buf())
+    }
+    #[cfg(all(not(test), target_arch = "wasm32"))]
+    {
+        None
+    }
+}
+
+/// Stands in for the directories of the player while testing, one per test run
+#[cfg(test)]
+fn test_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("blubs-dilemma-test-{}", std::process::id()))
+}
+
+/// Serializes the value as RON into the given path inside the [data_dir]
+pub fn save<T: Serialize>(
+    relative_path: impl AsRef<Path>,
+    value: &T,
+) -> Result<PathBuf, StorageError> {
+    let path = data_dir()
+        .ok_or(StorageError::NoDataDir)?
+        .join(relative_path);
+    write(&path, value)?;
+    Ok(path)
+}
+
+/// Deserializes RON from the given path inside the [data_dir]
+pub fn load<T: DeserializeOwned>(relative_path: impl AsRef<Path>) -> Result<T, StorageError> {
+    let path = data_dir()
+        .ok_or(StorageError::NoDataDir)?
+        .join(relative_path);
+    read(&path)
+}
+
+/// Reads the given file inside the [data_dir] as text, for formats that are parsed in steps
+pub fn load_text(relative_path: impl AsRef<Path>) -> Result<String, StorageError> {
+    let path = data_dir()
+        .ok_or(StorageError::NoDataDir)?
+        .join(relative_path);
+    Ok(std::fs::read_to_string(path)?)
+}
+
+/// Deletes the given file inside the [data_dir], if it exists
+pub fn remove(relative_path: impl AsRef<Path>) -> Result<(), StorageError> {
+    let path = data_dir()
+        .ok_or(StorageError::NoDataDir)?
+        .join(relative_path);
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The part of every versioned file needed to read the rest
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+/// Reads a versioned file inside the [data_dir], see [parse_versioned]
+pub fn load_versioned<T: DeserializeOwned>(
+    relative_path: impl AsRef<Path>,
+    current: u32,
+    migrate: fn(u32, &str) -> Result<T, StorageError>,
+) -> Result<T, StorageError> {
+    parse_versioned(&load_text(relative_path)?, current, migrate)
+}
+
+/// Reads RON with a `version` field of any known version
+///
+/// Older versions are converted by `migrate`, newer ones are refused with [StorageError::Newer].
+pub fn parse_versioned<T: DeserializeOwned>(
+    ron: &str,
+    current: u32,
+    migrate: fn(u32, &str) -> Result<T, StorageError>,
+) -> Result<T, StorageError> {
+    let header: VersionHeader = ron::from_str(ron)?;
+    match header.version {
+        version if version == current => Ok(ron::from_str(ron)?),
+        version if version > current => Err(StorageError::Newer(version)),
+        version => migrate(version, ron),
+    }
+}
+
+/// Saves like [save], unless the file was written by a newer version, whose data would be lost
+pub fn save_versioned<T: Serialize>(
+    relative_path: impl AsRef<Path>,
+    value: &T,
+    current: u32,
+) -> Result<PathBuf, StorageError> {
+    let existing = load_text(&relative_path)
+        .ok()
+        .and_then(|ron| ron::from_str::<VersionHeader>(&ron).ok());
+    if let Some(VersionHeader { version }) = existing {
+        if version > current {
+            return Err(StorageError::Newer(version));
+        }
+    }
+    save(relative_path, value)
+}
+
+/// Serializes the value as RON into the given file, creating missing directories
+pub fn write<T: Serialize>(path: &Path, value: &T) -> Result<(), StorageError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let ron = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+    std::fs::write(path, ron)?;
+    Ok(())
+}
+
+/// Deserializes RON from the given file
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<T, StorageError> {
+    let ron = std::fs::read_to_string(path)?;
+    Ok(ron::de::from_str(&ron)?)
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    NoDataDir,
+    Io(std::io::Error),
+    Ron(ron::Error),
+    /// Written by a newer version of the game
+    Newer(u32),
+    /// Written by an older version, which can not be converted
+    Outdated(u32),
+}
+
+impl StorageError {
+    /// True if the file simply does not exist (yet)
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, StorageError::Io(error) if error.kind() == std::io::ErrorKind::NotFound)
+    }
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NoDataDir => write!(f, "no data directory available"),
+            StorageError::Io(error) => write!(f, "{}", error),
+            StorageError::Ron(error) => write!(f, "{}", error),
+            StorageError::Newer(version) => write!(
+                f,
+                "the file has version {}, which is from a newer version of the game",
+                version
+            ),
+            StorageError::Outdated(version) => {
+                write!(
+                    f,
+                    "the file has version {}, which is no longer supported",
+                    version
+                )
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        StorageError::Io(error)
+    }
+}
+
+impl From<ron::Error> for StorageError {
+    fn from(error: ron::Error) -> Self {
+        StorageError::Ron(error)
+    }
+}
@@ -0,0 +1,246 @@
+use crate::actions::{Action, ActionSet};
+use crate::character::{Character, Controlled, LevelCompletedEvent, PLAYER_RADIUS};
+use crate::levels::read_directory;
+use crate::loading::LabyrinthLevel;
+use crate::map::{Maze, PIXEL_WORLD_SIZE};
+use crate::simulation::TickInput;
+use crate::simulation::{PendingInput, Position, SimulationStage};
+use crate::solver::{Direction, Solution, Step, Tile};
+use crate::{GameState, GameplayPlugin};
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::prelude::*;
+use bevy::transform::TransformPlugin;
+use leafwing_input_manager::Actionlike;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::path::Path;
+
+/// Upper bound for a single step of a solution, so that a broken solution fails instead of hanging
+const MAX_STEP_TICKS: usize = 1000;
+
+/// A headless game for tests
+///
+/// Every call to [TestGame::step] runs exactly one simulation tick with the injected input.
+pub struct TestGame {
+    pub app: App,
+    completed_reader: ManualEventReader<LevelCompletedEvent>,
+    completed_count: usize,
+}
+
+impl TestGame {
+    pub fn new(maze: Maze) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_state(GameState::Playing)
+            .insert_resource(maze)
+            .add_plugin(GameplayPlugin);
+        app.schedule
+            .get_stage_mut::<SystemStage>(&SimulationStage)
+            .unwrap()
+            .set_run_criteria(|| ShouldRun::Yes);
+        // enter the playing state and spawn the level
+        app.update();
+
+        TestGame {
+            app,
+            completed_reader: ManualEventReader::default(),
+            completed_count: 0,
+        }
+    }
+
+    /// Runs the given number of simulation ticks
+    pub fn step(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.app.update();
+            let events = self.app.world.resource::<Events<LevelCompletedEvent>>();
+            self.completed_count += self.completed_reader.iter(events).count();
+        }
+    }
+
+    pub fn hold(&mut self, action: Action) {
+        self.pending().held.insert(action);
+    }
+
+    pub fn release_all(&mut self) {
+        self.pending().held = ActionSet::default();
+    }
+
+    /// Presses the action for the next tick only
+    pub fn trigger(&mut self, action: Action) {
+        self.pending().triggered.insert(action);
+    }
+
+    /// Looks in the given direction, rotating around the y axis
+    pub fn look(&mut self, yaw: f32) {
+        self.pending().yaw = yaw;
+    }
+
+    /// Position of the character containing the given number
+    pub fn position_of(&mut self, number: u8) -> Vec3 {
+        self.app
+            .world
+            .query::<(&Character, &Position)>()
+            .iter(&self.app.world)
+            .find(|(character, _)| character.numbers().contains(&number))
+            .map(|(_, position)| position.current)
+            .expect("No character with that number")
+    }
+
+    pub fn controlled_position(&mut self) -> Vec3 {
+        self.app
+            .world
+            .query_filtered::<&Position, With<Controlled>>()
+            .single(&self.app.world)
+            .current
+    }
+
+    pub fn controlled_numbers(&mut self) -> Vec<u8> {
+        self.app
+            .world
+            .query_filtered::<&Character, With<Controlled>>()
+            .single(&self.app.world)
+            .numbers()
+            .to_vec()
+    }
+
+    pub fn character_count(&mut self) -> usize {
+        self.app
+            .world
+            .query::<&Character>()
+            .iter(&self.app.world)
+            .count()
+    }
+
+    /// True once a [LevelCompletedEvent] was sent
+    pub fn completed(&self) -> bool {
+        self.completed_count > 0
+    }
+
+    /// Walks the controlled character straight towards `target` until it is closer than `distance`
+    pub fn walk_to(&mut self, target: Vec3, distance: f32) {
+        for _ in 0..MAX_STEP_TICKS {
+            let offset = target - self.controlled_position();
+            if offset.x.hypot(offset.z) < distance {
+                self.release_all();
+                return;
+            }
+            self.look((-offset.x).atan2(-offset.z));
+            self.hold(Action::Forward);
+            self.step(1);
+        }
+        panic!("Failed to walk to {:?}", target);
+    }
+
+    /// Plays all steps of the given solution
+    pub fn play(&mut self, solution: &Solution) {
+        for step in &solution.steps {
+            match step {
+                Step::Control(number) => {
+                    let action = Action::variants()
+                        .find(|action| action.controlled_number() == Some(*number))
+                        .expect("No action to control that number");
+                    self.trigger(action);
+                    self.step(1);
+                }
+                Step::Walk(direction) => {
+                    let position = self.controlled_position();
+                    let maze = self.app.world.resource::<Maze>();
+                    let tile = direction
+                        .step(maze.slot(position), maze)
+                        .expect("Walked out of the maze");
+                    let target = tile_center(maze, tile, position.y);
+                    self.walk_to(target, 0.02);
+                }
+                Step::Combine(number) => {
+                    let target = self.position_of(*number);
+                    self.walk_to(target, PLAYER_RADIUS * 1.5);
+                    self.trigger(Action::Combine);
+                    self.step(1);
+                }
+                Step::Leave(direction) => {
+                    self.look(yaw(*direction));
+                    self.hold(Action::Forward);
+                    for _ in 0..MAX_STEP_TICKS {
+                        if self.completed() {
+                            break;
+                        }
+                        self.step(1);
+                    }
+                    self.release_all();
+                }
+            }
+        }
+    }
+
+    fn pending(&mut self) -> &mut TickInput {
+        &mut self.app.world.resource_mut::<PendingInput>().into_inner().0
+    }
+}
+
+/// Builds a maze from rows of `#` (wall) and `.` (floor)
+///
+/// Spawns and the exit are given in tiles.
+pub fn maze(rows: &[&str], spawns: &[[usize; 2]], exit: [usize; 2]) -> Maze {
+    let size = rows.len();
+    let open = rows
+        .iter()
+        .flat_map(|row| row.chars().map(|tile| tile == '.'))
+        .collect();
+    let spawns = spawns
+        .iter()
+        .map(|[x, y]| tile_to_spawn(size, *x, *y))
+        .collect();
+    Maze::new(
+        size,
+        open,
+        LabyrinthLevel {
+            image: None,
+            spawns,
+            exit,
+            marker_budget: default(),
+            private_markers: false,
+        },
+    )
+}
+
+/// Level files give spawns in tiles relative to the center of the maze
+fn tile_to_spawn(size: usize, x: usize, y: usize) -> [f32; 2] {
+    let half = size as f32 / 2.;
+    [x as f32 - half, y as f32 - half]
+}
+
+/// Mazes of all levels under `assets/mazes` with their name
+pub fn shipped_levels() -> Vec<(String, Maze)> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/mazes");
+    let mut levels: Vec<(String, Maze)> = read_directory(&directory)
+        .into_iter()
+        .map(|(path, level)| {
+            level.unwrap_or_else(|error| panic!("Invalid level {:?}: {}", path, error))
+        })
+        .collect();
+    levels.sort_by(|a, b| a.0.cmp(&b.0));
+    levels
+}
+
+/// World position of the center of the given tile at the given height
+pub fn tile_center(maze: &Maze, (x, y): Tile, height: f32) -> Vec3 {
+    let world_width = maze.world_width();
+    Vec3::new(
+        x as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
+        height,
+        y as f32 * PIXEL_WORLD_SIZE - world_width / 2.,
+    )
+}
+
+/// Yaw of a character looking in the given direction
+pub fn yaw(direction: Direction) -> f32 {
+    match direction {
+        Direction::North => 0.,
+        Direction::East => -FRAC_PI_2,
+        Direction::South => PI,
+        Direction::West => FRAC_PI_2,
+    }
+}
@@ -1,23 +1,24 @@
 use crate::loading::FontAssets;
+use crate::map::LevelEntity;
+use crate::notifications::{Notifications, MAX_TOASTS};
+use crate::simulation::GameStopWatch;
 use crate::GameState;
 use bevy::prelude::*;
-use bevy::time::Stopwatch;
 
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Notification>()
-            .add_system_set(
-                SystemSet::on_enter(GameState::Playing)
-                    .with_system(spawn_timer)
-                    .with_system(spawn_notification),
-            )
-            .add_system_set(
-                SystemSet::on_update(GameState::Playing)
-                    .with_system(update_timer)
-                    .with_system(update_notification),
-            );
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing)
+                .with_system(spawn_timer)
+                .with_system(spawn_notifications),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(update_timer)
+                .with_system(update_notifications),
+        );
     }
 }
 
@@ -30,16 +31,16 @@ struct NotificationText;
 #[derive(Component)]
 struct NotificationTextBox;
 
-struct GameStopWatch(Stopwatch);
+/// One of the stacked toasts, counted from the bottom
+#[derive(Component)]
+struct ToastBox(usize);
 
-impl Default for GameStopWatch {
-    fn default() -> Self {
-        GameStopWatch(Stopwatch::new())
-    }
-}
+#[derive(Component)]
+struct ToastText(usize);
+
+const BOX_COLOR: Color = Color::rgba(0.7, 0.7, 0.7, 0.7);
 
 fn spawn_timer(mut commands: Commands, font_assets: Res<FontAssets>) {
-    commands.insert_resource(GameStopWatch::default());
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -62,6 +63,7 @@ fn spawn_timer(mut commands: Commands, font_assets: Res<FontAssets>) {
             }),
             ..Default::default()
         })
+        .insert(LevelEntity)
         .with_children(|parent| {
             parent
                 .spawn_bundle(TextBundle {
@@ -82,7 +84,12 @@ fn spawn_timer(mut commands: Commands, font_assets: Res<FontAssets>) {
         });
 }
 
-fn spawn_notification(mut commands: Commands, font_assets: Res<FontAssets>) {
+fn spawn_notifications(mut commands: Commands, font_assets: Res<FontAssets>) {
+    let text_style = TextStyle {
+        font: font_assets.fira_sans.clone(),
+        font_size: 40.0,
+        color: Color::rgb_u8(34, 32, 52),
+    };
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -100,72 +107,112 @@ fn spawn_notification(mut commands: Commands, font_assets: Res<FontAssets>) {
             color: UiColor(Color::NONE),
             ..Default::default()
         })
+        .insert(LevelEntity)
         .insert(NotificationTextBox)
         .with_children(|parent| {
             parent
-                .spawn_bundle(TextBundle {
-                    text: Text {
-                        sections: vec![TextSection {
-                            value: "".to_string(),
-                            style: TextStyle {
-                                font: font_assets.fira_sans.clone(),
-                                font_size: 40.0,
-                                color: Color::rgb_u8(34, 32, 52),
-                            },
-                        }],
-                        alignment: Default::default(),
-                    },
-                    ..Default::default()
-                })
+                .spawn_bundle(TextBundle::from_section("", text_style.clone()))
                 .insert(NotificationText);
         });
+    // toasts stack upwards above the prompt, the first slot is the lowest
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(97.), Val::Auto),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                position: UiRect {
+                    bottom: Val::Px(70.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            color: UiColor(Color::NONE),
+            ..Default::default()
+        })
+        .insert(LevelEntity)
+        .with_children(|parent| {
+            for slot in 0..MAX_TOASTS {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            margin: UiRect::all(Val::Px(3.)),
+                            padding: UiRect::new(
+                                Val::Px(8.),
+                                Val::Px(8.),
+                                Val::Px(2.),
+                                Val::Px(2.),
+                            ),
+                            ..Default::default()
+                        },
+                        color: UiColor(Color::NONE),
+                        visibility: Visibility { is_visible: false },
+                        ..Default::default()
+                    })
+                    .insert(ToastBox(slot))
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(TextBundle::from_section(
+                                "",
+                                TextStyle {
+                                    font_size: 30.0,
+                                    ..text_style.clone()
+                                },
+                            ))
+                            .insert(ToastText(slot));
+                    });
+            }
+        });
 }
 
-#[derive(Default)]
-pub struct Notification {
-    pub remove_when: Option<f64>,
-    pub text: Option<String>,
-}
-
-fn update_notification(
-    mut notification: ResMut<Notification>,
+fn update_notifications(
+    notifications: Res<Notifications>,
     time: Res<Time>,
-    mut notification_text: Query<&mut Text, With<NotificationText>>,
-    mut notification_text_box: Query<
-        &mut UiColor,
-        (With<NotificationTextBox>, Without<NotificationText>),
+    mut prompt_text: Query<&mut Text, With<NotificationText>>,
+    mut prompt_box: Query<&mut UiColor, With<NotificationTextBox>>,
+    mut toast_boxes: Query<
+        (&ToastBox, &mut UiColor, &mut Visibility),
+        Without<NotificationTextBox>,
     >,
+    mut toast_texts: Query<(&ToastText, &mut Text, &mut Visibility), Without<NotificationText>>,
 ) {
-    if let Some(remove_when) = notification.remove_when {
-        if remove_when < time.seconds_since_startup() {
-            notification.text = None;
-            notification.remove_when = None;
-            notification_text_box.single_mut().0 = Color::NONE;
-        }
+    let prompt = notifications.prompt();
+    if let (Ok(mut text), Ok(mut color)) =
+        (prompt_text.get_single_mut(), prompt_box.get_single_mut())
+    {
+        text.sections[0].value = prompt.map_or_else(String::new, |prompt| prompt.text.clone());
+        color.0 = if prompt.is_some() {
+            BOX_COLOR
+        } else {
+            Color::NONE
+        };
     }
-    if !notification.is_changed() {
-        return;
+
+    let toasts = notifications.toasts(time.seconds_since_startup());
+    for (ToastBox(slot), mut color, mut visibility) in &mut toast_boxes {
+        let toast = toasts.get(*slot);
+        visibility.is_visible = toast.is_some();
+        if let Some((_, alpha)) = toast {
+            color.0 = BOX_COLOR;
+            color.0.set_a(BOX_COLOR.a() * alpha);
+        }
     }
-    if let Some(text) = &notification.text {
-        notification_text.single_mut().sections[0].value = text.clone();
-        notification_text_box.single_mut().0 = Color::Rgba {
-            red: 0.7,
-            green: 0.7,
-            blue: 0.7,
-            alpha: 0.7,
-        };
-    } else {
-        notification_text.single_mut().sections[0].value = "".to_string();
-        notification_text_box.single_mut().0 = Color::NONE;
+    for (ToastText(slot), mut text, mut visibility) in &mut toast_texts {
+        let toast = toasts.get(*slot);
+        visibility.is_visible = toast.is_some();
+        if let Some((toast, alpha)) = toast {
+            text.sections[0].value.clone_from(&toast.text);
+            text.sections[0].style.color.set_a(*alpha);
+        }
     }
 }
 
 fn update_timer(
-    time: Res<Time>,
-    mut game_stop_watch: ResMut<GameStopWatch>,
+    game_stop_watch: Res<GameStopWatch>,
     mut timer_text: Query<&mut Text, With<TimerText>>,
 ) {
-    game_stop_watch.0.tick(time.delta());
     let score = game_stop_watch.0.elapsed_secs();
     let minutes = (score / 60.).floor();
     let seconds = (score % 60.).floor();
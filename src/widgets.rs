@@ -0,0 +1,650 @@
+use bevy::ecs::system::EntityCommands;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+
+pub struct WidgetPlugin;
+
+/// This plugin drives the widgets of all menus
+/// Widgets are focused by hovering them with the mouse or by moving the focus with the arrow keys,
+/// tab or the D-pad. Clicking, enter, space or the south button activate the focused widget and
+/// left and right change its value. Menus react to the resulting [WidgetEvent]s.
+/// A focused text input takes the keyboard, gameplay systems should check [Typing].
+impl Plugin for WidgetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ButtonColors>()
+            .init_resource::<Focus>()
+            .init_resource::<Typing>()
+            .add_event::<WidgetEvent>()
+            // typing has to swallow keys like escape before any other system sees them
+            .add_system_to_stage(CoreStage::PreUpdate, type_into_widget.after(InputSystem))
+            .add_system(use_widgets_with_mouse)
+            .add_system(navigate_widgets.after(use_widgets_with_mouse))
+            .add_system(update_widgets.after(navigate_widgets));
+    }
+}
+
+pub struct ButtonColors {
+    pub normal: UiColor,
+    pub hovered: UiColor,
+}
+
+impl Default for ButtonColors {
+    fn default() -> Self {
+        ButtonColors {
+            normal: Color::rgb(0.15, 0.15, 0.15).into(),
+            hovered: Color::rgb(0.25, 0.25, 0.25).into(),
+        }
+    }
+}
+
+/// The widget receiving keyboard and gamepad input
+#[derive(Default)]
+pub struct Focus(pub Option<Entity>);
+
+/// Whether the focused widget is a text input, which the keyboard belongs to
+#[derive(Default)]
+pub struct Typing(pub bool);
+
+#[derive(Component, Clone, Debug)]
+pub struct Widget {
+    pub label: String,
+    pub kind: WidgetKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum WidgetKind {
+    Button,
+    Toggle(bool),
+    Slider {
+        value: f32,
+        min: f32,
+        max: f32,
+        step: f32,
+        /// Shows the value next to the label
+        format: fn(f32) -> String,
+    },
+    List {
+        options: Vec<String>,
+        selected: usize,
+    },
+    TextInput {
+        text: String,
+        max_length: usize,
+    },
+}
+
+/// Sent when the player used a widget
+pub struct WidgetEvent {
+    pub entity: Entity,
+    pub value: WidgetValue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WidgetValue {
+    Pressed,
+    Toggled(bool),
+    Slid(f32),
+    Selected(usize),
+    Submitted(String),
+    /// Escape was pressed in a text input, which loses the focus
+    Cancelled,
+}
+
+/// The text of a widget, showing its label and value
+#[derive(Component)]
+struct WidgetText;
+
+/// The arrows of sliders and lists
+#[derive(Component)]
+struct StepButton {
+    forward: bool,
+}
+
+impl Widget {
+    pub fn button(label: impl Into<String>) -> Self {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::Button,
+        }
+    }
+
+    pub fn toggle(label: impl Into<String>, value: bool) -> Self {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::Toggle(value),
+        }
+    }
+
+    pub fn slider(
+        label: impl Into<String>,
+        value: f32,
+        (min, max): (f32, f32),
+        step: f32,
+        format: fn(f32) -> String,
+    ) -> Self {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::Slider {
+                value,
+                min,
+                max,
+                step,
+                format,
+            },
+        }
+    }
+
+    pub fn list(label: impl Into<String>, options: Vec<String>, selected: usize) -> Self {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::List { options, selected },
+        }
+    }
+
+    pub fn text_input(label: impl Into<String>, max_length: usize) -> Self {
+        Widget {
+            label: label.into(),
+            kind: WidgetKind::TextInput {
+                text: String::new(),
+                max_length,
+            },
+        }
+    }
+
+    /// The label together with the current value
+    pub fn text(&self, focused: bool) -> String {
+        match &self.kind {
+            WidgetKind::Button => self.label.clone(),
+            WidgetKind::Toggle(value) => {
+                format!("{}: {}", self.label, if *value { "on" } else { "off" })
+            }
+            WidgetKind::Slider { value, format, .. } => {
+                format!("{}: {}", self.label, format(*value))
+            }
+            WidgetKind::List { options, selected } => format!(
+                "{}: {}",
+                self.label,
+                options.get(*selected).map_or("", String::as_str)
+            ),
+            WidgetKind::TextInput { text, .. } => {
+                format!("{}: {}{}", self.label, text, if focused { "_" } else { "" })
+            }
+        }
+    }
+
+    /// Pressing a button or submitting a text, flipping toggles and choosing the next option of lists
+    fn activate(&mut self) -> Option<WidgetValue> {
+        match &mut self.kind {
+            WidgetKind::Button => Some(WidgetValue::Pressed),
+            WidgetKind::TextInput { text, .. } => {
+                Some(WidgetValue::Submitted(std::mem::take(text)))
+            }
+            WidgetKind::Slider { .. } => None,
+            WidgetKind::Toggle(_) | WidgetKind::List { .. } => self.step(true),
+        }
+    }
+
+    /// Changes the value of toggles, sliders and lists, or does nothing for other widgets
+    ///
+    /// Sliders stop at their limits, while lists wrap around.
+    pub fn step(&mut self, forward: bool) -> Option<WidgetValue> {
+        match &mut self.kind {
+            WidgetKind::Toggle(value) => {
+                *value = !*value;
+                Some(WidgetValue::Toggled(*value))
+            }
+            WidgetKind::Slider {
+                value,
+                min,
+                max,
+                step,
+                ..
+            } => {
+                let steps = ((*value - *min) / *step).round() + if forward { 1. } else { -1. };
+                let next = (*min + steps * *step).clamp(*min, *max);
+                if next == *value {
+                    return None;
+                }
+                *value = next;
+                Some(WidgetValue::Slid(next))
+            }
+            WidgetKind::List { options, selected } => {
+                if options.is_empty() {
+                    return None;
+                }
+                *selected = if forward {
+                    (*selected + 1) % options.len()
+                } else {
+                    (*selected + options.len() - 1) % options.len()
+                };
+                Some(WidgetValue::Selected(*selected))
+            }
+            WidgetKind::Button | WidgetKind::TextInput { .. } => None,
+        }
+    }
+}
+
+/// Spawns a focusable widget, whose size can be changed by inserting another [Style]
+pub fn spawn_widget<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    text_style: &TextStyle,
+    button_colors: &ButtonColors,
+    widget: Widget,
+) -> EntityCommands<'w, 's, 'a> {
+    let text = widget.text(false);
+    let has_steps = matches!(
+        widget.kind,
+        WidgetKind::Slider { .. } | WidgetKind::List { .. }
+    );
+    let mut entity = parent.spawn_bundle(ButtonBundle {
+        style: Style {
+            min_size: Size::new(Val::Px(300.0), Val::Px(50.0)),
+            margin: UiRect::all(Val::Px(5.)),
+            padding: UiRect::new(Val::Px(10.), Val::Px(10.), Val::Px(0.), Val::Px(0.)),
+            justify_content: if has_steps {
+                JustifyContent::SpaceBetween
+            } else {
+                JustifyContent::Center
+            },
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: button_colors.normal,
+        ..default()
+    });
+    entity.insert(widget).with_children(|parent| {
+        let arrow = |parent: &mut ChildBuilder, forward: bool| {
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(40.0), Val::Px(40.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: button_colors.normal,
+                    ..default()
+                })
+                .insert(StepButton { forward })
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        if forward { ">" } else { "<" },
+                        text_style.clone(),
+                    ));
+                });
+        };
+        if has_steps {
+            arrow(parent, false);
+        }
+        parent
+            .spawn_bundle(
+                TextBundle::from_section(text, text_style.clone()).with_style(Style {
+                    margin: UiRect::new(Val::Px(10.), Val::Px(10.), Val::Px(0.), Val::Px(0.)),
+                    ..default()
+                }),
+            )
+            .insert(WidgetText);
+        if has_steps {
+            arrow(parent, true);
+        }
+    });
+    entity
+}
+
+/// Spawns a text that cannot be focused
+pub fn spawn_label<'w, 's, 'a>(
+    parent: &'a mut ChildBuilder<'w, 's, '_>,
+    text_style: &TextStyle,
+    text: impl Into<String>,
+) -> EntityCommands<'w, 's, 'a> {
+    parent.spawn_bundle(
+        TextBundle::from_section(text, text_style.clone()).with_style(Style {
+            margin: UiRect::all(Val::Px(5.)),
+            max_size: Size::new(Val::Px(700.), Val::Undefined),
+            ..default()
+        }),
+    )
+}
+
+/// The entity before or after the current one, wrapping around, or the first one without focus
+fn next_focus(order: &[Entity], current: Option<Entity>, forward: bool) -> Option<Entity> {
+    let index = current.and_then(|current| order.iter().position(|entity| *entity == current));
+    let next = match index {
+        Some(index) if forward => (index + 1) % order.len(),
+        Some(index) => (index + order.len() - 1) % order.len(),
+        None => 0,
+    };
+    order.get(next).copied()
+}
+
+/// Collects the visible widgets in the order of the UI tree, which is the order they are laid out in
+fn focus_order(
+    roots: &Query<Entity, (With<Node>, Without<Parent>)>,
+    children: &Query<&Children>,
+    widgets: &Query<(Entity, &Visibility), With<Widget>>,
+) -> Vec<Entity> {
+    let mut order = vec![];
+    let mut roots: Vec<Entity> = roots.iter().collect();
+    roots.sort();
+    let mut stack: Vec<Entity> = roots.into_iter().rev().collect();
+    while let Some(entity) = stack.pop() {
+        if let Ok((widget, visibility)) = widgets.get(entity) {
+            if visibility.is_visible {
+                order.push(widget);
+            }
+            continue;
+        }
+        if let Ok(children) = children.get(entity) {
+            stack.extend(children.iter().rev());
+        }
+    }
+    order
+}
+
+fn use_widgets_with_mouse(
+    mut focus: ResMut<Focus>,
+    mut events: EventWriter<WidgetEvent>,
+    changed: Query<Entity, (With<Widget>, Changed<Interaction>)>,
+    steps: Query<(&Interaction, &StepButton, &Parent), Changed<Interaction>>,
+    mut widgets: Query<(&Interaction, &mut Widget)>,
+) {
+    for entity in &changed {
+        let (interaction, mut widget) = widgets.get_mut(entity).unwrap();
+        match interaction {
+            Interaction::Hovered => focus.0 = Some(entity),
+            Interaction::Clicked => {
+                focus.0 = Some(entity);
+                let value = match widget.kind {
+                    // clicking a slider outside of its arrows does not say which way to go,
+                    // and text inputs are only focused to type into them
+                    WidgetKind::Slider { .. } | WidgetKind::TextInput { .. } => None,
+                    _ => widget.activate(),
+                };
+                if let Some(value) = value {
+                    events.send(WidgetEvent { entity, value });
+                }
+            }
+            Interaction::None => {}
+        }
+    }
+    for (interaction, step, parent) in &steps {
+        if *interaction == Interaction::None {
+            continue;
+        }
+        let entity = parent.get();
+        focus.0 = Some(entity);
+        if *interaction == Interaction::Clicked {
+            if let Ok((_, mut widget)) = widgets.get_mut(entity) {
+                if let Some(value) = widget.step(step.forward) {
+                    events.send(WidgetEvent { entity, value });
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn navigate_widgets(
+    mut focus: ResMut<Focus>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    mut events: EventWriter<WidgetEvent>,
+    roots: Query<Entity, (With<Node>, Without<Parent>)>,
+    children: Query<&Children>,
+    visible_widgets: Query<(Entity, &Visibility), With<Widget>>,
+    mut widgets: Query<&mut Widget>,
+) {
+    if focus.0.is_some_and(|entity| widgets.get(entity).is_err()) {
+        focus.0 = None;
+    }
+    let pressed = |key: KeyCode, button: GamepadButtonType| {
+        keys.just_pressed(key)
+            || gamepads
+                .iter()
+                .any(|gamepad| buttons.just_pressed(GamepadButton::new(*gamepad, button)))
+    };
+    let typing = focus.0.is_some_and(|entity| {
+        widgets
+            .get(entity)
+            .is_ok_and(|widget| matches!(widget.kind, WidgetKind::TextInput { .. }))
+    });
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let tab = keys.just_pressed(KeyCode::Tab);
+
+    let move_focus = if pressed(KeyCode::Down, GamepadButtonType::DPadDown) || (tab && !shift) {
+        Some(true)
+    } else if pressed(KeyCode::Up, GamepadButtonType::DPadUp) || (tab && shift) {
+        Some(false)
+    } else {
+        None
+    };
+    if let Some(forward) = move_focus {
+        let order = focus_order(&roots, &children, &visible_widgets);
+        focus.0 = next_focus(&order, focus.0, forward);
+        return;
+    }
+
+    let entity = match focus.0 {
+        Some(entity) => entity,
+        None => return,
+    };
+    let mut widget = match widgets.get_mut(entity) {
+        Ok(widget) => widget,
+        Err(_) => return,
+    };
+    // space is typed into text inputs instead of activating them, and enter was already taken
+    let activate = keys.just_pressed(KeyCode::Return)
+        || keys.just_pressed(KeyCode::NumpadEnter)
+        || (!typing && keys.just_pressed(KeyCode::Space))
+        || gamepads.iter().any(|gamepad| {
+            buttons.just_pressed(GamepadButton::new(*gamepad, GamepadButtonType::South))
+        });
+    let sideways = if pressed(KeyCode::Right, GamepadButtonType::DPadRight) {
+        Some(true)
+    } else if pressed(KeyCode::Left, GamepadButtonType::DPadLeft) {
+        Some(false)
+    } else {
+        None
+    };
+    let value = match sideways {
+        _ if activate => widget.activate(),
+        // buttons without a value are often placed side by side
+        Some(forward) if matches!(widget.kind, WidgetKind::Button) => {
+            let order = focus_order(&roots, &children, &visible_widgets);
+            focus.0 = next_focus(&order, Some(entity), forward);
+            None
+        }
+        Some(forward) => widget.step(forward),
+        None => None,
+    };
+    if let Some(value) = value {
+        events.send(WidgetEvent { entity, value });
+    }
+}
+
+/// Types into the focused text input, which is submitted with enter and cancelled with escape
+fn type_into_widget(
+    mut focus: ResMut<Focus>,
+    mut typing: ResMut<Typing>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut events: EventWriter<WidgetEvent>,
+    mut widgets: Query<&mut Widget>,
+) {
+    let focused = focus.0.and_then(|entity| {
+        widgets
+            .get_mut(entity)
+            .ok()
+            .filter(|widget| matches!(widget.kind, WidgetKind::TextInput { .. }))
+            .map(|widget| (entity, widget))
+    });
+    if typing.0 != focused.is_some() {
+        typing.0 = focused.is_some();
+    }
+    let (entity, mut widget) = match focused {
+        Some(focused) => focused,
+        None => {
+            characters.clear();
+            return;
+        }
+    };
+    if keys.clear_just_pressed(KeyCode::Escape) {
+        focus.0 = None;
+        typing.0 = false;
+        events.send(WidgetEvent {
+            entity,
+            value: WidgetValue::Cancelled,
+        });
+        return;
+    }
+    if keys.clear_just_pressed(KeyCode::Return) || keys.clear_just_pressed(KeyCode::NumpadEnter) {
+        if let Some(value) = widget.activate() {
+            events.send(WidgetEvent { entity, value });
+        }
+        return;
+    }
+    if let WidgetKind::TextInput { text, max_length } = &mut widget.kind {
+        if keys.clear_just_pressed(KeyCode::Back) {
+            text.pop();
+        }
+        for character in characters.iter() {
+            if !character.char.is_control() && text.chars().count() < *max_length {
+                text.push(character.char);
+            }
+        }
+    }
+}
+
+/// Highlights the focused widget and shows the current values
+fn update_widgets(
+    focus: Res<Focus>,
+    button_colors: Res<ButtonColors>,
+    mut widgets: Query<(Entity, &Widget, &Children, &mut UiColor)>,
+    mut texts: Query<&mut Text, With<WidgetText>>,
+) {
+    for (entity, widget, children, mut color) in &mut widgets {
+        let focused = focus.0 == Some(entity);
+        let wanted = if focused {
+            button_colors.hovered
+        } else {
+            button_colors.normal
+        };
+        if color.0 != wanted.0 {
+            *color = wanted;
+        }
+        let value = widget.text(focused);
+        for child in children {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                if text.sections[0].value != value {
+                    text.sections[0].value.clone_from(&value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::window::WindowId;
+
+    fn percent(value: f32) -> String {
+        format!("{:.0}%", value * 100.)
+    }
+
+    #[test]
+    fn values_change_within_their_limits() {
+        let mut volume = Widget::slider("Volume", 0.8, (0., 1.), 0.1, percent);
+        assert_eq!(volume.step(true), Some(WidgetValue::Slid(0.90000004)));
+        assert!(volume.step(true).is_some());
+        assert_eq!(volume.step(true), None);
+        assert_eq!(volume.text(false), "Volume: 100%");
+
+        let mut list = Widget::list("Mode", vec!["a".to_owned(), "b".to_owned()], 0);
+        assert_eq!(list.step(false), Some(WidgetValue::Selected(1)));
+        assert_eq!(list.activate(), Some(WidgetValue::Selected(0)));
+
+        let mut toggle = Widget::toggle("Shadows", true);
+        assert_eq!(toggle.activate(), Some(WidgetValue::Toggled(false)));
+        assert_eq!(toggle.text(false), "Shadows: off");
+
+        let mut button = Widget::button("Play");
+        assert_eq!(button.step(true), None);
+        assert_eq!(button.activate(), Some(WidgetValue::Pressed));
+    }
+
+    #[test]
+    fn submitting_a_text_input_clears_it() {
+        let mut input = Widget::text_input("Name", 20);
+        if let WidgetKind::TextInput { text, .. } = &mut input.kind {
+            text.push_str("Blub");
+        }
+        assert_eq!(input.text(true), "Name: Blub_");
+        assert_eq!(
+            input.activate(),
+            Some(WidgetValue::Submitted("Blub".to_owned()))
+        );
+        assert_eq!(input.text(false), "Name: ");
+    }
+
+    #[test]
+    fn text_inputs_take_the_keyboard_until_escape() {
+        let mut app = App::new();
+        app.init_resource::<Focus>()
+            .init_resource::<Typing>()
+            .init_resource::<Input<KeyCode>>()
+            .add_event::<ReceivedCharacter>()
+            .add_event::<WidgetEvent>()
+            .add_system(type_into_widget);
+        let input = app.world.spawn().insert(Widget::text_input("Name", 3)).id();
+        app.world.resource_mut::<Focus>().0 = Some(input);
+        for char in "Blub".chars() {
+            app.world.send_event(ReceivedCharacter {
+                id: WindowId::primary(),
+                char,
+            });
+        }
+        app.update();
+        assert!(app.world.resource::<Typing>().0);
+        assert_eq!(
+            app.world.get::<Widget>(input).unwrap().text(false),
+            "Name: Blu"
+        );
+
+        app.world
+            .resource_mut::<Input<KeyCode>>()
+            .press(KeyCode::Escape);
+        app.update();
+        assert!(!app
+            .world
+            .resource::<Input<KeyCode>>()
+            .just_pressed(KeyCode::Escape));
+        assert!(!app.world.resource::<Typing>().0);
+        assert_eq!(app.world.resource::<Focus>().0, None);
+        let events = app.world.resource::<Events<WidgetEvent>>();
+        let values: Vec<WidgetValue> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| event.value.clone())
+            .collect();
+        assert_eq!(values, vec![WidgetValue::Cancelled]);
+    }
+
+    #[test]
+    fn focus_wraps_around() {
+        let order = [
+            Entity::from_raw(3),
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+        ];
+        assert_eq!(next_focus(&order, None, false), Some(order[0]));
+        assert_eq!(next_focus(&order, Some(order[0]), false), Some(order[2]));
+        assert_eq!(next_focus(&order, Some(order[2]), true), Some(order[0]));
+        assert_eq!(
+            next_focus(&order, Some(Entity::from_raw(7)), true),
+            Some(order[0])
+        );
+        assert_eq!(next_focus(&[], None, true), None);
+    }
+}